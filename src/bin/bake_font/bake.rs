@@ -0,0 +1,235 @@
+// NOTE: Turns a TrueType font into the game's baked font file; shared by
+// bake_font and build_assets
+
+use super::font_format::*;
+use super::truetype::*;
+
+const FIRST_CODEPOINT: u32 = 32;
+const LAST_CODEPOINT: u32 = 126;
+const ATLAS_WIDTH: usize = 256;
+// NOTE: Gap between glyphs so bilinear sampling never bleeds a neighbour in
+const ATLAS_GAP: usize = 1;
+
+struct BakedGlyph {
+    x: usize,
+    y: usize,
+    bitmap: Option<GlyphBitmap>,
+    advance: f32,
+}
+
+pub struct BakedFont {
+    ascent: f32,
+    descent: f32,
+    line_advance: f32,
+    glyphs: Vec<BakedGlyph>,
+    kerning: Vec<(u32, u32, f32)>,
+    atlas_height: usize,
+    atlas: Vec<u8>,
+}
+
+pub fn bake_font(data: &[u8], pixel_height: f32) -> Result<BakedFont, String> {
+    let font = parse_truetype(data).map_err(|error| format!("{error:?}"))?;
+    let scale = pixel_height / (font.ascender as f32 - font.descender as f32);
+
+    let mut glyphs = Vec::new();
+    let mut glyph_indices = Vec::new();
+    for codepoint in FIRST_CODEPOINT..=LAST_CODEPOINT {
+        let glyph = glyph_index(&font, codepoint).map_err(|error| format!("{error:?}"))?;
+        let bitmap = rasterize_glyph(&font, glyph, scale).map_err(|error| format!("{error:?}"))?;
+        let advance = advance_width(&font, glyph).map_err(|error| format!("{error:?}"))?;
+
+        glyph_indices.push(glyph);
+        glyphs.push(BakedGlyph {
+            x: 0,
+            y: 0,
+            bitmap,
+            advance: scale * advance as f32,
+        });
+    }
+
+    // NOTE: Shelf packing, tallest glyphs first so each shelf wastes little
+    let mut order: Vec<usize> = (0..glyphs.len()).collect();
+    order.sort_by_key(|&index| {
+        std::cmp::Reverse(
+            glyphs[index]
+                .bitmap
+                .as_ref()
+                .map_or(0, |bitmap| bitmap.height),
+        )
+    });
+
+    let (mut shelf_x, mut shelf_y, mut shelf_height) = (0, 0, 0);
+    for index in order {
+        let Some((width, height)) = glyphs[index]
+            .bitmap
+            .as_ref()
+            .map(|bitmap| (bitmap.width, bitmap.height))
+        else {
+            continue;
+        };
+        if width > ATLAS_WIDTH {
+            return Err(format!(
+                "glyph for codepoint {} is wider than the atlas",
+                FIRST_CODEPOINT as usize + index
+            ));
+        }
+
+        if shelf_x + width > ATLAS_WIDTH {
+            shelf_x = 0;
+            shelf_y += shelf_height + ATLAS_GAP;
+            shelf_height = 0;
+        }
+        glyphs[index].x = shelf_x;
+        glyphs[index].y = shelf_y;
+        shelf_x += width + ATLAS_GAP;
+        shelf_height = shelf_height.max(height);
+    }
+
+    // NOTE: At least one row, an empty atlas doesn't load
+    let atlas_height = (shelf_y + shelf_height).max(1);
+    if atlas_height > u16::MAX as usize {
+        return Err("the atlas is too tall, try a smaller pixel height".to_string());
+    }
+
+    let mut atlas = vec![0u8; ATLAS_WIDTH * atlas_height];
+    for glyph in &glyphs {
+        let Some(bitmap) = &glyph.bitmap else {
+            continue;
+        };
+        for row in 0..bitmap.height {
+            let dest_at = (glyph.y + row) * ATLAS_WIDTH + glyph.x;
+            let source_at = row * bitmap.width;
+            atlas[dest_at..dest_at + bitmap.width]
+                .copy_from_slice(&bitmap.coverage[source_at..source_at + bitmap.width]);
+        }
+    }
+
+    // NOTE: Pairs are per glyph in the font, but per codepoint in the game,
+    // and several codepoints can share a glyph
+    let mut kerning = Vec::new();
+    for (first, second, adjust) in kerning_pairs(&font).map_err(|error| format!("{error:?}"))? {
+        // NOTE: Every missing codepoint shares glyph zero, nothing kerns it
+        if first == 0 || second == 0 {
+            continue;
+        }
+        for (first_index, &first_glyph) in glyph_indices.iter().enumerate() {
+            if first_glyph != first {
+                continue;
+            }
+            for (second_index, &second_glyph) in glyph_indices.iter().enumerate() {
+                if second_glyph == second {
+                    kerning.push((
+                        FIRST_CODEPOINT + first_index as u32,
+                        FIRST_CODEPOINT + second_index as u32,
+                        scale * adjust as f32,
+                    ));
+                }
+            }
+        }
+    }
+    kerning.sort_by_key(|&(first, second, _)| (first, second));
+    kerning.dedup_by_key(|&mut (first, second, _)| (first, second));
+
+    Ok(BakedFont {
+        ascent: scale * font.ascender as f32,
+        descent: -scale * font.descender as f32,
+        line_advance: scale * (font.ascender as f32 - font.descender as f32 + font.line_gap as f32),
+        glyphs,
+        kerning,
+        atlas_height,
+        atlas,
+    })
+}
+
+pub fn write_font(baked: &BakedFont) -> Vec<u8> {
+    let mut result = Vec::new();
+    result.extend_from_slice(&FONT_MAGIC);
+    for value in [
+        FONT_VERSION,
+        FIRST_CODEPOINT,
+        baked.glyphs.len() as u32,
+        baked.kerning.len() as u32,
+        ATLAS_WIDTH as u32,
+        baked.atlas_height as u32,
+    ] {
+        result.extend_from_slice(&value.to_le_bytes());
+    }
+    for value in [baked.ascent, baked.descent, baked.line_advance] {
+        result.extend_from_slice(&value.to_le_bytes());
+    }
+    debug_assert_eq!(result.len(), FONT_HEADER_SIZE);
+
+    for glyph in &baked.glyphs {
+        let (width, height, align_x, align_y) = match &glyph.bitmap {
+            Some(bitmap) => (bitmap.width, bitmap.height, bitmap.align_x, bitmap.align_y),
+            None => (0, 0, 0, 0),
+        };
+        for value in [glyph.x, glyph.y, width, height] {
+            result.extend_from_slice(&(value as u16).to_le_bytes());
+        }
+        result.extend_from_slice(&(align_x as i16).to_le_bytes());
+        result.extend_from_slice(&(align_y as i16).to_le_bytes());
+        result.extend_from_slice(&glyph.advance.to_le_bytes());
+    }
+    debug_assert_eq!(
+        result.len(),
+        FONT_HEADER_SIZE + baked.glyphs.len() * FONT_GLYPH_SIZE
+    );
+
+    for &(first, second, adjust) in &baked.kerning {
+        result.extend_from_slice(&first.to_le_bytes());
+        result.extend_from_slice(&second.to_le_bytes());
+        result.extend_from_slice(&adjust.to_le_bytes());
+    }
+    debug_assert_eq!(
+        result.len(),
+        FONT_HEADER_SIZE
+            + baked.glyphs.len() * FONT_GLYPH_SIZE
+            + baked.kerning.len() * FONT_KERNING_SIZE
+    );
+    result.extend_from_slice(&baked.atlas);
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bakes_the_square_font() {
+        // NOTE: 1000 units tall from descender to ascender baked at 10
+        // pixels makes the same 1/100 scale as the rasterizer test
+        let baked = bake_font(&super::super::truetype::tests::square_font(), 10.0).unwrap();
+
+        assert_eq!(
+            (baked.ascent, baked.descent, baked.line_advance),
+            (8.0, 2.0, 10.0)
+        );
+        assert_eq!(baked.glyphs.len(), 95);
+        assert_eq!(baked.kerning, vec![(b'A' as u32, b'A' as u32, -0.5)]);
+
+        let a = &baked.glyphs[(b'A' as u32 - FIRST_CODEPOINT) as usize];
+        assert_eq!(a.advance, 7.0);
+        let a_bitmap = a.bitmap.as_ref().unwrap();
+        assert_eq!((a.x, a.y), (0, 0));
+        assert_eq!(
+            baked.atlas[3 * ATLAS_WIDTH + 4],
+            a_bitmap.coverage[3 * a_bitmap.width + 4]
+        );
+
+        let space = &baked.glyphs[0];
+        assert!(space.bitmap.is_none());
+        assert_eq!(space.advance, 5.0);
+
+        let file = write_font(&baked);
+        assert_eq!(file[0..4], FONT_MAGIC);
+        assert_eq!(
+            file.len(),
+            FONT_HEADER_SIZE
+                + 95 * FONT_GLYPH_SIZE
+                + FONT_KERNING_SIZE
+                + ATLAS_WIDTH * baked.atlas_height
+        );
+    }
+}
@@ -0,0 +1,52 @@
+// NOTE: Offline tool that bakes a TrueType font into the game's font file,
+// so the game itself never has to touch TrueType:
+//
+//   bake_font <font.ttf> <pixel height> <output.hhf>
+
+mod bake;
+#[path = "../../handmade/font_format.rs"]
+mod font_format;
+mod truetype;
+
+use std::env;
+use std::fs;
+use std::process;
+
+use bake::*;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 4 {
+        eprintln!("usage: bake_font <font.ttf> <pixel height> <output.hhf>");
+        process::exit(1);
+    }
+
+    let pixel_height = match args[2].parse::<f32>() {
+        Ok(height) if height > 0.0 && height <= 512.0 => height,
+        _ => {
+            eprintln!("bake_font: {} is not a usable pixel height", args[2]);
+            process::exit(1);
+        }
+    };
+
+    let data = match fs::read(&args[1]) {
+        Ok(data) => data,
+        Err(error) => {
+            eprintln!("bake_font: can't read {}: {error}", args[1]);
+            process::exit(1);
+        }
+    };
+
+    let baked = match bake_font(&data, pixel_height) {
+        Ok(baked) => baked,
+        Err(error) => {
+            eprintln!("bake_font: can't bake {}: {error}", args[1]);
+            process::exit(1);
+        }
+    };
+
+    if let Err(error) = fs::write(&args[3], write_font(&baked)) {
+        eprintln!("bake_font: can't write {}: {error}", args[3]);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,792 @@
+// NOTE: Just enough TrueType to bake a font: glyf outlines (simple and
+// composite), cmap formats 4 and 12, hmtx advances and the old kern table.
+// No hinting, and CFF flavored OpenType fonts are not supported.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrueTypeError {
+    Truncated,
+    Corrupt,
+    Unsupported,
+    MissingTable(&'static str),
+}
+
+pub struct TrueTypeFont<'a> {
+    data: &'a [u8],
+
+    pub ascender: i16,
+    pub descender: i16,
+    pub line_gap: i16,
+
+    glyph_count: u16,
+    long_loca: bool,
+    h_metric_count: u16,
+
+    loca: usize,
+    glyf: usize,
+    hmtx: usize,
+    cmap: usize,
+    kern: Option<usize>,
+}
+
+// NOTE: A glyph rasterized at some scale, with the pen position on the
+// baseline at (align_x, align_y) from its top-left corner
+pub struct GlyphBitmap {
+    pub width: usize,
+    pub height: usize,
+    pub align_x: i32,
+    pub align_y: i32,
+    pub coverage: Vec<u8>,
+}
+
+fn read_u8(data: &[u8], at: usize) -> Result<u8, TrueTypeError> {
+    data.get(at).copied().ok_or(TrueTypeError::Truncated)
+}
+
+fn read_u16(data: &[u8], at: usize) -> Result<u16, TrueTypeError> {
+    match data.get(at..at + 2) {
+        Some(bytes) => Ok(u16::from_be_bytes([bytes[0], bytes[1]])),
+        None => Err(TrueTypeError::Truncated),
+    }
+}
+
+fn read_i16(data: &[u8], at: usize) -> Result<i16, TrueTypeError> {
+    read_u16(data, at).map(|value| value as i16)
+}
+
+fn read_u32(data: &[u8], at: usize) -> Result<u32, TrueTypeError> {
+    match data.get(at..at + 4) {
+        Some(bytes) => Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+        None => Err(TrueTypeError::Truncated),
+    }
+}
+
+fn find_table(data: &[u8], tag: &[u8; 4]) -> Result<Option<usize>, TrueTypeError> {
+    let table_count = read_u16(data, 4)? as usize;
+    for table_index in 0..table_count {
+        let record = 12 + 16 * table_index;
+        if data.get(record..record + 4) == Some(tag) {
+            let offset = read_u32(data, record + 8)? as usize;
+            let length = read_u32(data, record + 12)? as usize;
+            if offset
+                .checked_add(length)
+                .is_none_or(|end| end > data.len())
+            {
+                return Err(TrueTypeError::Truncated);
+            }
+            return Ok(Some(offset));
+        }
+    }
+
+    Ok(None)
+}
+
+fn require_table(data: &[u8], tag: &'static [u8; 4]) -> Result<usize, TrueTypeError> {
+    find_table(data, tag)?.ok_or(TrueTypeError::MissingTable(
+        std::str::from_utf8(tag).unwrap_or("????"),
+    ))
+}
+
+// NOTE: Prefers full Unicode tables over the BMP only ones
+fn find_cmap_subtable(data: &[u8], cmap: usize) -> Result<usize, TrueTypeError> {
+    let subtable_count = read_u16(data, cmap + 2)? as usize;
+    let mut best = None;
+    for subtable_index in 0..subtable_count {
+        let record = cmap + 4 + 8 * subtable_index;
+        let platform = read_u16(data, record)?;
+        let encoding = read_u16(data, record + 2)?;
+        let offset = cmap + read_u32(data, record + 4)? as usize;
+        let format = read_u16(data, offset)?;
+
+        let rank = match (platform, encoding, format) {
+            (3, 10, 12) | (0, 4, 12) | (0, 6, 12) => 2,
+            (3, 1, 4) | (0, _, 4) => 1,
+            _ => 0,
+        };
+        if rank > 0 && best.is_none_or(|(best_rank, _)| rank > best_rank) {
+            best = Some((rank, offset));
+        }
+    }
+
+    best.map(|(_, offset)| offset)
+        .ok_or(TrueTypeError::Unsupported)
+}
+
+pub fn parse_truetype(data: &[u8]) -> Result<TrueTypeFont<'_>, TrueTypeError> {
+    match read_u32(data, 0)? {
+        0x00010000 | 0x74727565 => {}
+        _ => return Err(TrueTypeError::Unsupported),
+    }
+
+    let head = require_table(data, b"head")?;
+    let maxp = require_table(data, b"maxp")?;
+    let hhea = require_table(data, b"hhea")?;
+    let hmtx = require_table(data, b"hmtx")?;
+    let cmap = require_table(data, b"cmap")?;
+    let loca = require_table(data, b"loca")?;
+    let glyf = require_table(data, b"glyf")?;
+
+    // NOTE: Scale comes from the ascender and descender, but a font without
+    // units per em is broken anyway
+    if read_u16(data, head + 18)? == 0 {
+        return Err(TrueTypeError::Corrupt);
+    }
+
+    Ok(TrueTypeFont {
+        data,
+
+        ascender: read_i16(data, hhea + 4)?,
+        descender: read_i16(data, hhea + 6)?,
+        line_gap: read_i16(data, hhea + 8)?,
+
+        glyph_count: read_u16(data, maxp + 4)?,
+        long_loca: read_i16(data, head + 50)? != 0,
+        h_metric_count: read_u16(data, hhea + 34)?,
+
+        loca,
+        glyf,
+        hmtx,
+        cmap: find_cmap_subtable(data, cmap)?,
+        kern: find_table(data, b"kern")?,
+    })
+}
+
+// NOTE: Zero is the missing glyph, which is what fonts map unknown characters to
+pub fn glyph_index(font: &TrueTypeFont, codepoint: u32) -> Result<u16, TrueTypeError> {
+    let data = font.data;
+    let subtable = font.cmap;
+
+    if read_u16(data, subtable)? == 12 {
+        let group_count = read_u32(data, subtable + 12)? as usize;
+        for group_index in 0..group_count {
+            let group = subtable + 16 + 12 * group_index;
+            let start = read_u32(data, group)?;
+            let end = read_u32(data, group + 4)?;
+            if (start..=end).contains(&codepoint) {
+                let glyph = read_u32(data, group + 8)? + (codepoint - start);
+                return Ok(u16::try_from(glyph).unwrap_or(0));
+            }
+        }
+        return Ok(0);
+    }
+
+    if codepoint > 0xFFFF {
+        return Ok(0);
+    }
+    let codepoint = codepoint as u16;
+
+    let segment_count_x2 = read_u16(data, subtable + 6)? as usize;
+    let end_codes = subtable + 14;
+    let start_codes = end_codes + segment_count_x2 + 2;
+    let id_deltas = start_codes + segment_count_x2;
+    let id_range_offsets = id_deltas + segment_count_x2;
+
+    for segment in (0..segment_count_x2).step_by(2) {
+        if codepoint > read_u16(data, end_codes + segment)? {
+            continue;
+        }
+
+        let start = read_u16(data, start_codes + segment)?;
+        if codepoint < start {
+            return Ok(0);
+        }
+
+        let delta = read_u16(data, id_deltas + segment)?;
+        let range_offset = read_u16(data, id_range_offsets + segment)? as usize;
+        if range_offset == 0 {
+            return Ok(codepoint.wrapping_add(delta));
+        }
+
+        // NOTE: The offset is relative to where it is stored, per the spec
+        let at = id_range_offsets + segment + range_offset + 2 * (codepoint - start) as usize;
+        let glyph = read_u16(data, at)?;
+        return Ok(if glyph == 0 {
+            0
+        } else {
+            glyph.wrapping_add(delta)
+        });
+    }
+
+    Ok(0)
+}
+
+pub fn advance_width(font: &TrueTypeFont, glyph: u16) -> Result<u16, TrueTypeError> {
+    if font.h_metric_count == 0 {
+        return Err(TrueTypeError::Corrupt);
+    }
+
+    // NOTE: Glyphs past the last long metric share its advance
+    let metric = glyph.min(font.h_metric_count - 1) as usize;
+    read_u16(font.data, font.hmtx + 4 * metric)
+}
+
+// NOTE: Horizontal pairs from format 0 kern subtables, in font units
+pub fn kerning_pairs(font: &TrueTypeFont) -> Result<Vec<(u16, u16, i16)>, TrueTypeError> {
+    let mut result = Vec::new();
+    let Some(kern) = font.kern else {
+        return Ok(result);
+    };
+    let data = font.data;
+
+    // TODO: GPOS pair adjustment, which is where most newer fonts keep kerning
+    if read_u16(data, kern)? != 0 {
+        return Ok(result);
+    }
+
+    let subtable_count = read_u16(data, kern + 2)?;
+    let mut subtable = kern + 4;
+    for _ in 0..subtable_count {
+        let length = read_u16(data, subtable + 2)? as usize;
+        let coverage = read_u16(data, subtable + 4)?;
+        let is_horizontal = coverage & 0x1 != 0;
+        let is_minimum_or_cross_stream = coverage & 0x6 != 0;
+        let format = coverage >> 8;
+
+        if format == 0 && is_horizontal && !is_minimum_or_cross_stream {
+            let pair_count = read_u16(data, subtable + 6)? as usize;
+            for pair_index in 0..pair_count {
+                let pair = subtable + 14 + 6 * pair_index;
+                result.push((
+                    read_u16(data, pair)?,
+                    read_u16(data, pair + 2)?,
+                    read_i16(data, pair + 4)?,
+                ));
+            }
+        }
+
+        if length == 0 {
+            break;
+        }
+        subtable += length;
+    }
+
+    Ok(result)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct OutlinePoint {
+    x: f32,
+    y: f32,
+    on_curve: bool,
+}
+
+fn glyph_range(font: &TrueTypeFont, glyph: u16) -> Result<(usize, usize), TrueTypeError> {
+    if glyph >= font.glyph_count {
+        return Err(TrueTypeError::Corrupt);
+    }
+
+    let index = glyph as usize;
+    let (start, end) = if font.long_loca {
+        (
+            read_u32(font.data, font.loca + 4 * index)? as usize,
+            read_u32(font.data, font.loca + 4 * index + 4)? as usize,
+        )
+    } else {
+        (
+            2 * read_u16(font.data, font.loca + 2 * index)? as usize,
+            2 * read_u16(font.data, font.loca + 2 * index + 2)? as usize,
+        )
+    };
+    if end < start {
+        return Err(TrueTypeError::Corrupt);
+    }
+
+    Ok((font.glyf + start, font.glyf + end))
+}
+
+const MAX_COMPOSITE_DEPTH: u32 = 8;
+
+// NOTE: Contours in font units, y up
+fn glyph_contours(
+    font: &TrueTypeFont,
+    glyph: u16,
+    depth: u32,
+) -> Result<Vec<Vec<OutlinePoint>>, TrueTypeError> {
+    let data = font.data;
+    let (start, end) = glyph_range(font, glyph)?;
+    if start == end {
+        return Ok(Vec::new());
+    }
+
+    let contour_count = read_i16(data, start)?;
+    if contour_count < 0 {
+        return composite_contours(font, start + 10, depth);
+    }
+
+    let contour_count = contour_count as usize;
+    let mut end_points = Vec::with_capacity(contour_count);
+    for contour_index in 0..contour_count {
+        end_points.push(read_u16(data, start + 10 + 2 * contour_index)? as usize);
+    }
+    let Some(&last_point) = end_points.last() else {
+        return Ok(Vec::new());
+    };
+    let point_count = last_point + 1;
+
+    let instruction_length = read_u16(data, start + 10 + 2 * contour_count)? as usize;
+    let mut at = start + 12 + 2 * contour_count + instruction_length;
+
+    let mut flags = Vec::with_capacity(point_count);
+    while flags.len() < point_count {
+        let flag = read_u8(data, at)?;
+        at += 1;
+        flags.push(flag);
+        if flag & 0x08 != 0 {
+            let repeat_count = read_u8(data, at)?;
+            at += 1;
+            for _ in 0..repeat_count {
+                flags.push(flag);
+            }
+        }
+    }
+    flags.truncate(point_count);
+
+    // NOTE: Coordinates are deltas, either a signed word, an unsigned byte
+    // with the sign in the flags, or repeated from the last point
+    let mut read_coordinates = |short_bit: u8, same_bit: u8| {
+        let mut value = 0i32;
+        let mut result = Vec::with_capacity(point_count);
+        for &flag in &flags {
+            if flag & short_bit != 0 {
+                let delta = read_u8(data, at)? as i32;
+                at += 1;
+                value += if flag & same_bit != 0 { delta } else { -delta };
+            } else if flag & same_bit == 0 {
+                value += read_i16(data, at)? as i32;
+                at += 2;
+            }
+            result.push(value as f32);
+        }
+        Ok::<_, TrueTypeError>(result)
+    };
+    let xs = read_coordinates(0x02, 0x10)?;
+    let ys = read_coordinates(0x04, 0x20)?;
+
+    let mut contours = Vec::with_capacity(contour_count);
+    let mut first = 0;
+    for &end_point in &end_points {
+        if end_point < first || end_point >= point_count {
+            return Err(TrueTypeError::Corrupt);
+        }
+        contours.push(
+            (first..=end_point)
+                .map(|index| OutlinePoint {
+                    x: xs[index],
+                    y: ys[index],
+                    on_curve: flags[index] & 0x01 != 0,
+                })
+                .collect(),
+        );
+        first = end_point + 1;
+    }
+
+    Ok(contours)
+}
+
+fn composite_contours(
+    font: &TrueTypeFont,
+    mut at: usize,
+    depth: u32,
+) -> Result<Vec<Vec<OutlinePoint>>, TrueTypeError> {
+    const ARGS_ARE_WORDS: u16 = 0x0001;
+    const ARGS_ARE_XY_VALUES: u16 = 0x0002;
+    const HAVE_A_SCALE: u16 = 0x0008;
+    const MORE_COMPONENTS: u16 = 0x0020;
+    const HAVE_AN_X_AND_Y_SCALE: u16 = 0x0040;
+    const HAVE_A_TWO_BY_TWO: u16 = 0x0080;
+
+    if depth >= MAX_COMPOSITE_DEPTH {
+        return Err(TrueTypeError::Corrupt);
+    }
+
+    let data = font.data;
+    let f2dot14 = |at: usize| read_i16(data, at).map(|value| value as f32 / 16384.0);
+
+    let mut result = Vec::new();
+    loop {
+        let flags = read_u16(data, at)?;
+        let component = read_u16(data, at + 2)?;
+        at += 4;
+
+        let (arg1, arg2) = if flags & ARGS_ARE_WORDS != 0 {
+            at += 4;
+            (
+                read_i16(data, at - 4)? as f32,
+                read_i16(data, at - 2)? as f32,
+            )
+        } else {
+            at += 2;
+            (
+                read_u8(data, at - 2)? as i8 as f32,
+                read_u8(data, at - 1)? as i8 as f32,
+            )
+        };
+
+        // NOTE: Components placed by matching points need the hinter, they
+        // just stay where they are
+        let (dx, dy) = if flags & ARGS_ARE_XY_VALUES != 0 {
+            (arg1, arg2)
+        } else {
+            (0.0, 0.0)
+        };
+
+        let (mut a, mut b, mut c, mut d) = (1.0, 0.0, 0.0, 1.0);
+        if flags & HAVE_A_SCALE != 0 {
+            a = f2dot14(at)?;
+            d = a;
+            at += 2;
+        } else if flags & HAVE_AN_X_AND_Y_SCALE != 0 {
+            a = f2dot14(at)?;
+            d = f2dot14(at + 2)?;
+            at += 4;
+        } else if flags & HAVE_A_TWO_BY_TWO != 0 {
+            a = f2dot14(at)?;
+            b = f2dot14(at + 2)?;
+            c = f2dot14(at + 4)?;
+            d = f2dot14(at + 6)?;
+            at += 8;
+        }
+
+        for contour in glyph_contours(font, component, depth + 1)? {
+            result.push(
+                contour
+                    .into_iter()
+                    .map(|p| OutlinePoint {
+                        x: a * p.x + c * p.y + dx,
+                        y: b * p.x + d * p.y + dy,
+                        on_curve: p.on_curve,
+                    })
+                    .collect(),
+            );
+        }
+
+        if flags & MORE_COMPONENTS == 0 {
+            break;
+        }
+    }
+
+    Ok(result)
+}
+
+// NOTE: Accumulates signed area per pixel the way font-rs does, so
+// coverage comes out of a running sum along each row
+struct Raster {
+    width: usize,
+    height: usize,
+    accumulation: Vec<f32>,
+}
+
+fn draw_line(raster: &mut Raster, from: (f32, f32), to: (f32, f32)) {
+    if (from.1 - to.1).abs() <= f32::EPSILON {
+        return;
+    }
+
+    let (direction, from, to) = if from.1 < to.1 {
+        (1.0, from, to)
+    } else {
+        (-1.0, to, from)
+    };
+    let dxdy = (to.0 - from.0) / (to.1 - from.1);
+
+    let mut x = from.0;
+    let first_row = from.1.max(0.0) as usize;
+    if from.1 < 0.0 {
+        x -= from.1 * dxdy;
+    }
+
+    let last_row = raster.height.min(to.1.ceil() as usize);
+    for y in first_row..last_row {
+        let row = y * raster.width;
+        let dy = ((y + 1) as f32).min(to.1) - (y as f32).max(from.1);
+        let x_next = x + dxdy * dy;
+        let d = dy * direction;
+
+        let (x0, x1) = if x < x_next { (x, x_next) } else { (x_next, x) };
+        let x0_floor = x0.floor();
+        let x0i = x0_floor as usize;
+        let x1_ceil = x1.ceil();
+        let x1i = x1_ceil as usize;
+
+        let cells = &mut raster.accumulation;
+        if x1i <= x0i + 1 {
+            let x_mid = 0.5 * (x + x_next) - x0_floor;
+            cells[row + x0i] += d - d * x_mid;
+            cells[row + x0i + 1] += d * x_mid;
+        } else {
+            let s = 1.0 / (x1 - x0);
+            let x0f = x0 - x0_floor;
+            let a0 = 0.5 * s * (1.0 - x0f) * (1.0 - x0f);
+            let x1f = x1 - x1_ceil + 1.0;
+            let am = 0.5 * s * x1f * x1f;
+
+            cells[row + x0i] += d * a0;
+            if x1i == x0i + 2 {
+                cells[row + x0i + 1] += d * (1.0 - a0 - am);
+            } else {
+                let a1 = s * (1.5 - x0f);
+                cells[row + x0i + 1] += d * (a1 - a0);
+                for xi in x0i + 2..x1i - 1 {
+                    cells[row + xi] += d * s;
+                }
+                let a2 = a1 + (x1i - x0i - 3) as f32 * s;
+                cells[row + x1i - 1] += d * (1.0 - a2 - am);
+            }
+            cells[row + x1i] += d * am;
+        }
+
+        x = x_next;
+    }
+}
+
+fn draw_quadratic(raster: &mut Raster, from: (f32, f32), control: (f32, f32), to: (f32, f32)) {
+    let deviation_x = from.0 - 2.0 * control.0 + to.0;
+    let deviation_y = from.1 - 2.0 * control.1 + to.1;
+    let deviation_squared = deviation_x * deviation_x + deviation_y * deviation_y;
+    if deviation_squared < 0.333 {
+        draw_line(raster, from, to);
+        return;
+    }
+
+    // NOTE: Enough segments to keep the error from the curve under about a
+    // third of a pixel
+    let segment_count = 1 + (3.0 * deviation_squared).sqrt().sqrt().floor() as usize;
+    let mut at = from;
+    for segment in 1..=segment_count {
+        let t = segment as f32 / segment_count as f32;
+        let u = 1.0 - t;
+        let next = (
+            u * u * from.0 + 2.0 * u * t * control.0 + t * t * to.0,
+            u * u * from.1 + 2.0 * u * t * control.1 + t * t * to.1,
+        );
+        draw_line(raster, at, next);
+        at = next;
+    }
+}
+
+fn draw_contour(raster: &mut Raster, contour: &[(f32, f32, bool)]) {
+    // NOTE: Two off curve points in a row have an implied on curve point
+    // halfway between them
+    let mut points = Vec::with_capacity(2 * contour.len());
+    for (index, &point) in contour.iter().enumerate() {
+        let next = contour[(index + 1) % contour.len()];
+        points.push(point);
+        if !point.2 && !next.2 {
+            points.push((0.5 * (point.0 + next.0), 0.5 * (point.1 + next.1), true));
+        }
+    }
+
+    let Some(start) = points.iter().position(|point| point.2) else {
+        return;
+    };
+    points.rotate_left(start);
+
+    let count = points.len();
+    let mut at = (points[0].0, points[0].1);
+    let mut index = 1;
+    while index <= count {
+        let point = points[index % count];
+        if point.2 {
+            let to = (point.0, point.1);
+            draw_line(raster, at, to);
+            at = to;
+            index += 1;
+        } else {
+            let end = points[(index + 1) % count];
+            let to = (end.0, end.1);
+            draw_quadratic(raster, at, (point.0, point.1), to);
+            at = to;
+            index += 2;
+        }
+    }
+}
+
+// NOTE: scale is pixels per font unit. The bitmap has a pixel of padding all
+// round so the rasterizer never writes outside it
+pub fn rasterize_glyph(
+    font: &TrueTypeFont,
+    glyph: u16,
+    scale: f32,
+) -> Result<Option<GlyphBitmap>, TrueTypeError> {
+    let contours = glyph_contours(font, glyph, 0)?;
+    let mut points = contours.iter().flatten();
+    let Some(first) = points.next() else {
+        return Ok(None);
+    };
+
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (first.x, first.y, first.x, first.y);
+    for point in points {
+        min_x = min_x.min(point.x);
+        min_y = min_y.min(point.y);
+        max_x = max_x.max(point.x);
+        max_y = max_y.max(point.y);
+    }
+
+    // NOTE: Pixel y runs down from the top of the glyph
+    let left = (scale * min_x).floor() as i32 - 1;
+    let top = (-scale * max_y).floor() as i32 - 1;
+    let right = (scale * max_x).ceil() as i32 + 1;
+    let bottom = (-scale * min_y).ceil() as i32 + 1;
+
+    let width = (right - left) as usize;
+    let height = (bottom - top) as usize;
+    let mut raster = Raster {
+        width,
+        height,
+        accumulation: vec![0.0; width * height + 1],
+    };
+
+    for contour in &contours {
+        let pixels: Vec<_> = contour
+            .iter()
+            .map(|p| {
+                (
+                    scale * p.x - left as f32,
+                    -scale * p.y - top as f32,
+                    p.on_curve,
+                )
+            })
+            .collect();
+        draw_contour(&mut raster, &pixels);
+    }
+
+    let mut sum = 0.0f32;
+    let coverage = raster.accumulation[..width * height]
+        .iter()
+        .map(|area| {
+            sum += area;
+            (255.0 * sum.abs().min(1.0) + 0.5) as u8
+        })
+        .collect();
+
+    Ok(Some(GlyphBitmap {
+        width,
+        height,
+        align_x: -left,
+        align_y: -top,
+        coverage,
+    }))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn table_file(tables: &mut [(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        tables.sort_by_key(|(tag, _)| **tag);
+
+        let mut result = Vec::new();
+        result.extend_from_slice(&0x00010000u32.to_be_bytes());
+        result.extend_from_slice(&(tables.len() as u16).to_be_bytes());
+        result.extend_from_slice(&[0; 6]);
+
+        let mut offset = 12 + 16 * tables.len();
+        for (tag, table) in tables.iter() {
+            result.extend_from_slice(*tag);
+            result.extend_from_slice(&0u32.to_be_bytes());
+            result.extend_from_slice(&(offset as u32).to_be_bytes());
+            result.extend_from_slice(&(table.len() as u32).to_be_bytes());
+            offset += table.len().next_multiple_of(4);
+        }
+        for (_, table) in tables.iter() {
+            result.extend_from_slice(table);
+            result.resize(result.len().next_multiple_of(4), 0);
+        }
+
+        result
+    }
+
+    fn be16(values: &[i32]) -> Vec<u8> {
+        values
+            .iter()
+            .flat_map(|&value| (value as u16).to_be_bytes())
+            .collect()
+    }
+
+    // NOTE: 1000 units per em with an empty missing glyph and a 600 unit
+    // square, drawn with one off curve point on its top edge, as 'A'. A
+    // followed by A kerns by -50.
+    pub fn square_font() -> Vec<u8> {
+        let mut head = vec![0; 54];
+        head[18..20].copy_from_slice(&1000u16.to_be_bytes());
+
+        let mut maxp = vec![0; 6];
+        maxp[4..6].copy_from_slice(&2u16.to_be_bytes());
+
+        let mut hhea = vec![0; 36];
+        hhea[4..6].copy_from_slice(&800i16.to_be_bytes());
+        hhea[6..8].copy_from_slice(&(-200i16).to_be_bytes());
+        hhea[34..36].copy_from_slice(&2u16.to_be_bytes());
+
+        let hmtx = be16(&[500, 0, 700, 50]);
+
+        // NOTE: Format 4 with one segment for A and the closing 0xFFFF one
+        let mut cmap = be16(&[0, 1, 3, 1, 0, 12]);
+        cmap.extend(be16(&[4, 32, 0, 4, 0, 0, 0, 0x41, 0xFFFF, 0, 0x41, 0xFFFF]));
+        cmap.extend(be16(&[1 - 0x41, 1, 0, 0]));
+
+        // NOTE: Points (50,0) (650,0) (650,600) (350,650 off) (50,600), all
+        // with word deltas so the flags stay simple
+        let mut glyf = be16(&[1, 50, 0, 650, 650, 4, 0]);
+        glyf.extend([0x01, 0x01, 0x01, 0x00, 0x01]);
+        glyf.extend(be16(&[50, 600, 0, -300, -300]));
+        glyf.extend(be16(&[0, 0, 600, 50, -50]));
+        glyf.resize(glyf.len().next_multiple_of(2), 0);
+        let loca = be16(&[0, 0, glyf.len() as i32 / 2]);
+
+        let mut kern = be16(&[0, 1, 0, 20, 1, 1, 6, 0, 0]);
+        kern.extend(be16(&[1, 1, -50]));
+
+        table_file(&mut [
+            (b"head", head),
+            (b"maxp", maxp),
+            (b"hhea", hhea),
+            (b"hmtx", hmtx),
+            (b"cmap", cmap),
+            (b"loca", loca),
+            (b"glyf", glyf),
+            (b"kern", kern),
+        ])
+    }
+
+    #[test]
+    fn parses_metrics_cmap_and_kerning() {
+        let data = square_font();
+        let font = parse_truetype(&data).unwrap();
+
+        assert_eq!((font.ascender, font.descender), (800, -200));
+        assert_eq!(glyph_index(&font, 'A' as u32), Ok(1));
+        assert_eq!(glyph_index(&font, 'B' as u32), Ok(0));
+        assert_eq!(glyph_index(&font, '@' as u32), Ok(0));
+        assert_eq!(advance_width(&font, 0), Ok(500));
+        assert_eq!(advance_width(&font, 1), Ok(700));
+        assert_eq!(kerning_pairs(&font), Ok(vec![(1, 1, -50)]));
+    }
+
+    #[test]
+    fn rasterizes_the_square_with_antialiased_edges() {
+        let data = square_font();
+        let font = parse_truetype(&data).unwrap();
+
+        assert!(rasterize_glyph(&font, 0, 0.01).unwrap().is_none());
+
+        // NOTE: At 1/100 the square covers x 0.5 to 6.5 and y 0 to 6 in
+        // pixels, plus the curve bulging half a pixel above the top edge
+        let glyph = rasterize_glyph(&font, 1, 0.01).unwrap().unwrap();
+        assert_eq!((glyph.align_x, glyph.align_y), (1, 8));
+        let at = |x: i32, y: i32| {
+            let x = (x + glyph.align_x) as usize;
+            let y = (y + glyph.align_y) as usize;
+            glyph.coverage[y * glyph.width + x]
+        };
+
+        // NOTE: Pixel (x, y) here is the one right of x and below y from
+        // the pen, so the baseline row is y = -1
+        assert_eq!(at(3, -3), 255);
+        assert_eq!(at(0, -3), 128);
+        assert_eq!(at(6, -3), 128);
+        assert_eq!(at(7, -3), 0);
+        assert_eq!(at(3, 0), 0);
+        assert_eq!(at(3, -1), 255);
+        assert!(at(3, -7) > 0 && at(3, -7) < 255);
+        assert_eq!(at(3, -8), 0);
+    }
+}
@@ -0,0 +1,455 @@
+// NOTE: Offline tool that packs every asset the game loads into one file:
+//
+//   build_assets <source dir> <output.hha>
+//
+// The source directory holds the BMP, PNG, WAV and TTF files along with an
+// assets.txt that lists what goes in the pack, one asset per line:
+//
+//   <type> <file> [align=<x>,<y>] [size=<pixel height>] [<tag>=<value>]...
+//
+// where the file is relative to the source directory, align is the bitmap's
+// alignment pixel, size is the height TTF fonts get baked at, and anything
+// after a # is a comment. The game looks for test/test_assets.hha, and
+// reloads BMPs, PNGs and WAVs from the paths recorded in it whenever they
+// change, so the source directory should be given relative to where the game
+// runs from.
+
+#[path = "../../handmade/asset_format.rs"]
+mod asset_format;
+#[path = "../bake_font/bake.rs"]
+mod bake;
+#[path = "../../handmade/font_format.rs"]
+mod font_format;
+#[path = "../bake_font/truetype.rs"]
+mod truetype;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process;
+use std::time::UNIX_EPOCH;
+
+use asset_format::*;
+use bake::*;
+
+const ASSET_TYPES: [(&str, u32); 7] = [
+    ("backdrop", ASSET_TYPE_BACKDROP),
+    ("shadow", ASSET_TYPE_SHADOW),
+    ("hero_head", ASSET_TYPE_HERO_HEAD),
+    ("hero_cape", ASSET_TYPE_HERO_CAPE),
+    ("hero_torso", ASSET_TYPE_HERO_TORSO),
+    ("bloop", ASSET_TYPE_BLOOP),
+    ("debug_font", ASSET_TYPE_DEBUG_FONT),
+];
+
+const ASSET_TAGS: [(&str, u32); 3] = [
+    ("facing_direction", ASSET_TAG_FACING_DIRECTION),
+    ("height", ASSET_TAG_HEIGHT),
+    ("color", ASSET_TAG_COLOR),
+];
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+#[derive(Debug, PartialEq)]
+struct SourceAsset {
+    line_number: usize,
+    type_id: u32,
+    filename: String,
+    align: (i32, i32),
+    pixel_height: Option<f32>,
+    tags: Vec<(u32, f32)>,
+}
+
+fn lookup(names: &[(&str, u32)], name: &str) -> Option<u32> {
+    names
+        .iter()
+        .find(|&&(known, _)| known == name)
+        .map(|&(_, id)| id)
+}
+
+fn parse_manifest(manifest: &str) -> Result<Vec<SourceAsset>, String> {
+    let mut result = Vec::new();
+    for (line_index, line) in manifest.lines().enumerate() {
+        let line_number = line_index + 1;
+        let line = line.split('#').next().unwrap_or("");
+        let mut words = line.split_whitespace();
+        let Some(type_name) = words.next() else {
+            continue;
+        };
+
+        let type_id = lookup(&ASSET_TYPES, type_name)
+            .ok_or_else(|| format!("line {line_number}: unknown asset type {type_name}"))?;
+        let filename = words
+            .next()
+            .ok_or_else(|| format!("line {line_number}: {type_name} needs a file"))?;
+
+        let mut asset = SourceAsset {
+            line_number,
+            type_id,
+            filename: filename.to_string(),
+            align: (0, 0),
+            pixel_height: None,
+            tags: Vec::new(),
+        };
+        for option in words {
+            let bad_option = || format!("line {line_number}: can't make sense of {option}");
+            let (key, value) = option.split_once('=').ok_or_else(bad_option)?;
+            match key {
+                "align" => {
+                    let (x, y) = value.split_once(',').ok_or_else(bad_option)?;
+                    asset.align = (
+                        x.parse().map_err(|_| bad_option())?,
+                        y.parse().map_err(|_| bad_option())?,
+                    );
+                }
+                "size" => asset.pixel_height = Some(value.parse().map_err(|_| bad_option())?),
+                _ => {
+                    let tag_id = lookup(&ASSET_TAGS, key)
+                        .ok_or_else(|| format!("line {line_number}: unknown tag {key}"))?;
+                    asset
+                        .tags
+                        .push((tag_id, value.parse().map_err(|_| bad_option())?));
+                }
+            }
+        }
+
+        result.push(asset);
+    }
+
+    Ok(result)
+}
+
+struct BuiltAsset {
+    kind: u32,
+    data: Vec<u8>,
+    // NOTE: Empty for anything the game can't rebuild itself
+    source_path: String,
+    source_write_time: u64,
+}
+
+// NOTE: Has to match what the platform layer reports, the game compares the two
+fn file_write_time(path: &Path) -> u64 {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since_epoch| since_epoch.as_nanos() as u64)
+}
+
+// NOTE: Bitmaps and sounds go in as they are, the game's loaders read them
+// straight out of the pack; fonts get baked first since the game never
+// touches TrueType
+fn build_asset(source_dir: &Path, asset: &SourceAsset) -> Result<BuiltAsset, String> {
+    let path = source_dir.join(&asset.filename);
+    // NOTE: Taken before the read, so an edit that lands during it still
+    // looks newer than the pack to the game
+    let source_write_time = file_write_time(&path);
+    let data =
+        fs::read(&path).map_err(|error| format!("can't read {}: {error}", path.display()))?;
+
+    let source_path = path.to_str().unwrap_or("").to_string();
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase();
+    match extension.as_str() {
+        "bmp" | "png" => {
+            if !(data.starts_with(b"BM") || data.starts_with(&PNG_SIGNATURE)) {
+                return Err(format!("{} is not a BMP or PNG", path.display()));
+            }
+            Ok(BuiltAsset {
+                kind: ASSET_KIND_BITMAP,
+                data,
+                source_path,
+                source_write_time,
+            })
+        }
+        "wav" => {
+            if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+                return Err(format!("{} is not a WAV", path.display()));
+            }
+            Ok(BuiltAsset {
+                kind: ASSET_KIND_SOUND,
+                data,
+                source_path,
+                source_write_time,
+            })
+        }
+        "ttf" => {
+            let pixel_height = asset
+                .pixel_height
+                .filter(|&height| height > 0.0 && height <= 512.0)
+                .ok_or_else(|| format!("{} needs a usable size=", path.display()))?;
+            let baked = bake_font(&data, pixel_height)
+                .map_err(|error| format!("can't bake {}: {error}", path.display()))?;
+            Ok(BuiltAsset {
+                kind: ASSET_KIND_FONT,
+                data: write_font(&baked),
+                source_path: String::new(),
+                source_write_time: 0,
+            })
+        }
+        _ => Err(format!("{} is not a BMP, PNG, WAV or TTF", path.display())),
+    }
+}
+
+// NOTE: Assets are grouped by type in the order the manifest lists them,
+// after the placeholder asset zero
+fn write_pack(assets: &[SourceAsset], built: &[BuiltAsset]) -> Vec<u8> {
+    let mut order: Vec<usize> = (0..assets.len()).collect();
+    order.sort_by_key(|&index| assets[index].type_id);
+
+    let tag_count: usize = assets.iter().map(|asset| asset.tags.len()).sum();
+    let asset_count = assets.len() + 1;
+    let source_paths_size: usize = built.iter().map(|built| built.source_path.len()).sum();
+
+    let mut result = Vec::new();
+    result.extend_from_slice(&ASSET_PACK_MAGIC);
+    for value in [
+        ASSET_PACK_VERSION,
+        tag_count as u32,
+        ASSET_TYPE_COUNT as u32,
+        asset_count as u32,
+        source_paths_size as u32,
+    ] {
+        result.extend_from_slice(&value.to_le_bytes());
+    }
+    debug_assert_eq!(result.len(), ASSET_PACK_HEADER_SIZE);
+
+    for &index in &order {
+        for &(tag_id, value) in &assets[index].tags {
+            result.extend_from_slice(&tag_id.to_le_bytes());
+            result.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    debug_assert_eq!(
+        result.len(),
+        ASSET_PACK_HEADER_SIZE + tag_count * ASSET_PACK_TAG_SIZE
+    );
+
+    for type_id in 0..ASSET_TYPE_COUNT as u32 {
+        let first = 1 + order
+            .iter()
+            .take_while(|&&index| assets[index].type_id < type_id)
+            .count() as u32;
+        let count = assets
+            .iter()
+            .filter(|asset| asset.type_id == type_id)
+            .count() as u32;
+        result.extend_from_slice(&first.to_le_bytes());
+        result.extend_from_slice(&(first + count).to_le_bytes());
+    }
+
+    let tables_size = ASSET_PACK_HEADER_SIZE
+        + tag_count * ASSET_PACK_TAG_SIZE
+        + ASSET_TYPE_COUNT * ASSET_PACK_TYPE_SIZE
+        + asset_count * ASSET_PACK_ASSET_SIZE
+        + source_paths_size;
+    let mut data_offset = tables_size as u64;
+    let mut first_tag = 0u32;
+    let mut source_path_offset = 0u32;
+    result.extend_from_slice(&[0; ASSET_PACK_ASSET_SIZE]);
+    for &index in &order {
+        let asset = &assets[index];
+        let built = &built[index];
+
+        result.extend_from_slice(&data_offset.to_le_bytes());
+        for value in [
+            built.data.len() as u32,
+            first_tag,
+            first_tag + asset.tags.len() as u32,
+            built.kind,
+        ] {
+            result.extend_from_slice(&value.to_le_bytes());
+        }
+        result.extend_from_slice(&asset.align.0.to_le_bytes());
+        result.extend_from_slice(&asset.align.1.to_le_bytes());
+        result.extend_from_slice(&source_path_offset.to_le_bytes());
+        result.extend_from_slice(&(built.source_path.len() as u32).to_le_bytes());
+        result.extend_from_slice(&built.source_write_time.to_le_bytes());
+
+        data_offset += built.data.len() as u64;
+        first_tag += asset.tags.len() as u32;
+        source_path_offset += built.source_path.len() as u32;
+    }
+    for &index in &order {
+        result.extend_from_slice(built[index].source_path.as_bytes());
+    }
+    debug_assert_eq!(result.len(), tables_size);
+
+    for &index in &order {
+        result.extend_from_slice(&built[index].data);
+    }
+
+    result
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        eprintln!("usage: build_assets <source dir> <output.hha>");
+        process::exit(1);
+    }
+
+    let source_dir = Path::new(&args[1]);
+    let manifest_path = source_dir.join("assets.txt");
+    let manifest = match fs::read_to_string(&manifest_path) {
+        Ok(manifest) => manifest,
+        Err(error) => {
+            eprintln!(
+                "build_assets: can't read {}: {error}",
+                manifest_path.display()
+            );
+            process::exit(1);
+        }
+    };
+
+    let assets = match parse_manifest(&manifest) {
+        Ok(assets) => assets,
+        Err(error) => {
+            eprintln!("build_assets: {}: {error}", manifest_path.display());
+            process::exit(1);
+        }
+    };
+
+    let mut built = Vec::new();
+    for asset in &assets {
+        match build_asset(source_dir, asset) {
+            Ok(data) => built.push(data),
+            Err(error) => {
+                eprintln!("build_assets: line {}: {error}", asset.line_number);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Err(error) = fs::write(&args[2], write_pack(&assets, &built)) {
+        eprintln!("build_assets: can't write {}: {error}", args[2]);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn manifests_name_types_tags_and_options() {
+        let assets = parse_manifest(
+            "# the hero\n\
+             hero_head  front_head.bmp align=72,182 facing_direction=3 height=1.5 color=0.25\n\
+             \n\
+             debug_font mono.ttf size=16  # small\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            assets,
+            [
+                SourceAsset {
+                    line_number: 2,
+                    type_id: ASSET_TYPE_HERO_HEAD,
+                    filename: "front_head.bmp".to_string(),
+                    align: (72, 182),
+                    pixel_height: None,
+                    tags: vec![
+                        (ASSET_TAG_FACING_DIRECTION, 3.0),
+                        (ASSET_TAG_HEIGHT, 1.5),
+                        (ASSET_TAG_COLOR, 0.25),
+                    ],
+                },
+                SourceAsset {
+                    line_number: 4,
+                    type_id: ASSET_TYPE_DEBUG_FONT,
+                    filename: "mono.ttf".to_string(),
+                    align: (0, 0),
+                    pixel_height: Some(16.0),
+                    tags: Vec::new(),
+                },
+            ]
+        );
+
+        assert!(parse_manifest("tree tree.bmp").is_err());
+        assert!(parse_manifest("shadow").is_err());
+        assert!(parse_manifest("shadow shadow.bmp weight=2").is_err());
+        assert!(parse_manifest("shadow shadow.bmp align=1").is_err());
+    }
+
+    #[test]
+    fn packs_group_assets_by_type() {
+        let dir = env::temp_dir().join(format!("build_assets_test_{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("a.bmp"), b"BMfake").unwrap();
+        fs::write(dir.join("b.wav"), b"RIFF\0\0\0\0WAVEfake").unwrap();
+        fs::write(dir.join("c.ttf"), truetype::tests::square_font()).unwrap();
+        fs::write(dir.join("d.bmp"), b"not a bitmap").unwrap();
+
+        let assets = parse_manifest(
+            "bloop b.wav\n\
+             debug_font c.ttf size=10\n\
+             hero_head a.bmp align=1,2 facing_direction=3\n",
+        )
+        .unwrap();
+        let built: Vec<_> = assets
+            .iter()
+            .map(|asset| build_asset(&dir, asset).unwrap())
+            .collect();
+        let broken = parse_manifest("shadow d.bmp").unwrap();
+        assert!(build_asset(&dir, &broken[0]).is_err());
+        let head_write_time = file_write_time(&dir.join("a.bmp"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let pack = write_pack(&assets, &built);
+        let read_u32 = |at: usize| u32::from_le_bytes(pack[at..at + 4].try_into().unwrap());
+        assert_eq!(pack[0..4], ASSET_PACK_MAGIC);
+        assert_eq!(read_u32(8), 1);
+        assert_eq!(read_u32(16), 4);
+        let head_path = dir.join("a.bmp").to_str().unwrap().to_string();
+        let wav_path = dir.join("b.wav").to_str().unwrap().to_string();
+        assert_eq!(read_u32(20) as usize, head_path.len() + wav_path.len());
+
+        // NOTE: Hero head sorts first, then the bloop, then the font
+        let types_at = ASSET_PACK_HEADER_SIZE + ASSET_PACK_TAG_SIZE;
+        let type_range = |type_id: u32| {
+            let at = types_at + type_id as usize * ASSET_PACK_TYPE_SIZE;
+            (read_u32(at), read_u32(at + 4))
+        };
+        assert_eq!(type_range(ASSET_TYPE_HERO_HEAD), (1, 2));
+        assert_eq!(type_range(ASSET_TYPE_BLOOP), (2, 3));
+        assert_eq!(type_range(ASSET_TYPE_DEBUG_FONT), (3, 4));
+        assert_eq!(type_range(ASSET_TYPE_SHADOW), (1, 1));
+
+        let assets_at = types_at + ASSET_TYPE_COUNT * ASSET_PACK_TYPE_SIZE;
+        let head_at = assets_at + ASSET_PACK_ASSET_SIZE;
+        let data_offset = u64::from_le_bytes(pack[head_at..head_at + 8].try_into().unwrap());
+        assert_eq!(read_u32(head_at + 8), 6);
+        assert_eq!((read_u32(head_at + 12), read_u32(head_at + 16)), (0, 1));
+        assert_eq!(read_u32(head_at + 20), ASSET_KIND_BITMAP);
+        assert_eq!(&pack[data_offset as usize..][..6], b"BMfake");
+
+        // NOTE: Only the bitmap and the sound can be rebuilt from source
+        let source_paths_at = assets_at + 4 * ASSET_PACK_ASSET_SIZE;
+        let source_path = |asset_at: usize| {
+            let at = source_paths_at + read_u32(asset_at + 32) as usize;
+            &pack[at..at + read_u32(asset_at + 36) as usize]
+        };
+        assert_eq!(source_path(head_at), head_path.as_bytes());
+        assert_eq!(
+            source_path(head_at + ASSET_PACK_ASSET_SIZE),
+            wav_path.as_bytes()
+        );
+        let write_time = |asset_at: usize| {
+            u64::from_le_bytes(pack[asset_at + 40..asset_at + 48].try_into().unwrap())
+        };
+        assert_ne!(head_write_time, 0);
+        assert_eq!(write_time(head_at), head_write_time);
+
+        let font_at = assets_at + 3 * ASSET_PACK_ASSET_SIZE;
+        assert_eq!(read_u32(font_at + 20), ASSET_KIND_FONT);
+        assert_eq!(read_u32(font_at + 36), 0);
+        assert_eq!(write_time(font_at), 0);
+        let font_offset = u64::from_le_bytes(pack[font_at..font_at + 8].try_into().unwrap());
+        assert_eq!(pack[font_offset as usize..][..4], font_format::FONT_MAGIC);
+    }
+}
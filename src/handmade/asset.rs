@@ -0,0 +1,1410 @@
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use super::asset_format::*;
+use super::audio::*;
+use super::bitmap::*;
+use super::font::*;
+use super::memory::*;
+use super::sound::*;
+use super::work_queue::*;
+use super::{
+    DebugPlatformGetFileWriteTime, DebugPlatformLog, DebugPlatformReadEntireFile,
+    DebugPlatformReadFileRange, ThreadContext,
+};
+
+// NOTE: Index into the pack's assets; zero never names one
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BitmapId(u32);
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SoundId(u32);
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FontId(u32);
+
+#[derive(Clone, Copy, Debug)]
+struct AssetTag {
+    id: u32,
+    value: f32,
+}
+
+// NOTE: One more than the largest tag id this build knows
+pub const ASSET_TAG_COUNT: usize = 4;
+
+// NOTE: A value per tag, indexed by tag id
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AssetVector {
+    pub e: [f32; ASSET_TAG_COUNT],
+}
+
+#[derive(Clone, Copy, Debug)]
+struct AssetType {
+    first_asset: u32,
+    one_past_last_asset: u32,
+}
+
+// NOTE: Where a load goes. Failed assets aren't tried again, the data in the
+// pack won't have changed.
+const ASSET_STATE_UNLOADED: u32 = 0;
+const ASSET_STATE_QUEUED: u32 = 1;
+const ASSET_STATE_LOADED: u32 = 2;
+const ASSET_STATE_FAILED: u32 = 3;
+
+const MAX_ASSET_FILENAME_LENGTH: usize = 128;
+
+// NOTE: What a loading thread needs to find an asset's data
+#[derive(Clone, Copy)]
+struct AssetFile {
+    filename: [u8; MAX_ASSET_FILENAME_LENGTH],
+    filename_length: usize,
+    read_file_range: Option<DebugPlatformReadFileRange>,
+}
+
+#[derive(Clone, Copy)]
+struct Asset {
+    // NOTE: One of the ASSET_STATEs. Once an asset is queued, everything
+    // below the state belongs to the loading thread until it stores LOADED
+    // or FAILED, except requested and last_used_frame which only the game
+    // touches.
+    state: u32,
+    requested: bool,
+    last_used_frame: u32,
+
+    kind: u32,
+    first_tag: u32,
+    one_past_last_tag: u32,
+    file: *const AssetFile,
+    data_offset: u64,
+    data_size: u32,
+    align_x: i32,
+    align_y: i32,
+
+    // NOTE: The file the asset was built from, empty if it can't be rebuilt
+    // in game; once it has been, the asset's data comes from here instead of
+    // the pack. The write time starts out as the one the pack was built from.
+    source: AssetFile,
+    source_write_time: u64,
+
+    // NOTE: The block everything decoded lives in, freed on eviction. Only
+    // the part the decoded asset takes counts against the budget, since the
+    // block itself is sized off a guess.
+    memory: *mut u8,
+    memory_size: usize,
+    memory_used: usize,
+    // NOTE: Only the one matching the kind is used, and only once loaded
+    bitmap: LoadedBitmap,
+    sound: LoadedSound,
+    font: LoadedFont,
+}
+
+// NOTE: Lives in the game state, so all zeroes has to mean an empty pack
+// that every lookup misses in
+#[derive(Clone, Copy)]
+pub struct Assets {
+    types: [AssetType; ASSET_TYPE_COUNT],
+
+    tag_count: u32,
+    tags: *mut AssetTag,
+
+    asset_count: u32,
+    assets: *mut Asset,
+
+    memory_budget: usize,
+    frame_index: u32,
+    // NOTE: The last asset reload_changed_assets looked at
+    reload_cursor: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoadAssetsError {
+    ReadFailed,
+    Truncated,
+    Corrupt,
+    Unsupported,
+    OutOfMemory,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AssetLoadStats {
+    pub unloaded: u32,
+    pub queued: u32,
+    pub loaded: u32,
+    pub failed: u32,
+    pub memory_used: usize,
+    pub memory_budget: usize,
+}
+
+// NOTE: Anything larger than this is certainly not one of our packs
+const MAX_ASSET_COUNT: u32 = 1 << 20;
+const MAX_ASSET_TAG_COUNT: u32 = 1 << 22;
+// NOTE: Nor one of our assets, decoded
+const MAX_ASSET_MEMORY_SIZE: usize = 1 << 30;
+const MIN_ASSET_MEMORY_SIZE: usize = 4096;
+// NOTE: Debug only, how many source files get checked for edits a frame
+const RELOAD_CHECKS_PER_FRAME: u32 = 8;
+
+fn read_le<const N: usize>(data: &[u8], at: usize) -> Result<[u8; N], LoadAssetsError> {
+    data.get(at..at + N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(LoadAssetsError::Truncated)
+}
+
+fn read_u32(data: &[u8], at: usize) -> Result<u32, LoadAssetsError> {
+    read_le(data, at).map(u32::from_le_bytes)
+}
+
+fn read_u64(data: &[u8], at: usize) -> Result<u64, LoadAssetsError> {
+    read_le(data, at).map(u64::from_le_bytes)
+}
+
+fn read_i32(data: &[u8], at: usize) -> Result<i32, LoadAssetsError> {
+    read_le(data, at).map(i32::from_le_bytes)
+}
+
+fn read_f32(data: &[u8], at: usize) -> Result<f32, LoadAssetsError> {
+    read_le(data, at).map(f32::from_le_bytes)
+}
+
+fn asset_tags(assets: &Assets) -> &[AssetTag] {
+    if assets.tags.is_null() {
+        return &[];
+    }
+    unsafe { slice::from_raw_parts(assets.tags, assets.tag_count as usize) }
+}
+
+// NOTE: Assets can be written by a loading thread at any time, so they're
+// only ever reached through raw pointers, never a slice
+fn asset_pointer(assets: &Assets, asset_index: u32) -> Option<*mut Asset> {
+    if asset_index == 0 || asset_index >= assets.asset_count {
+        return None;
+    }
+    Some(unsafe { assets.assets.add(asset_index as usize) })
+}
+
+fn asset_state<'a>(asset: *mut Asset) -> &'a AtomicU32 {
+    unsafe { AtomicU32::from_ptr(ptr::addr_of_mut!((*asset).state)) }
+}
+
+fn asset_file_name(file: &AssetFile) -> &str {
+    std::str::from_utf8(&file.filename[..file.filename_length]).unwrap_or("")
+}
+
+// NOTE: Checks the header and returns how much of the file the tables take,
+// header included
+fn asset_pack_tables_size(contents: &[u8]) -> Result<usize, LoadAssetsError> {
+    use LoadAssetsError::*;
+
+    if contents.len() < ASSET_PACK_HEADER_SIZE {
+        return Err(Truncated);
+    }
+    if contents[0..4] != ASSET_PACK_MAGIC {
+        return Err(Corrupt);
+    }
+    if read_u32(contents, 4)? != ASSET_PACK_VERSION {
+        return Err(Unsupported);
+    }
+
+    let tag_count = read_u32(contents, 8)?;
+    let type_count = read_u32(contents, 12)? as usize;
+    let asset_count = read_u32(contents, 16)?;
+    let source_paths_size = read_u32(contents, 20)?;
+    if tag_count > MAX_ASSET_TAG_COUNT
+        || type_count > MAX_ASSET_COUNT as usize
+        || asset_count > MAX_ASSET_COUNT
+        || source_paths_size > MAX_ASSET_COUNT * MAX_ASSET_FILENAME_LENGTH as u32
+    {
+        return Err(Unsupported);
+    }
+
+    Ok(ASSET_PACK_HEADER_SIZE
+        + tag_count as usize * ASSET_PACK_TAG_SIZE
+        + type_count * ASSET_PACK_TYPE_SIZE
+        + asset_count as usize * ASSET_PACK_ASSET_SIZE
+        + source_paths_size as usize)
+}
+
+// NOTE: Reads the header and tables; the assets themselves stay in the file
+// until the game asks for them
+fn parse_asset_pack(
+    arena: &mut MemoryArena,
+    contents: &[u8],
+    file: *const AssetFile,
+) -> Result<Assets, LoadAssetsError> {
+    use LoadAssetsError::*;
+
+    let tables_size = asset_pack_tables_size(contents)?;
+    if contents.len() < tables_size {
+        return Err(Truncated);
+    }
+
+    let tag_count = read_u32(contents, 8)?;
+    let type_count = read_u32(contents, 12)? as usize;
+    let asset_count = read_u32(contents, 16)?;
+    let tags_at = ASSET_PACK_HEADER_SIZE;
+    let types_at = tags_at + tag_count as usize * ASSET_PACK_TAG_SIZE;
+    let assets_at = types_at + type_count * ASSET_PACK_TYPE_SIZE;
+    let source_paths = &contents[assets_at + asset_count as usize * ASSET_PACK_ASSET_SIZE..];
+
+    let mut result = Assets {
+        types: [AssetType {
+            first_asset: 0,
+            one_past_last_asset: 0,
+        }; ASSET_TYPE_COUNT],
+        tag_count,
+        tags: push_array::<AssetTag>(arena, tag_count as usize).ok_or(OutOfMemory)?,
+        asset_count,
+        assets: push_array::<Asset>(arena, asset_count as usize).ok_or(OutOfMemory)?,
+        memory_budget: 0,
+        frame_index: 0,
+        reload_cursor: 0,
+    };
+
+    for tag_index in 0..tag_count as usize {
+        let at = tags_at + tag_index * ASSET_PACK_TAG_SIZE;
+        let tag = AssetTag {
+            id: read_u32(contents, at)?,
+            value: read_f32(contents, at + 4)?,
+        };
+        unsafe { result.tags.add(tag_index).write(tag) };
+    }
+
+    // NOTE: Types this build doesn't know about are skipped, a newer pack
+    // still has everything an older game asks for
+    for type_id in 0..type_count.min(ASSET_TYPE_COUNT) {
+        let at = types_at + type_id * ASSET_PACK_TYPE_SIZE;
+        let first_asset = read_u32(contents, at)?;
+        let one_past_last_asset = read_u32(contents, at + 4)?;
+        if first_asset > one_past_last_asset || one_past_last_asset > asset_count {
+            return Err(Corrupt);
+        }
+        result.types[type_id] = AssetType {
+            first_asset,
+            one_past_last_asset,
+        };
+    }
+
+    for asset_index in 0..asset_count as usize {
+        let at = assets_at + asset_index * ASSET_PACK_ASSET_SIZE;
+        let data_offset = read_u64(contents, at)?;
+        let data_size = read_u32(contents, at + 8)?;
+        let first_tag = read_u32(contents, at + 12)?;
+        let one_past_last_tag = read_u32(contents, at + 16)?;
+
+        if first_tag > one_past_last_tag || one_past_last_tag > tag_count {
+            return Err(Corrupt);
+        }
+        data_offset.checked_add(data_size as u64).ok_or(Corrupt)?;
+        let source_path_offset = read_u32(contents, at + 32)? as usize;
+        let source_path_length = read_u32(contents, at + 36)? as usize;
+        let source_path = source_paths
+            .get(source_path_offset..source_path_offset + source_path_length)
+            .ok_or(Corrupt)?;
+
+        let asset = unsafe { &mut *result.assets.add(asset_index) };
+        asset.kind = read_u32(contents, at + 20)?;
+        asset.first_tag = first_tag;
+        asset.one_past_last_tag = one_past_last_tag;
+        asset.file = file;
+        asset.data_offset = data_offset;
+        asset.data_size = data_size;
+        asset.align_x = read_i32(contents, at + 24)?;
+        asset.align_y = read_i32(contents, at + 28)?;
+        // NOTE: A path too long to keep just means no reloading that asset
+        if source_path.len() <= MAX_ASSET_FILENAME_LENGTH {
+            asset.source.filename[..source_path.len()].copy_from_slice(source_path);
+            asset.source.filename_length = source_path.len();
+        }
+        asset.source_write_time = read_u64(contents, at + 40)?;
+    }
+
+    Ok(result)
+}
+
+// NOTE: Only the tables are read here. The pack has to stay where it is, the
+// assets are read out of it as they're asked for; decoded, they're kept to
+// within memory_budget bytes.
+pub fn load_asset_pack(
+    thread: &ThreadContext,
+    read_file_range: DebugPlatformReadFileRange,
+    arena: &mut MemoryArena,
+    filename: &str,
+    memory_budget: usize,
+) -> Result<Assets, LoadAssetsError> {
+    if filename.len() > MAX_ASSET_FILENAME_LENGTH {
+        return Err(LoadAssetsError::Unsupported);
+    }
+
+    let mut header = [0u8; ASSET_PACK_HEADER_SIZE];
+    match read_file_range(thread, filename, 0, &mut header) {
+        0 => return Err(LoadAssetsError::ReadFailed),
+        size if size < header.len() => return Err(LoadAssetsError::Truncated),
+        _ => {}
+    }
+
+    let mut tables = vec![0u8; asset_pack_tables_size(&header)?];
+    let tables_read = read_file_range(thread, filename, 0, &mut tables);
+
+    let file = push_array::<AssetFile>(arena, 1).ok_or(LoadAssetsError::OutOfMemory)?;
+    let file = unsafe { &mut *file };
+    file.filename[..filename.len()].copy_from_slice(filename.as_bytes());
+    file.filename_length = filename.len();
+    file.read_file_range = Some(read_file_range);
+
+    let mut result = parse_asset_pack(arena, &tables[..tables_read], file)?;
+    result.memory_budget = memory_budget;
+
+    Ok(result)
+}
+
+enum DecodedAsset {
+    Bitmap(LoadedBitmap),
+    Sound(LoadedSound),
+    Font(LoadedFont),
+}
+
+fn decode_asset(
+    arena: &mut MemoryArena,
+    kind: u32,
+    data: &[u8],
+    align_x: i32,
+    align_y: i32,
+) -> Result<DecodedAsset, LoadAssetsError> {
+    use LoadAssetsError::*;
+
+    match kind {
+        ASSET_KIND_BITMAP => match parse_bitmap(arena, data, align_x, align_y) {
+            Ok(bitmap) => Ok(DecodedAsset::Bitmap(bitmap)),
+            Err(LoadBitmapError::OutOfMemory) => Err(OutOfMemory),
+            Err(_) => Err(Corrupt),
+        },
+        ASSET_KIND_SOUND => match parse_wav(arena, data) {
+            Ok(sound) => Ok(DecodedAsset::Sound(sound)),
+            Err(LoadSoundError::OutOfMemory) => Err(OutOfMemory),
+            Err(_) => Err(Corrupt),
+        },
+        ASSET_KIND_FONT => match parse_font(arena, data) {
+            Ok(font) => Ok(DecodedAsset::Font(font)),
+            Err(LoadFontError::OutOfMemory) => Err(OutOfMemory),
+            Err(_) => Err(Corrupt),
+        },
+        _ => Err(Unsupported),
+    }
+}
+
+// NOTE: Runs on a loading thread, and returns the state to leave the asset in
+fn load_asset(asset: *mut Asset) -> u32 {
+    let (file, data_offset, data_size, kind, align_x, align_y) = unsafe {
+        (
+            &*(*asset).file,
+            (*asset).data_offset,
+            (*asset).data_size,
+            (*asset).kind,
+            (*asset).align_x,
+            (*asset).align_y,
+        )
+    };
+    let Some(read_file_range) = file.read_file_range else {
+        return ASSET_STATE_FAILED;
+    };
+
+    let thread = ThreadContext { placeholder: 0 };
+    let mut data = vec![0u8; data_size as usize];
+    if read_file_range(&thread, asset_file_name(file), data_offset, &mut data) != data.len() {
+        return ASSET_STATE_FAILED;
+    }
+
+    match decode_asset_block(kind, &data, align_x, align_y) {
+        Ok(block) => {
+            unsafe { set_asset_block(asset, block) };
+            ASSET_STATE_LOADED
+        }
+        Err(_) => ASSET_STATE_FAILED,
+    }
+}
+
+struct AssetBlock {
+    memory: *mut u8,
+    memory_size: usize,
+    memory_used: usize,
+    decoded: DecodedAsset,
+}
+
+// NOTE: How much room an asset takes decoded isn't known until it's
+// decoded, so this starts from a guess off the file size and doubles until
+// it fits
+fn decode_asset_block(
+    kind: u32,
+    data: &[u8],
+    align_x: i32,
+    align_y: i32,
+) -> Result<AssetBlock, LoadAssetsError> {
+    let mut memory_size = (4 * data.len()).max(MIN_ASSET_MEMORY_SIZE);
+    loop {
+        let memory = Box::into_raw(vec![0u8; memory_size].into_boxed_slice()) as *mut u8;
+        let mut arena = MemoryArena {
+            size: 0,
+            base: ptr::null_mut(),
+            used: 0,
+        };
+        initialize_arena(&mut arena, memory_size, memory);
+
+        match decode_asset(&mut arena, kind, data, align_x, align_y) {
+            Ok(decoded) => {
+                return Ok(AssetBlock {
+                    memory,
+                    memory_size,
+                    memory_used: arena.used,
+                    decoded,
+                })
+            }
+            Err(LoadAssetsError::OutOfMemory) if memory_size < MAX_ASSET_MEMORY_SIZE => {
+                free_asset_memory(memory, memory_size);
+                memory_size *= 2;
+            }
+            Err(error) => {
+                free_asset_memory(memory, memory_size);
+                return Err(error);
+            }
+        }
+    }
+}
+
+// NOTE: Whoever calls this owns the asset: the loading thread while it's
+// queued, the game otherwise
+unsafe fn set_asset_block(asset: *mut Asset, block: AssetBlock) {
+    (*asset).memory = block.memory;
+    (*asset).memory_size = block.memory_size;
+    (*asset).memory_used = block.memory_used;
+    match block.decoded {
+        DecodedAsset::Bitmap(bitmap) => (*asset).bitmap = bitmap,
+        DecodedAsset::Sound(sound) => (*asset).sound = sound,
+        DecodedAsset::Font(font) => (*asset).font = font,
+    }
+}
+
+fn load_asset_work(data: *mut u8) {
+    let asset = data as *mut Asset;
+    let state = load_asset(asset);
+    asset_state(asset).store(state, Ordering::Release);
+}
+
+fn free_asset_memory(memory: *mut u8, memory_size: usize) {
+    drop(unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(memory, memory_size)) });
+}
+
+// NOTE: Queued assets count as their data size until they're decoded and
+// their real size is known
+fn asset_memory_used(assets: &Assets) -> usize {
+    let mut result = 0;
+    for asset_index in 1..assets.asset_count {
+        let Some(asset) = asset_pointer(assets, asset_index) else {
+            continue;
+        };
+        match asset_state(asset).load(Ordering::Acquire) {
+            ASSET_STATE_LOADED => result += unsafe { (*asset).memory_used },
+            ASSET_STATE_QUEUED => result += unsafe { (*asset).data_size } as usize,
+            _ => {}
+        }
+    }
+    result
+}
+
+// NOTE: Loaded, not used this frame and not still being played from
+fn is_evictable(asset: *mut Asset, frame_index: u32, audio: &AudioState) -> bool {
+    if asset_state(asset).load(Ordering::Acquire) != ASSET_STATE_LOADED {
+        return false;
+    }
+
+    let asset = unsafe { &*asset };
+    asset.last_used_frame != frame_index
+        && !(asset.kind == ASSET_KIND_SOUND && is_sound_in_use(audio, &asset.sound))
+}
+
+// NOTE: Frees the least recently used asset that can go, returning how much
+// memory that gave back, or None if nothing could
+fn evict_asset(assets: &mut Assets, audio: &AudioState) -> Option<usize> {
+    let frame_index = assets.frame_index;
+    let oldest = (1..assets.asset_count)
+        .filter_map(|asset_index| asset_pointer(assets, asset_index))
+        .filter(|&asset| is_evictable(asset, frame_index, audio))
+        .max_by_key(|&asset| frame_index.wrapping_sub(unsafe { (*asset).last_used_frame }))?;
+
+    let asset = unsafe { &mut *oldest };
+    let memory_used = asset.memory_used;
+    free_asset_memory(asset.memory, asset.memory_size);
+    asset.memory = ptr::null_mut();
+    asset.memory_size = 0;
+    asset.memory_used = 0;
+    asset_state(oldest).store(ASSET_STATE_UNLOADED, Ordering::Relaxed);
+
+    Some(memory_used)
+}
+
+// NOTE: Called once a frame after everything this frame fetched is done
+// with. Evicts down to the budget, then queues whatever was asked for and
+// isn't loaded, oldest assets first out to make room; anything that still
+// doesn't fit stays requested for a later frame. Without a queue, assets load right
+// here.
+pub fn update_assets(assets: &mut Assets, audio: &AudioState, queue: Option<&PlatformWorkQueue>) {
+    let mut memory_used = asset_memory_used(assets);
+    while memory_used > assets.memory_budget {
+        match evict_asset(assets, audio) {
+            Some(freed) => memory_used -= freed,
+            None => break,
+        }
+    }
+
+    for asset_index in 1..assets.asset_count {
+        let Some(asset) = asset_pointer(assets, asset_index) else {
+            continue;
+        };
+        if !unsafe { (*asset).requested } {
+            continue;
+        }
+        if asset_state(asset).load(Ordering::Acquire) != ASSET_STATE_UNLOADED {
+            unsafe { (*asset).requested = false };
+            continue;
+        }
+
+        let data_size = unsafe { (*asset).data_size } as usize;
+        while memory_used + data_size > assets.memory_budget {
+            match evict_asset(assets, audio) {
+                Some(freed) => memory_used -= freed,
+                None => break,
+            }
+        }
+        if memory_used + data_size > assets.memory_budget {
+            continue;
+        }
+
+        unsafe { (*asset).requested = false };
+        asset_state(asset).store(ASSET_STATE_QUEUED, Ordering::Release);
+        memory_used += data_size;
+        match queue {
+            Some(queue) => add_entry(queue, load_asset_work, asset as *mut u8),
+            None => load_asset_work(asset as *mut u8),
+        }
+    }
+
+    assets.frame_index = assets.frame_index.wrapping_add(1);
+}
+
+// NOTE: Debug only. Checks a few assets a frame, rather than statting every
+// source file every frame, for a source whose write time differs from the one
+// the pack or the last reload took it at, so edits from before the game
+// started count too. From then on the asset loads from the source rather
+// than the pack. A loaded asset is rebuilt and swapped in for the next frame;
+// anything else stays unloaded for the usual request and queue to load. If
+// the new file doesn't read or decode, that goes to the log and the old
+// asset stays. Like update_assets, this frees memory, so it runs once nothing
+// fetched this frame is still in use.
+pub fn reload_changed_assets(
+    assets: &mut Assets,
+    audio: &AudioState,
+    thread: &ThreadContext,
+    get_file_write_time: DebugPlatformGetFileWriteTime,
+    read_entire_file: DebugPlatformReadEntireFile,
+    log: DebugPlatformLog,
+) {
+    if assets.asset_count <= 1 {
+        return;
+    }
+    let check_count = RELOAD_CHECKS_PER_FRAME.min(assets.asset_count - 1);
+    for _ in 0..check_count {
+        let asset_index = 1 + assets.reload_cursor % (assets.asset_count - 1);
+        assets.reload_cursor = asset_index;
+        let Some(asset_pointer) = asset_pointer(assets, asset_index) else {
+            continue;
+        };
+        let state = asset_state(asset_pointer).load(Ordering::Acquire);
+        // NOTE: A load in flight owns the asset, it's looked at again after
+        if state == ASSET_STATE_QUEUED {
+            continue;
+        }
+
+        let asset = unsafe { &mut *asset_pointer };
+        if asset.source.filename_length == 0 {
+            continue;
+        }
+        let source = asset.source;
+        let source_path = asset_file_name(&source);
+        let write_time = get_file_write_time(thread, source_path);
+        if write_time == 0 || write_time == asset.source_write_time {
+            continue;
+        }
+        // NOTE: The old samples can't go while a voice still plays them, so
+        // the new ones wait until it's done
+        if state == ASSET_STATE_LOADED
+            && asset.kind == ASSET_KIND_SOUND
+            && is_sound_in_use(audio, &asset.sound)
+        {
+            continue;
+        }
+        asset.source_write_time = write_time;
+
+        // NOTE: Read even when it's not decoded here, since that's the only
+        // way to know how much a load from the source has to read
+        let read_result = read_entire_file(thread, source_path);
+        if read_result.contents_size == 0 {
+            log(
+                thread,
+                &format!("can't reload {source_path}, keeping the old asset"),
+            );
+            continue;
+        }
+        let data = &read_result.contents;
+        if state == ASSET_STATE_LOADED {
+            let block = match decode_asset_block(asset.kind, data, asset.align_x, asset.align_y) {
+                Ok(block) => block,
+                Err(error) => {
+                    log(
+                        thread,
+                        &format!("can't reload {source_path} ({error:?}), keeping the old asset"),
+                    );
+                    continue;
+                }
+            };
+            free_asset_memory(asset.memory, asset.memory_size);
+            unsafe { set_asset_block(asset_pointer, block) };
+        }
+
+        let asset = unsafe { &mut *asset_pointer };
+        asset.source.read_file_range =
+            unsafe { asset.file.as_ref() }.and_then(|file| file.read_file_range);
+        asset.file = &asset.source;
+        asset.data_offset = 0;
+        asset.data_size = data.len() as u32;
+        // NOTE: A failed asset gets another go now that there's a new file
+        if state == ASSET_STATE_FAILED {
+            asset_state(asset_pointer).store(ASSET_STATE_UNLOADED, Ordering::Release);
+        }
+    }
+}
+
+pub fn get_asset_load_stats(assets: &Assets) -> AssetLoadStats {
+    let mut result = AssetLoadStats {
+        memory_used: asset_memory_used(assets),
+        memory_budget: assets.memory_budget,
+        ..Default::default()
+    };
+
+    for asset_index in 1..assets.asset_count {
+        let Some(asset) = asset_pointer(assets, asset_index) else {
+            continue;
+        };
+        match asset_state(asset).load(Ordering::Relaxed) {
+            ASSET_STATE_UNLOADED => result.unloaded += 1,
+            ASSET_STATE_QUEUED => result.queued += 1,
+            ASSET_STATE_LOADED => result.loaded += 1,
+            _ => result.failed += 1,
+        }
+    }
+
+    result
+}
+
+// NOTE: Marks the asset used this frame; if it isn't loaded yet, it's
+// requested and the next update_assets starts on it
+fn use_asset(assets: &mut Assets, asset_index: u32, kind: u32) -> Option<*mut Asset> {
+    let asset = asset_pointer(assets, asset_index)?;
+    if unsafe { (*asset).kind } != kind {
+        return None;
+    }
+
+    unsafe { (*asset).last_used_frame = assets.frame_index };
+    match asset_state(asset).load(Ordering::Acquire) {
+        ASSET_STATE_LOADED => Some(asset),
+        ASSET_STATE_UNLOADED => {
+            unsafe { (*asset).requested = true };
+            None
+        }
+        _ => None,
+    }
+}
+
+// NOTE: The distance between two tag values; tags that wrap around go the
+// short way
+fn tag_distance(tag_id: u32, a: f32, b: f32) -> f32 {
+    let period = match tag_id {
+        ASSET_TAG_FACING_DIRECTION => 4.0,
+        ASSET_TAG_COLOR => 1.0,
+        _ => return (a - b).abs(),
+    };
+    let distance = (a - b).rem_euclid(period);
+    distance.min(period - distance)
+}
+
+// NOTE: The asset of the type whose tags are closest to the match vector,
+// each tag's distance scaled by its weight. Only the tags an asset has
+// count, so one without tags matches anything; ties go to the first in the
+// pack.
+fn best_match_asset(
+    assets: &Assets,
+    type_id: u32,
+    match_vector: &AssetVector,
+    weight_vector: &AssetVector,
+) -> u32 {
+    let Some(asset_type) = assets.types.get(type_id as usize) else {
+        return 0;
+    };
+
+    let tags = asset_tags(assets);
+    let mut result = 0;
+    let mut best_diff = f32::MAX;
+    for asset_index in asset_type.first_asset..asset_type.one_past_last_asset {
+        let Some(asset) = asset_pointer(assets, asset_index) else {
+            continue;
+        };
+        let (first_tag, one_past_last_tag) =
+            unsafe { ((*asset).first_tag, (*asset).one_past_last_tag) };
+
+        let mut total_diff = 0.0;
+        for tag in &tags[first_tag as usize..one_past_last_tag as usize] {
+            // NOTE: Tags this build doesn't know can't be asked for
+            let Some(&wanted) = match_vector.e.get(tag.id as usize) else {
+                continue;
+            };
+            let weight = weight_vector.e[tag.id as usize];
+            total_diff += weight * tag_distance(tag.id, wanted, tag.value);
+        }
+
+        if total_diff < best_diff {
+            best_diff = total_diff;
+            result = asset_index;
+        }
+    }
+
+    result
+}
+
+fn first_asset(assets: &Assets, type_id: u32) -> u32 {
+    match assets.types.get(type_id as usize) {
+        Some(asset_type) if asset_type.first_asset < asset_type.one_past_last_asset => {
+            asset_type.first_asset
+        }
+        _ => 0,
+    }
+}
+
+pub fn first_bitmap(assets: &Assets, type_id: u32) -> BitmapId {
+    BitmapId(first_asset(assets, type_id))
+}
+
+pub fn best_match_bitmap(
+    assets: &Assets,
+    type_id: u32,
+    match_vector: &AssetVector,
+    weight_vector: &AssetVector,
+) -> BitmapId {
+    BitmapId(best_match_asset(
+        assets,
+        type_id,
+        match_vector,
+        weight_vector,
+    ))
+}
+
+pub fn first_sound(assets: &Assets, type_id: u32) -> SoundId {
+    SoundId(first_asset(assets, type_id))
+}
+
+pub fn first_font(assets: &Assets, type_id: u32) -> FontId {
+    FontId(first_asset(assets, type_id))
+}
+
+// NOTE: None until the asset is loaded, which takes a few frames after it's
+// first asked for. What comes back is good until the next update_assets.
+pub fn get_bitmap(assets: &mut Assets, id: BitmapId) -> Option<LoadedBitmap> {
+    use_asset(assets, id.0, ASSET_KIND_BITMAP).map(|asset| unsafe { (*asset).bitmap })
+}
+
+pub fn get_sound(assets: &mut Assets, id: SoundId) -> Option<LoadedSound> {
+    use_asset(assets, id.0, ASSET_KIND_SOUND).map(|asset| unsafe { (*asset).sound })
+}
+
+pub fn get_font(assets: &mut Assets, id: FontId) -> Option<LoadedFont> {
+    use_asset(assets, id.0, ASSET_KIND_FONT).map(|asset| unsafe { (*asset).font })
+}
+
+// NOTE: For sounds that have to be ready the moment they're played
+pub fn prefetch_sound(assets: &mut Assets, id: SoundId) {
+    use_asset(assets, id.0, ASSET_KIND_SOUND);
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::super::font::tests::font_file;
+    use super::super::sound::tests::wav_file;
+    use super::super::tests::with_arena;
+    use super::super::DebugReadFileResult;
+    use super::*;
+    use std::sync::atomic::AtomicU64;
+    use std::sync::Mutex;
+
+    pub struct PackAsset {
+        pub type_id: u32,
+        pub kind: u32,
+        pub tags: Vec<(u32, f32)>,
+        pub align: (i32, i32),
+        pub data: Vec<u8>,
+        pub source_path: &'static str,
+        pub source_write_time: u64,
+    }
+
+    // NOTE: A pack the way build_assets writes one, assets already grouped
+    // by type
+    pub fn pack_file(assets: &[PackAsset]) -> Vec<u8> {
+        let tag_count: usize = assets.iter().map(|asset| asset.tags.len()).sum();
+        let asset_count = assets.len() + 1;
+        let source_paths_size: usize = assets.iter().map(|asset| asset.source_path.len()).sum();
+
+        let mut result = Vec::new();
+        result.extend_from_slice(&ASSET_PACK_MAGIC);
+        for value in [
+            ASSET_PACK_VERSION,
+            tag_count as u32,
+            ASSET_TYPE_COUNT as u32,
+            asset_count as u32,
+            source_paths_size as u32,
+        ] {
+            result.extend_from_slice(&value.to_le_bytes());
+        }
+
+        for &(id, value) in assets.iter().flat_map(|asset| asset.tags.iter()) {
+            result.extend_from_slice(&id.to_le_bytes());
+            result.extend_from_slice(&value.to_le_bytes());
+        }
+
+        for type_id in 0..ASSET_TYPE_COUNT as u32 {
+            let first = assets.iter().position(|asset| asset.type_id == type_id);
+            let count = assets
+                .iter()
+                .filter(|asset| asset.type_id == type_id)
+                .count();
+            let first = first.map_or(0, |index| index + 1) as u32;
+            result.extend_from_slice(&first.to_le_bytes());
+            result.extend_from_slice(&(first + count as u32).to_le_bytes());
+        }
+
+        let mut data_offset = (ASSET_PACK_HEADER_SIZE
+            + tag_count * ASSET_PACK_TAG_SIZE
+            + ASSET_TYPE_COUNT * ASSET_PACK_TYPE_SIZE
+            + asset_count * ASSET_PACK_ASSET_SIZE
+            + source_paths_size) as u64;
+        result.extend_from_slice(&[0; ASSET_PACK_ASSET_SIZE]);
+        let mut first_tag = 0;
+        let mut source_path_offset = 0u32;
+        for asset in assets {
+            result.extend_from_slice(&data_offset.to_le_bytes());
+            for value in [
+                asset.data.len() as u32,
+                first_tag,
+                first_tag + asset.tags.len() as u32,
+                asset.kind,
+            ] {
+                result.extend_from_slice(&value.to_le_bytes());
+            }
+            result.extend_from_slice(&asset.align.0.to_le_bytes());
+            result.extend_from_slice(&asset.align.1.to_le_bytes());
+            result.extend_from_slice(&source_path_offset.to_le_bytes());
+            result.extend_from_slice(&(asset.source_path.len() as u32).to_le_bytes());
+            result.extend_from_slice(&asset.source_write_time.to_le_bytes());
+
+            data_offset += asset.data.len() as u64;
+            first_tag += asset.tags.len() as u32;
+            source_path_offset += asset.source_path.len() as u32;
+        }
+        for asset in assets {
+            result.extend_from_slice(asset.source_path.as_bytes());
+        }
+
+        for asset in assets {
+            result.extend_from_slice(&asset.data);
+        }
+
+        result
+    }
+
+    // NOTE: A 1x1 24 bit BMP of the given color
+    pub fn pixel_bmp(blue: u8, green: u8, red: u8) -> Vec<u8> {
+        let mut result = Vec::new();
+        result.extend_from_slice(b"BM");
+        result.extend_from_slice(&58u32.to_le_bytes());
+        result.extend_from_slice(&0u32.to_le_bytes());
+        result.extend_from_slice(&54u32.to_le_bytes());
+        result.extend_from_slice(&40u32.to_le_bytes());
+        result.extend_from_slice(&1i32.to_le_bytes());
+        result.extend_from_slice(&1i32.to_le_bytes());
+        result.extend_from_slice(&1u16.to_le_bytes());
+        result.extend_from_slice(&24u16.to_le_bytes());
+        result.extend_from_slice(&[0; 24]);
+        result.extend_from_slice(&[blue, green, red, 0]);
+        result
+    }
+
+    pub fn test_pack() -> Vec<u8> {
+        let hero_head = |facing: f32, blue: u8| PackAsset {
+            type_id: ASSET_TYPE_HERO_HEAD,
+            kind: ASSET_KIND_BITMAP,
+            tags: vec![(ASSET_TAG_FACING_DIRECTION, facing)],
+            align: (1, 2),
+            data: pixel_bmp(blue, 0, 0),
+            source_path: "",
+            source_write_time: 0,
+        };
+
+        pack_file(&[
+            PackAsset {
+                type_id: ASSET_TYPE_BACKDROP,
+                kind: ASSET_KIND_BITMAP,
+                tags: Vec::new(),
+                align: (0, 0),
+                data: pixel_bmp(0, 0, 255),
+                source_path: "art/backdrop.bmp",
+                source_write_time: 1,
+            },
+            hero_head(0.0, 10),
+            hero_head(3.0, 13),
+            PackAsset {
+                type_id: ASSET_TYPE_BLOOP,
+                kind: ASSET_KIND_SOUND,
+                tags: Vec::new(),
+                align: (0, 0),
+                data: wav_file(22050, 1, 16, &[1, 0, 2, 0]),
+                source_path: "",
+                source_write_time: 0,
+            },
+            PackAsset {
+                type_id: ASSET_TYPE_DEBUG_FONT,
+                kind: ASSET_KIND_FONT,
+                tags: Vec::new(),
+                align: (0, 0),
+                data: font_file(65, &[(0, 0, 1, 1, 0, 1, 2.0)], &[], 1, &[255]),
+                source_path: "",
+                source_write_time: 0,
+            },
+        ])
+    }
+
+    fn read_test_file_range(
+        _thread: &ThreadContext,
+        filename: &str,
+        offset: u64,
+        dest: &mut [u8],
+    ) -> usize {
+        let file = match filename {
+            "test.hha" => test_pack(),
+            "header_only.hha" => test_pack()[..10].to_vec(),
+            "truncated.hha" => {
+                let pack = test_pack();
+                pack[..pack.len() - 1].to_vec()
+            }
+            "wrong_magic.hha" => {
+                let mut pack = test_pack();
+                pack[0] = b'X';
+                pack
+            }
+            "art/backdrop.bmp" => backdrop_source(),
+            "shadows.hha" => {
+                let shadows: Vec<_> = (0..=RELOAD_CHECKS_PER_FRAME)
+                    .map(|_| PackAsset {
+                        type_id: ASSET_TYPE_SHADOW,
+                        kind: ASSET_KIND_BITMAP,
+                        tags: Vec::new(),
+                        align: (0, 0),
+                        data: pixel_bmp(0, 0, 0),
+                        source_path: "art/shadow.bmp",
+                        source_write_time: 1,
+                    })
+                    .collect();
+                pack_file(&shadows)
+            }
+            "broken_bitmap.hha" => pack_file(&[PackAsset {
+                type_id: ASSET_TYPE_SHADOW,
+                kind: ASSET_KIND_BITMAP,
+                tags: Vec::new(),
+                align: (0, 0),
+                data: b"not a bitmap".to_vec(),
+                source_path: "",
+                source_write_time: 0,
+            }]),
+            _ => return 0,
+        };
+        let start = (offset as usize).min(file.len());
+        let count = dest.len().min(file.len() - start);
+        dest[..count].copy_from_slice(&file[start..start + count]);
+        count
+    }
+
+    // NOTE: The backdrop's source file as the reload test edits it, which is
+    // also its write time; zero is no file
+    static BACKDROP_SOURCE_VERSION: AtomicU64 = AtomicU64::new(0);
+    static RELOAD_LOG: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    static SHADOW_SOURCE_CHECKS: AtomicU32 = AtomicU32::new(0);
+
+    fn backdrop_source() -> Vec<u8> {
+        match BACKDROP_SOURCE_VERSION.load(Ordering::Relaxed) {
+            0 => Vec::new(),
+            1 => pixel_bmp(0, 0, 255),
+            2 => pixel_bmp(0, 255, 0),
+            _ => b"half a bitmap".to_vec(),
+        }
+    }
+
+    fn test_file_write_time(_thread: &ThreadContext, filename: &str) -> u64 {
+        match filename {
+            "art/backdrop.bmp" => BACKDROP_SOURCE_VERSION.load(Ordering::Relaxed),
+            "art/shadow.bmp" => {
+                SHADOW_SOURCE_CHECKS.fetch_add(1, Ordering::Relaxed);
+                0
+            }
+            _ => 0,
+        }
+    }
+
+    fn read_test_file(thread: &ThreadContext, filename: &str) -> DebugReadFileResult {
+        let mut contents = vec![0u8; 1 << 16];
+        let size = read_test_file_range(thread, filename, 0, &mut contents);
+        contents.truncate(size);
+        DebugReadFileResult {
+            contents_size: size as u32,
+            contents,
+        }
+    }
+
+    fn log_to_test_log(_thread: &ThreadContext, message: &str) {
+        RELOAD_LOG.lock().unwrap().push(message.to_string());
+    }
+
+    fn load_test_pack(
+        arena: &mut MemoryArena,
+        filename: &str,
+        memory_budget: usize,
+    ) -> Result<Assets, LoadAssetsError> {
+        let thread = ThreadContext { placeholder: 0 };
+        load_asset_pack(
+            &thread,
+            read_test_file_range,
+            arena,
+            filename,
+            memory_budget,
+        )
+    }
+
+    // NOTE: Zeroed the way the game state is, so nothing is playing
+    fn silent_audio() -> Box<AudioState> {
+        unsafe { Box::new(std::mem::zeroed()) }
+    }
+
+    #[test]
+    fn packs_hold_bitmaps_sounds_and_fonts_by_type() {
+        with_arena(64 * 1024, |arena| {
+            let mut assets = load_test_pack(arena, "test.hha", 1 << 20).unwrap();
+            let backdrop_id = first_bitmap(&assets, ASSET_TYPE_BACKDROP);
+            let bloop_id = first_sound(&assets, ASSET_TYPE_BLOOP);
+            let font_id = first_font(&assets, ASSET_TYPE_DEBUG_FONT);
+
+            // NOTE: Nothing the first time, the loads happen in the background
+            assert!(get_bitmap(&mut assets, backdrop_id).is_none());
+            assert!(get_sound(&mut assets, bloop_id).is_none());
+            assert!(get_font(&mut assets, font_id).is_none());
+            let queue = make_work_queue(2);
+            update_assets(&mut assets, &silent_audio(), Some(&queue));
+            complete_all_work(&queue);
+
+            let backdrop = get_bitmap(&mut assets, backdrop_id).unwrap();
+            assert_eq!((backdrop.width, backdrop.height), (1, 1));
+            assert_eq!(bitmap_row(&backdrop, 0), [0xFFFF0000]);
+
+            let bloop = get_sound(&mut assets, bloop_id).unwrap();
+            assert_eq!(bloop.samples_per_second, 22050);
+            assert_eq!(sound_channel(&bloop, 0), [1, 2]);
+
+            let font = get_font(&mut assets, font_id).unwrap();
+            assert_eq!(get_glyph(&font, 65).unwrap().advance, 2.0);
+
+            // NOTE: Asking for the wrong kind, or a type with nothing in it
+            let as_sound = SoundId(backdrop_id.0);
+            assert!(get_sound(&mut assets, as_sound).is_none());
+            assert_eq!(first_bitmap(&assets, ASSET_TYPE_SHADOW), BitmapId(0));
+            assert!(get_bitmap(&mut assets, BitmapId(0)).is_none());
+        });
+    }
+
+    fn hero_head(assets: &Assets, facing_direction: f32) -> BitmapId {
+        let mut match_vector = AssetVector::default();
+        let mut weight_vector = AssetVector::default();
+        match_vector.e[ASSET_TAG_FACING_DIRECTION as usize] = facing_direction;
+        weight_vector.e[ASSET_TAG_FACING_DIRECTION as usize] = 1.0;
+        best_match_bitmap(assets, ASSET_TYPE_HERO_HEAD, &match_vector, &weight_vector)
+    }
+
+    #[test]
+    fn variants_are_found_by_closest_tags() {
+        with_arena(64 * 1024, |arena| {
+            let mut assets = load_test_pack(arena, "test.hha", 1 << 20).unwrap();
+
+            let front = hero_head(&assets, 3.0);
+            get_bitmap(&mut assets, front);
+            update_assets(&mut assets, &silent_audio(), None);
+            let front = get_bitmap(&mut assets, front).unwrap();
+            assert_eq!(bitmap_row(&front, 0), [0xFF00000D]);
+            assert_eq!((front.align_x, front.align_y), (1, 2));
+
+            // NOTE: Only right and front exist; back is nearer right, and
+            // left is nearer front going round the other way
+            let (right, front) = (BitmapId(2), BitmapId(3));
+            assert_eq!(hero_head(&assets, 0.0), right);
+            assert_eq!(hero_head(&assets, 1.0), right);
+            assert_eq!(hero_head(&assets, 2.0), front);
+            assert_eq!(hero_head(&assets, 3.0), front);
+
+            // NOTE: With nothing weighted everything ties, and the first wins
+            let anything = best_match_bitmap(
+                &assets,
+                ASSET_TYPE_HERO_HEAD,
+                &AssetVector::default(),
+                &AssetVector::default(),
+            );
+            assert_eq!(anything, right);
+            let nothing = best_match_bitmap(
+                &assets,
+                ASSET_TYPE_SHADOW,
+                &AssetVector::default(),
+                &AssetVector::default(),
+            );
+            assert_eq!(nothing, BitmapId(0));
+        });
+    }
+
+    #[test]
+    fn weights_decide_between_tags() {
+        with_arena(64 * 1024, |arena| {
+            let torso = |height: f32, color: f32| PackAsset {
+                type_id: ASSET_TYPE_HERO_TORSO,
+                kind: ASSET_KIND_BITMAP,
+                tags: vec![(ASSET_TAG_HEIGHT, height), (ASSET_TAG_COLOR, color)],
+                align: (0, 0),
+                data: pixel_bmp(0, 0, 0),
+                source_path: "",
+                source_write_time: 0,
+            };
+            let pack = pack_file(&[torso(1.0, 0.1), torso(2.0, 0.9)]);
+            let tables_size = asset_pack_tables_size(&pack).unwrap();
+            let assets = parse_asset_pack(arena, &pack[..tables_size], ptr::null()).unwrap();
+            let (short_red, tall_violet) = (BitmapId(1), BitmapId(2));
+
+            let mut match_vector = AssetVector::default();
+            match_vector.e[ASSET_TAG_HEIGHT as usize] = 1.8;
+            match_vector.e[ASSET_TAG_COLOR as usize] = 0.05;
+            let mut weight_vector = AssetVector::default();
+            weight_vector.e[ASSET_TAG_HEIGHT as usize] = 1.0;
+            let best = |weight_vector: &AssetVector| {
+                best_match_bitmap(&assets, ASSET_TYPE_HERO_TORSO, &match_vector, weight_vector)
+            };
+            assert_eq!(best(&weight_vector), tall_violet);
+
+            // NOTE: Hue wraps, so 0.05 is 0.05 from 0.1 and 0.15 from 0.9
+            weight_vector.e[ASSET_TAG_COLOR as usize] = 10.0;
+            assert_eq!(best(&weight_vector), short_red);
+        });
+    }
+
+    #[test]
+    fn broken_packs_are_rejected_and_broken_assets_fail_to_load() {
+        with_arena(64 * 1024, |arena| {
+            assert_eq!(
+                load_test_pack(arena, "missing.hha", 1 << 20).err(),
+                Some(LoadAssetsError::ReadFailed)
+            );
+            assert_eq!(
+                load_test_pack(arena, "header_only.hha", 1 << 20).err(),
+                Some(LoadAssetsError::Truncated)
+            );
+            assert_eq!(
+                load_test_pack(arena, "wrong_magic.hha", 1 << 20).err(),
+                Some(LoadAssetsError::Corrupt)
+            );
+
+            // NOTE: The font's data is last, so only it is cut short
+            let mut assets = load_test_pack(arena, "truncated.hha", 1 << 20).unwrap();
+            let font = first_font(&assets, ASSET_TYPE_DEBUG_FONT);
+            let backdrop = first_bitmap(&assets, ASSET_TYPE_BACKDROP);
+            get_font(&mut assets, font);
+            get_bitmap(&mut assets, backdrop);
+            update_assets(&mut assets, &silent_audio(), None);
+            assert!(get_font(&mut assets, font).is_none());
+            assert!(get_bitmap(&mut assets, backdrop).is_some());
+
+            let mut assets = load_test_pack(arena, "broken_bitmap.hha", 1 << 20).unwrap();
+            let shadow = first_bitmap(&assets, ASSET_TYPE_SHADOW);
+            assert_eq!(shadow, BitmapId(1));
+            get_bitmap(&mut assets, shadow);
+            update_assets(&mut assets, &silent_audio(), None);
+            assert!(get_bitmap(&mut assets, shadow).is_none());
+            let stats = get_asset_load_stats(&assets);
+            assert_eq!((stats.loaded, stats.failed), (0, 1));
+        });
+    }
+
+    // NOTE: One pixel, which is all every test bitmap decodes to
+    const PIXEL_SIZE: usize = 4;
+
+    #[test]
+    fn only_the_decoded_size_counts_against_the_budget() {
+        with_arena(64 * 1024, |arena| {
+            let mut assets = load_test_pack(arena, "test.hha", 1 << 20).unwrap();
+            let backdrop = first_bitmap(&assets, ASSET_TYPE_BACKDROP);
+            let bloop = first_sound(&assets, ASSET_TYPE_BLOOP);
+
+            get_bitmap(&mut assets, backdrop);
+            prefetch_sound(&mut assets, bloop);
+            update_assets(&mut assets, &silent_audio(), None);
+
+            // NOTE: The bloop is two 16 bit samples
+            let stats = get_asset_load_stats(&assets);
+            assert_eq!(stats.loaded, 2);
+            assert_eq!(stats.memory_used, PIXEL_SIZE + 2 * 2);
+        });
+    }
+
+    #[test]
+    fn least_recently_used_assets_are_evicted_over_budget() {
+        with_arena(64 * 1024, |arena| {
+            // NOTE: Queued bitmaps count as their file size, so there's room
+            // to queue one more next to one loaded bitmap but not two
+            let budget = PIXEL_SIZE + pixel_bmp(0, 0, 0).len();
+            let mut assets = load_test_pack(arena, "test.hha", budget).unwrap();
+            let audio = silent_audio();
+            let backdrop = first_bitmap(&assets, ASSET_TYPE_BACKDROP);
+            let (right, front) = (hero_head(&assets, 0.0), hero_head(&assets, 3.0));
+
+            get_bitmap(&mut assets, backdrop);
+            update_assets(&mut assets, &audio, None);
+            get_bitmap(&mut assets, right);
+            update_assets(&mut assets, &audio, None);
+            assert!(get_bitmap(&mut assets, right).is_some());
+            get_bitmap(&mut assets, front);
+            update_assets(&mut assets, &audio, None);
+
+            let stats = get_asset_load_stats(&assets);
+            assert_eq!((stats.loaded, stats.unloaded), (2, 3));
+            assert_eq!(stats.memory_used, 2 * PIXEL_SIZE);
+            assert!(get_bitmap(&mut assets, right).is_some());
+            assert!(get_bitmap(&mut assets, front).is_some());
+            assert!(get_bitmap(&mut assets, backdrop).is_none());
+        });
+    }
+
+    #[test]
+    fn sounds_still_playing_are_not_evicted() {
+        with_arena(64 * 1024, |arena| {
+            // NOTE: Room to queue either one, but not the backdrop next to
+            // the loaded bloop
+            let budget = wav_file(22050, 1, 16, &[1, 0, 2, 0]).len();
+            let mut assets = load_test_pack(arena, "test.hha", budget).unwrap();
+            let mut audio = silent_audio();
+            let bloop = first_sound(&assets, ASSET_TYPE_BLOOP);
+            let backdrop = first_bitmap(&assets, ASSET_TYPE_BACKDROP);
+
+            prefetch_sound(&mut assets, bloop);
+            update_assets(&mut assets, &audio, None);
+            let sound = get_sound(&mut assets, bloop).unwrap();
+            play_sound(&mut audio, &sound, 1.0, 0.0, true).unwrap();
+            update_assets(&mut assets, &audio, None);
+
+            get_bitmap(&mut assets, backdrop);
+            update_assets(&mut assets, &audio, None);
+            assert!(get_bitmap(&mut assets, backdrop).is_none());
+
+            // NOTE: Once it stops, the backdrop that was waiting gets its room
+            update_assets(&mut assets, &silent_audio(), None);
+            assert!(get_bitmap(&mut assets, backdrop).is_some());
+            assert!(get_sound(&mut assets, bloop).is_none());
+        });
+    }
+
+    #[test]
+    fn only_a_few_sources_are_checked_a_frame() {
+        with_arena(64 * 1024, |arena| {
+            let mut assets = load_test_pack(arena, "shadows.hha", 1 << 20).unwrap();
+            let thread = ThreadContext { placeholder: 0 };
+            let checks = || SHADOW_SOURCE_CHECKS.load(Ordering::Relaxed);
+            for expected in [RELOAD_CHECKS_PER_FRAME, 2 * RELOAD_CHECKS_PER_FRAME] {
+                reload_changed_assets(
+                    &mut assets,
+                    &silent_audio(),
+                    &thread,
+                    test_file_write_time,
+                    read_test_file,
+                    log_to_test_log,
+                );
+                assert_eq!(checks(), expected);
+            }
+            assert_eq!(assets.reload_cursor, RELOAD_CHECKS_PER_FRAME - 1);
+        });
+    }
+
+    #[test]
+    fn changed_sources_are_swapped_in_and_broken_ones_logged() {
+        with_arena(64 * 1024, |arena| {
+            // NOTE: Room for one tiny bitmap at a time
+            let budget = pixel_bmp(0, 0, 0).len();
+            let mut assets = load_test_pack(arena, "test.hha", budget).unwrap();
+            let audio = silent_audio();
+            let thread = ThreadContext { placeholder: 0 };
+            let reload = |assets: &mut Assets| {
+                reload_changed_assets(
+                    assets,
+                    &audio,
+                    &thread,
+                    test_file_write_time,
+                    read_test_file,
+                    log_to_test_log,
+                );
+            };
+            let backdrop = first_bitmap(&assets, ASSET_TYPE_BACKDROP);
+            let backdrop_row = |assets: &mut Assets| {
+                bitmap_row(&get_bitmap(assets, backdrop).unwrap(), 0).to_vec()
+            };
+            get_bitmap(&mut assets, backdrop);
+            update_assets(&mut assets, &audio, None);
+
+            // NOTE: Still the file the pack was built from
+            BACKDROP_SOURCE_VERSION.store(1, Ordering::Relaxed);
+            reload(&mut assets);
+            assert_eq!(backdrop_row(&mut assets), [0xFFFF0000]);
+
+            BACKDROP_SOURCE_VERSION.store(2, Ordering::Relaxed);
+            reload(&mut assets);
+            assert_eq!(backdrop_row(&mut assets), [0xFF00FF00]);
+            update_assets(&mut assets, &audio, None);
+
+            // NOTE: A game started after the edit, with the pack not rebuilt
+            // yet, picks the edit up the first time it loads the asset, and
+            // not before it's asked for
+            let mut relaunched = load_test_pack(arena, "test.hha", budget).unwrap();
+            reload(&mut relaunched);
+            assert_eq!(get_asset_load_stats(&relaunched).loaded, 0);
+            get_bitmap(&mut relaunched, backdrop);
+            update_assets(&mut relaunched, &audio, None);
+            assert_eq!(backdrop_row(&mut relaunched), [0xFF00FF00]);
+
+            // NOTE: Pushed out by something else and loaded again, it comes
+            // from the source rather than the pack
+            let right = hero_head(&assets, 0.0);
+            get_bitmap(&mut assets, right);
+            update_assets(&mut assets, &audio, None);
+            assert!(get_bitmap(&mut assets, backdrop).is_none());
+            update_assets(&mut assets, &audio, None);
+            assert_eq!(backdrop_row(&mut assets), [0xFF00FF00]);
+
+            BACKDROP_SOURCE_VERSION.store(3, Ordering::Relaxed);
+            reload(&mut assets);
+            assert_eq!(backdrop_row(&mut assets), [0xFF00FF00]);
+            let log = RELOAD_LOG.lock().unwrap();
+            assert_eq!(log.len(), 1);
+            assert!(log[0].contains("art/backdrop.bmp"));
+        });
+    }
+}
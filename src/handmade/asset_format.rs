@@ -0,0 +1,48 @@
+// NOTE: The asset pack, shared with the build_assets tool so it has to stay
+// std only. Everything is little-endian:
+//
+//   header  magic, version, tag count, asset type count, asset count,
+//           source paths size (u32s)
+//   tags    tag id (u32), value (f32); each asset owns a run of these
+//   types   first asset, one past the last asset (u32s), for every asset
+//           type id from zero on, so the id indexes the table directly
+//   assets  data offset (u64), data size, first tag, one past the last tag,
+//           kind (u32s), align x, align y (i32s, bitmaps only), source path
+//           offset into the source paths, source path length (u32s), source
+//           write time (u64, nanoseconds since the Unix epoch like the
+//           platform layer reports them, zero if unknown); grouped by type,
+//           with asset zero a placeholder so zero never names an asset
+//   source  the UTF-8 path of the file each BMP, PNG or WAV was built from,
+//   paths   as build_assets was given it, for reloading it when it changes;
+//           empty for baked fonts
+//   data    every asset's file in the form the game's loaders read: a BMP or
+//           PNG, a WAV, or a baked font
+
+pub const ASSET_PACK_MAGIC: [u8; 4] = *b"HHAP";
+pub const ASSET_PACK_VERSION: u32 = 3;
+
+pub const ASSET_PACK_HEADER_SIZE: usize = 24;
+pub const ASSET_PACK_TAG_SIZE: usize = 8;
+pub const ASSET_PACK_TYPE_SIZE: usize = 8;
+pub const ASSET_PACK_ASSET_SIZE: usize = 48;
+
+pub const ASSET_KIND_BITMAP: u32 = 1;
+pub const ASSET_KIND_SOUND: u32 = 2;
+pub const ASSET_KIND_FONT: u32 = 3;
+
+// NOTE: Zero is no type, no asset is ever filed under it
+pub const ASSET_TYPE_BACKDROP: u32 = 1;
+pub const ASSET_TYPE_SHADOW: u32 = 2;
+pub const ASSET_TYPE_HERO_HEAD: u32 = 3;
+pub const ASSET_TYPE_HERO_CAPE: u32 = 4;
+pub const ASSET_TYPE_HERO_TORSO: u32 = 5;
+pub const ASSET_TYPE_BLOOP: u32 = 6;
+pub const ASSET_TYPE_DEBUG_FONT: u32 = 7;
+pub const ASSET_TYPE_COUNT: usize = 8;
+
+// NOTE: Facing direction is one of the game's four facings, 0 right going
+// counterclockwise to 3 front, and wraps around; height is in meters; color
+// is a hue from 0 to 1, and wraps around too
+pub const ASSET_TAG_FACING_DIRECTION: u32 = 1;
+pub const ASSET_TAG_HEIGHT: u32 = 2;
+pub const ASSET_TAG_COLOR: u32 = 3;
@@ -0,0 +1,938 @@
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+use std::f32::consts::TAU;
+use std::slice;
+
+use super::memory::*;
+use super::simd::{detect_simd_level, SimdLevel};
+use super::sound::*;
+use super::{subtract, GameSoundOutputBuffer, World, WorldPosition};
+
+// NOTE: The output is always stereo, left then right
+pub const AUDIO_CHANNEL_COUNT: usize = 2;
+
+pub const MAX_PLAYING_SOUND_COUNT: usize = 64;
+
+// NOTE: In meters from the listener; full volume up close, then falling off
+// on a square curve until nothing is left
+const SOUND_FULL_VOLUME_DISTANCE: f32 = 3.0;
+const SOUND_SILENT_DISTANCE: f32 = 25.0;
+// NOTE: Horizontal offset at which a sound is all the way on one side
+const SOUND_FULL_PAN_DISTANCE: f32 = 10.0;
+// NOTE: Sounds on another floor come through the ceiling quieter and with
+// the highs taken off
+const SOUND_OTHER_FLOOR_VOLUME: f32 = 0.5;
+const SOUND_MUFFLE_CUTOFF_HZ: f32 = 600.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlayingSoundId {
+    index: u32,
+    generation: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct PlayingSound {
+    active: bool,
+    // NOTE: Bumped every time the slot is reused so stale ids do nothing
+    generation: u32,
+
+    sound: LoadedSound,
+    // NOTE: Picked up where sound ends instead of stopping, for sounds that
+    // arrive a piece at a time
+    next_sound: LoadedSound,
+    // NOTE: Fractional position in the sound's own samples, advanced by
+    // d_sample per sample at the sound's rate, so 2 plays an octave up
+    samples_played: f32,
+    d_sample: f32,
+    looping: bool,
+
+    // NOTE: Per output channel, the fade moves current towards target by
+    // d_volume every second
+    current_volume: [f32; AUDIO_CHANNEL_COUNT],
+    d_current_volume: [f32; AUDIO_CHANNEL_COUNT],
+    target_volume: [f32; AUDIO_CHANNEL_COUNT],
+    stop_when_faded: bool,
+
+    // NOTE: Sounds played at a world position get their pan, falloff and
+    // muffling from where the listener is, on top of their own volume
+    positioned: bool,
+    p: WorldPosition,
+    spatial_volume: [f32; AUDIO_CHANNEL_COUNT],
+    muffled: bool,
+    // NOTE: Last output of the low-pass per channel, carried across buffers
+    filter_state: [f32; AUDIO_CHANNEL_COUNT],
+}
+
+// NOTE: Lives in the game state, so all zeroes has to mean nothing playing
+#[derive(Clone, Copy)]
+pub struct AudioState {
+    playing_sounds: [PlayingSound; MAX_PLAYING_SOUND_COUNT],
+    listener_p: WorldPosition,
+}
+
+// NOTE: Pan runs from -1 (left only) to 1 (right only); the near side
+// stays at full volume so centered sounds aren't quieter
+fn channel_volumes(volume: f32, pan: f32) -> [f32; AUDIO_CHANNEL_COUNT] {
+    let pan = pan.clamp(-1.0, 1.0);
+    [volume * (1.0 - pan).min(1.0), volume * (1.0 + pan).min(1.0)]
+}
+
+const NO_SOUND: LoadedSound = LoadedSound {
+    samples_per_second: 0,
+    sample_count: 0,
+    channel_count: 0,
+    samples: [std::ptr::null_mut(); MAX_SOUND_CHANNEL_COUNT],
+};
+
+fn get_playing_sound(audio: &mut AudioState, id: PlayingSoundId) -> Option<&mut PlayingSound> {
+    let playing = audio.playing_sounds.get_mut(id.index as usize)?;
+    if playing.active && playing.generation == id.generation {
+        Some(playing)
+    } else {
+        None
+    }
+}
+
+// NOTE: Returns None when the sound isn't loaded or every slot is taken
+pub fn play_sound(
+    audio: &mut AudioState,
+    sound: &LoadedSound,
+    volume: f32,
+    pan: f32,
+    looping: bool,
+) -> Option<PlayingSoundId> {
+    if !sound_is_loaded(sound) {
+        return None;
+    }
+
+    let index = audio
+        .playing_sounds
+        .iter()
+        .position(|playing| !playing.active)?;
+    let listener_p = audio.listener_p;
+    let playing = &mut audio.playing_sounds[index];
+
+    let volumes = channel_volumes(volume, pan);
+    *playing = PlayingSound {
+        active: true,
+        generation: playing.generation.wrapping_add(1),
+
+        sound: *sound,
+        next_sound: NO_SOUND,
+        samples_played: 0.0,
+        d_sample: 1.0,
+        looping,
+
+        current_volume: volumes,
+        d_current_volume: [0.0; AUDIO_CHANNEL_COUNT],
+        target_volume: volumes,
+        stop_when_faded: false,
+
+        positioned: false,
+        p: listener_p,
+        spatial_volume: [1.0; AUDIO_CHANNEL_COUNT],
+        muffled: false,
+        filter_state: [0.0; AUDIO_CHANNEL_COUNT],
+    };
+
+    Some(PlayingSoundId {
+        index: index as u32,
+        generation: playing.generation,
+    })
+}
+
+fn set_spatial_volume(world: &World, listener_p: WorldPosition, playing: &mut PlayingSound) {
+    let d = subtract(world, playing.p, listener_p);
+    let distance = (d.x * d.x + d.y * d.y).sqrt();
+
+    let falloff = ((SOUND_SILENT_DISTANCE - distance)
+        / (SOUND_SILENT_DISTANCE - SOUND_FULL_VOLUME_DISTANCE))
+        .clamp(0.0, 1.0);
+    let mut gain = falloff * falloff;
+
+    playing.muffled = playing.p.abs_tile_z != listener_p.abs_tile_z;
+    if playing.muffled {
+        gain *= SOUND_OTHER_FLOOR_VOLUME;
+    }
+
+    playing.spatial_volume = channel_volumes(gain, d.x / SOUND_FULL_PAN_DISTANCE);
+}
+
+// NOTE: Volume is the sound's own, before any falloff; the pan comes from
+// where it is relative to the listener
+pub fn play_sound_at(
+    audio: &mut AudioState,
+    world: &World,
+    sound: &LoadedSound,
+    p: WorldPosition,
+    volume: f32,
+    looping: bool,
+) -> Option<PlayingSoundId> {
+    let id = play_sound(audio, sound, volume, 0.0, looping)?;
+
+    let listener_p = audio.listener_p;
+    let playing = get_playing_sound(audio, id)?;
+    playing.positioned = true;
+    playing.p = p;
+    set_spatial_volume(world, listener_p, playing);
+
+    Some(id)
+}
+
+// NOTE: Called once a frame with wherever the camera ended up, so sounds
+// already playing pan and fade as it moves past them
+// TODO: Ramp the spatial volume across the buffer if camera snaps ever
+// make it click
+pub fn update_audio_listener(audio: &mut AudioState, world: &World, listener_p: WorldPosition) {
+    audio.listener_p = listener_p;
+    for playing in audio.playing_sounds.iter_mut() {
+        if playing.active && playing.positioned {
+            set_spatial_volume(world, listener_p, playing);
+        }
+    }
+}
+
+// NOTE: A fade_duration of zero snaps to the new volume right away
+pub fn change_volume(
+    audio: &mut AudioState,
+    id: PlayingSoundId,
+    fade_duration: f32,
+    volume: f32,
+    pan: f32,
+) {
+    let Some(playing) = get_playing_sound(audio, id) else {
+        return;
+    };
+
+    playing.target_volume = channel_volumes(volume, pan);
+    for channel in 0..AUDIO_CHANNEL_COUNT {
+        if fade_duration <= 0.0 {
+            playing.current_volume[channel] = playing.target_volume[channel];
+            playing.d_current_volume[channel] = 0.0;
+        } else {
+            playing.d_current_volume[channel] =
+                (playing.target_volume[channel] - playing.current_volume[channel]) / fade_duration;
+        }
+    }
+}
+
+pub fn change_pitch(audio: &mut AudioState, id: PlayingSoundId, d_sample: f32) {
+    if let Some(playing) = get_playing_sound(audio, id) {
+        playing.d_sample = d_sample.max(0.0);
+    }
+}
+
+pub fn fade_in_sound(
+    audio: &mut AudioState,
+    sound: &LoadedSound,
+    fade_duration: f32,
+    volume: f32,
+    pan: f32,
+    looping: bool,
+) -> Option<PlayingSoundId> {
+    let id = play_sound(audio, sound, 0.0, pan, looping)?;
+    change_volume(audio, id, fade_duration, volume, pan);
+
+    Some(id)
+}
+
+// NOTE: Asking again while a fade out is under way leaves it alone, so
+// callers can keep asking every frame; a duration of zero stops right away
+pub fn fade_out_sound(audio: &mut AudioState, id: PlayingSoundId, fade_duration: f32) {
+    if fade_duration <= 0.0 {
+        if let Some(playing) = get_playing_sound(audio, id) {
+            playing.active = false;
+        }
+        return;
+    }
+
+    if get_playing_sound(audio, id).is_some_and(|playing| !playing.stop_when_faded) {
+        change_volume(audio, id, fade_duration, 0.0, 0.0);
+        if let Some(playing) = get_playing_sound(audio, id) {
+            playing.stop_when_faded = true;
+        }
+    }
+}
+
+pub fn is_sound_playing(audio: &mut AudioState, id: PlayingSoundId) -> bool {
+    get_playing_sound(audio, id).is_some()
+}
+
+// NOTE: The queued sound has to match the playing one's channels and rate,
+// and stay put until it starts playing and the one after it gets queued
+pub fn queue_next_sound(audio: &mut AudioState, id: PlayingSoundId, sound: &LoadedSound) {
+    if let Some(playing) = get_playing_sound(audio, id) {
+        playing.next_sound = *sound;
+    }
+}
+
+// NOTE: True once the queued sound has taken over, so another can go in
+pub fn needs_next_sound(audio: &mut AudioState, id: PlayingSoundId) -> bool {
+    get_playing_sound(audio, id).is_some_and(|playing| !sound_is_loaded(&playing.next_sound))
+}
+
+// NOTE: Whether any voice still reads the sound's samples, playing or queued,
+// so whoever owns them knows not to free them yet
+pub fn is_sound_in_use(audio: &AudioState, sound: &LoadedSound) -> bool {
+    let samples = sound.samples[0];
+    !samples.is_null()
+        && audio.playing_sounds.iter().any(|playing| {
+            playing.active
+                && (playing.sound.samples[0] == samples || playing.next_sound.samples[0] == samples)
+        })
+}
+
+// NOTE: One run of output samples for one voice, over which nothing but
+// the position and volume changes, and both change linearly. Sample j of the
+// run reads the source at position + step*j with volume + d_volume*j; every
+// level computes exactly that, so they all mix identical samples.
+struct MixRun<'a> {
+    source: [&'a [i16]; AUDIO_CHANNEL_COUNT],
+    looping: bool,
+    position: f32,
+    step: f32,
+    volume: [f32; AUDIO_CHANNEL_COUNT],
+    d_volume: [f32; AUDIO_CHANNEL_COUNT],
+}
+
+// NOTE: Linear interpolation towards the next sample, which wraps around
+// for looping sounds and holds the last one otherwise
+fn interpolated_sample(source: &[i16], looping: bool, position: f32) -> f32 {
+    let last = source.len() - 1;
+    let index = (position as usize).min(last);
+    let fraction = position - index as f32;
+
+    let s0 = source[index] as f32;
+    let s1 = if index < last {
+        source[index + 1] as f32
+    } else if looping {
+        source[0] as f32
+    } else {
+        s0
+    };
+
+    s0 + fraction * (s1 - s0)
+}
+
+fn mix_run_scalar(run: &MixRun, dest: &mut [&mut [f32]; AUDIO_CHANNEL_COUNT], from: usize) {
+    for j in from..dest[0].len() {
+        let position = run.position + run.step * j as f32;
+        for (channel, dest) in dest.iter_mut().enumerate() {
+            let value = interpolated_sample(run.source[channel], run.looping, position);
+            let volume = run.volume[channel] + run.d_volume[channel] * j as f32;
+            dest[j] += volume * value;
+        }
+    }
+}
+
+// NOTE: One-pole low-pass on the source before the volume; it depends on
+// the previous output, so it stays scalar at every level
+fn mix_run_muffled(
+    run: &MixRun,
+    dest: &mut [&mut [f32]; AUDIO_CHANNEL_COUNT],
+    filter_state: &mut [f32; AUDIO_CHANNEL_COUNT],
+    filter_coefficient: f32,
+) {
+    for j in 0..dest[0].len() {
+        let position = run.position + run.step * j as f32;
+        for (channel, dest) in dest.iter_mut().enumerate() {
+            let value = interpolated_sample(run.source[channel], run.looping, position);
+            let state = &mut filter_state[channel];
+            *state += filter_coefficient * (value - *state);
+
+            let volume = run.volume[channel] + run.d_volume[channel] * j as f32;
+            dest[j] += volume * *state;
+        }
+    }
+}
+
+fn mix_run(level: SimdLevel, run: &MixRun, dest: &mut [&mut [f32]; AUDIO_CHANNEL_COUNT]) {
+    let mut done = 0;
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    match level {
+        SimdLevel::Avx2 => done = unsafe { mix_run_avx2(run, dest) },
+        SimdLevel::Sse2 => done = unsafe { mix_run_sse2(run, dest) },
+        SimdLevel::Scalar => {}
+    }
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+    let _ = level;
+
+    mix_run_scalar(run, dest, done);
+}
+
+// NOTE: The wide paths stop at the first group that would read the sample
+// past the end, the scalar loop handles the wrap or the hold from there
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn group_stays_inside(run: &MixRun, last_j: usize) -> bool {
+    let position = run.position + run.step * last_j as f32;
+    (position as usize) + 1 < run.source[0].len()
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "sse2")]
+unsafe fn mix_run_sse2(run: &MixRun, dest: &mut [&mut [f32]; AUDIO_CHANNEL_COUNT]) -> usize {
+    let count = dest[0].len();
+    let position_4x = _mm_set1_ps(run.position);
+    let step_4x = _mm_set1_ps(run.step);
+    let lane_offsets = _mm_setr_epi32(0, 1, 2, 3);
+
+    let mut j = 0;
+    while j + 4 <= count && group_stays_inside(run, j + 3) {
+        let j_4x = _mm_cvtepi32_ps(_mm_add_epi32(_mm_set1_epi32(j as i32), lane_offsets));
+        let position = _mm_add_ps(position_4x, _mm_mul_ps(step_4x, j_4x));
+        let index = _mm_cvttps_epi32(position);
+        let fraction = _mm_sub_ps(position, _mm_cvtepi32_ps(index));
+
+        let mut indices = [0i32; 4];
+        _mm_storeu_si128(indices.as_mut_ptr() as *mut __m128i, index);
+
+        for (channel, dest) in dest.iter_mut().enumerate() {
+            let source = run.source[channel];
+            let mut s0 = [0.0f32; 4];
+            let mut s1 = [0.0f32; 4];
+            for lane in 0..4 {
+                let index = indices[lane] as usize;
+                s0[lane] = *source.get_unchecked(index) as f32;
+                s1[lane] = *source.get_unchecked(index + 1) as f32;
+            }
+            let s0 = _mm_loadu_ps(s0.as_ptr());
+            let s1 = _mm_loadu_ps(s1.as_ptr());
+            let value = _mm_add_ps(s0, _mm_mul_ps(fraction, _mm_sub_ps(s1, s0)));
+
+            let volume = _mm_add_ps(
+                _mm_set1_ps(run.volume[channel]),
+                _mm_mul_ps(_mm_set1_ps(run.d_volume[channel]), j_4x),
+            );
+
+            let dest_ptr = dest.as_mut_ptr().add(j);
+            let mixed = _mm_add_ps(_mm_loadu_ps(dest_ptr), _mm_mul_ps(volume, value));
+            _mm_storeu_ps(dest_ptr, mixed);
+        }
+
+        j += 4;
+    }
+
+    j
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn mix_run_avx2(run: &MixRun, dest: &mut [&mut [f32]; AUDIO_CHANNEL_COUNT]) -> usize {
+    let count = dest[0].len();
+    let position_8x = _mm256_set1_ps(run.position);
+    let step_8x = _mm256_set1_ps(run.step);
+    let lane_offsets = _mm256_setr_epi32(0, 1, 2, 3, 4, 5, 6, 7);
+
+    let mut j = 0;
+    while j + 8 <= count && group_stays_inside(run, j + 7) {
+        let j_8x = _mm256_cvtepi32_ps(_mm256_add_epi32(_mm256_set1_epi32(j as i32), lane_offsets));
+        let position = _mm256_add_ps(position_8x, _mm256_mul_ps(step_8x, j_8x));
+        let index = _mm256_cvttps_epi32(position);
+        let fraction = _mm256_sub_ps(position, _mm256_cvtepi32_ps(index));
+
+        for (channel, dest) in dest.iter_mut().enumerate() {
+            // NOTE: Samples are 16 bits, so gather the 32 bits at each index
+            // and keep the low half for s0 and the high half for s1
+            let base = run.source[channel].as_ptr() as *const i32;
+            let pairs = _mm256_i32gather_epi32::<2>(base, index);
+            let s0 = _mm256_cvtepi32_ps(_mm256_srai_epi32::<16>(_mm256_slli_epi32::<16>(pairs)));
+            let s1 = _mm256_cvtepi32_ps(_mm256_srai_epi32::<16>(pairs));
+            let value = _mm256_add_ps(s0, _mm256_mul_ps(fraction, _mm256_sub_ps(s1, s0)));
+
+            let volume = _mm256_add_ps(
+                _mm256_set1_ps(run.volume[channel]),
+                _mm256_mul_ps(_mm256_set1_ps(run.d_volume[channel]), j_8x),
+            );
+
+            let dest_ptr = dest.as_mut_ptr().add(j);
+            let mixed = _mm256_add_ps(_mm256_loadu_ps(dest_ptr), _mm256_mul_ps(volume, value));
+            _mm256_storeu_ps(dest_ptr, mixed);
+        }
+
+        j += 8;
+    }
+
+    j
+}
+
+// NOTE: Splits the buffer into runs that end wherever a fade lands on its
+// target or the voice runs off the end of its sound
+fn mix_playing_sound(
+    level: SimdLevel,
+    playing: &mut PlayingSound,
+    mix: &mut [&mut [f32]; AUDIO_CHANNEL_COUNT],
+    output_samples_per_second: u32,
+) {
+    let seconds_per_sample = 1.0 / output_samples_per_second as f32;
+    let filter_coefficient =
+        1.0 - (-TAU * SOUND_MUFFLE_CUTOFF_HZ / output_samples_per_second as f32).exp();
+
+    let total = mix[0].len();
+    let mut at = 0;
+    while at < total {
+        // NOTE: Per run, since a queued sound can take over partway through
+        let sound = playing.sound;
+        let sample_count = sound.sample_count as f32;
+        let step =
+            playing.d_sample * sound.samples_per_second as f32 / output_samples_per_second as f32;
+
+        // NOTE: Mono sounds feed both sides
+        let source = [
+            sound_channel(&sound, 0),
+            sound_channel(&sound, sound.channel_count as usize - 1),
+        ];
+
+        let mut run_count = total - at;
+
+        let mut d_volume = [0.0; AUDIO_CHANNEL_COUNT];
+        let mut fade_count = [usize::MAX; AUDIO_CHANNEL_COUNT];
+        for channel in 0..AUDIO_CHANNEL_COUNT {
+            d_volume[channel] = playing.d_current_volume[channel] * seconds_per_sample;
+            if d_volume[channel] != 0.0 {
+                let remaining = (playing.target_volume[channel] - playing.current_volume[channel])
+                    / d_volume[channel];
+                fade_count[channel] = (remaining.ceil() as usize).max(1);
+                run_count = run_count.min(fade_count[channel]);
+            }
+        }
+
+        // NOTE: Samples until the read position passes the end of the sound
+        let end_count = if step > 0.0 {
+            (((sample_count - playing.samples_played) / step).ceil() as usize).max(1)
+        } else {
+            usize::MAX
+        };
+        run_count = run_count.min(end_count);
+
+        let mut run = MixRun {
+            source,
+            looping: playing.looping,
+            position: playing.samples_played,
+            step,
+            volume: playing.current_volume,
+            d_volume,
+        };
+        for channel in 0..AUDIO_CHANNEL_COUNT {
+            run.volume[channel] *= playing.spatial_volume[channel];
+            run.d_volume[channel] *= playing.spatial_volume[channel];
+        }
+        let [left, right] = mix;
+        let mut dest = [
+            &mut left[at..at + run_count],
+            &mut right[at..at + run_count],
+        ];
+        if playing.muffled {
+            mix_run_muffled(
+                &run,
+                &mut dest,
+                &mut playing.filter_state,
+                filter_coefficient,
+            );
+        } else {
+            mix_run(level, &run, &mut dest);
+        }
+        at += run_count;
+
+        for channel in 0..AUDIO_CHANNEL_COUNT {
+            if fade_count[channel] == run_count {
+                // NOTE: Land exactly on the target rather than a float step off it
+                playing.current_volume[channel] = playing.target_volume[channel];
+                playing.d_current_volume[channel] = 0.0;
+            } else {
+                playing.current_volume[channel] += d_volume[channel] * run_count as f32;
+            }
+        }
+
+        playing.samples_played += step * run_count as f32;
+        if playing.samples_played >= sample_count {
+            if playing.looping {
+                playing.samples_played %= sample_count;
+            } else if sound_is_loaded(&playing.next_sound) {
+                // NOTE: Carries the fraction over, so the queued sound starts
+                // exactly where this one left off
+                playing.samples_played -= sample_count;
+                playing.sound = playing.next_sound;
+                playing.next_sound = NO_SOUND;
+            } else {
+                playing.active = false;
+                return;
+            }
+        }
+
+        if playing.stop_when_faded && playing.current_volume.iter().all(|&volume| volume <= 0.0) {
+            playing.active = false;
+            return;
+        }
+    }
+}
+
+fn mix_playing_sounds(
+    level: SimdLevel,
+    audio: &mut AudioState,
+    mix: &mut [&mut [f32]; AUDIO_CHANNEL_COUNT],
+    output_samples_per_second: u32,
+) {
+    for playing in audio.playing_sounds.iter_mut() {
+        if playing.active {
+            mix_playing_sound(level, playing, mix, output_samples_per_second);
+        }
+    }
+}
+
+// NOTE: Mixes in float so loud overlaps only clip once, on the way out
+pub fn output_playing_sounds(
+    audio: &mut AudioState,
+    sound_buffer: &mut GameSoundOutputBuffer,
+    temp_arena: &mut MemoryArena,
+) {
+    let mix_memory = begin_temporary_memory(temp_arena);
+
+    let sample_count = sound_buffer.sample_count as usize;
+    let (Some(left), Some(right)) = (
+        push_array::<f32>(temp_arena, sample_count),
+        push_array::<f32>(temp_arena, sample_count),
+    ) else {
+        // NOTE: Out of transient memory, better silence than garbage
+        sound_buffer.samples.fill(0);
+        end_temporary_memory(temp_arena, mix_memory);
+        return;
+    };
+    let mut mix = unsafe {
+        [
+            slice::from_raw_parts_mut(left, sample_count),
+            slice::from_raw_parts_mut(right, sample_count),
+        ]
+    };
+
+    mix_playing_sounds(
+        detect_simd_level(),
+        audio,
+        &mut mix,
+        sound_buffer.samples_per_second as u32,
+    );
+
+    for (sample_index, frame) in sound_buffer
+        .samples
+        .chunks_exact_mut(AUDIO_CHANNEL_COUNT)
+        .take(sample_count)
+        .enumerate()
+    {
+        for (channel, sample) in frame.iter_mut().enumerate() {
+            let value = mix[channel][sample_index];
+            *sample = value.round().clamp(i16::MIN as f32, i16::MAX as f32) as i16;
+        }
+    }
+
+    end_temporary_memory(temp_arena, mix_memory);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::math::v2;
+    use super::super::tests::{test_world, with_arena, Series};
+    use super::super::{centered_tile_point, offset};
+    use super::*;
+
+    struct TestSound {
+        samples_per_second: u32,
+        channels: Vec<Vec<i16>>,
+    }
+
+    fn test_sound(samples_per_second: u32, channels: &[&[i16]]) -> TestSound {
+        TestSound {
+            samples_per_second,
+            channels: channels.iter().map(|channel| channel.to_vec()).collect(),
+        }
+    }
+
+    fn loaded(sound: &mut TestSound) -> LoadedSound {
+        let mut samples = [std::ptr::null_mut(); MAX_SOUND_CHANNEL_COUNT];
+        for (dest, channel) in samples.iter_mut().zip(sound.channels.iter_mut()) {
+            *dest = channel.as_mut_ptr();
+        }
+
+        LoadedSound {
+            samples_per_second: sound.samples_per_second,
+            sample_count: sound.channels[0].len() as u32,
+            channel_count: sound.channels.len() as u32,
+            samples,
+        }
+    }
+
+    fn new_audio() -> Box<AudioState> {
+        // NOTE: Zeroed the way the game state is
+        unsafe { Box::new(std::mem::zeroed()) }
+    }
+
+    // NOTE: Mixes sample_count frames at the given rate, interleaved
+    fn mix(audio: &mut AudioState, sample_count: usize, samples_per_second: i32) -> Vec<i16> {
+        let mut samples = vec![0i16; AUDIO_CHANNEL_COUNT * sample_count];
+        let mut buffer = GameSoundOutputBuffer {
+            samples_per_second,
+            sample_count: sample_count as i32,
+            samples: &mut samples,
+        };
+        with_arena(64 * 1024, |arena| {
+            output_playing_sounds(audio, &mut buffer, arena);
+            assert_eq!(arena.used, 0);
+        });
+
+        samples
+    }
+
+    #[test]
+    fn volume_and_pan_scale_each_side() {
+        let mut mono = test_sound(48000, &[&[1000, -2000]]);
+        let mut stereo = test_sound(48000, &[&[100, 100], &[-100, -100]]);
+        let mut audio = new_audio();
+
+        play_sound(&mut audio, &loaded(&mut mono), 0.5, 0.5, false).unwrap();
+        play_sound(&mut audio, &loaded(&mut stereo), 1.0, 0.0, false).unwrap();
+
+        // NOTE: Right of center, so the left side drops to half of the volume
+        assert_eq!(mix(&mut audio, 3, 48000), [350, 400, -400, -1100, 0, 0]);
+    }
+
+    #[test]
+    fn sounds_stop_at_the_end_unless_looping() {
+        let mut sound = test_sound(48000, &[&[1, 2, 3]]);
+        let mut audio = new_audio();
+
+        let once = play_sound(&mut audio, &loaded(&mut sound), 1.0, 0.0, false).unwrap();
+        let looped = play_sound(&mut audio, &loaded(&mut sound), 10.0, 0.0, true).unwrap();
+
+        let samples = mix(&mut audio, 4, 48000);
+        let left: Vec<i16> = samples.iter().step_by(2).copied().collect();
+        assert_eq!(left, [11, 22, 33, 10]);
+        assert!(!is_sound_playing(&mut audio, once));
+        assert!(is_sound_playing(&mut audio, looped));
+
+        // NOTE: The freed slot is reused, the old id stays dead
+        let again = play_sound(&mut audio, &loaded(&mut sound), 1.0, 0.0, false).unwrap();
+        assert_ne!(again, once);
+        fade_out_sound(&mut audio, once, 0.0);
+        assert!(is_sound_playing(&mut audio, again));
+        fade_out_sound(&mut audio, again, 0.0);
+        assert!(!is_sound_playing(&mut audio, again));
+    }
+
+    #[test]
+    fn fades_ramp_per_sample_and_fade_outs_stop() {
+        let mut sound = test_sound(4, &[&[10000; 8]]);
+        let mut audio = new_audio();
+
+        // NOTE: At 4 samples a second, a one second fade takes four samples
+        let id = fade_in_sound(&mut audio, &loaded(&mut sound), 1.0, 1.0, 0.0, true).unwrap();
+        let samples = mix(&mut audio, 6, 4);
+        let left: Vec<i16> = samples.iter().step_by(2).copied().collect();
+        assert_eq!(left, [0, 2500, 5000, 7500, 10000, 10000]);
+
+        fade_out_sound(&mut audio, id, 0.5);
+        fade_out_sound(&mut audio, id, 100.0);
+        let samples = mix(&mut audio, 4, 4);
+        let left: Vec<i16> = samples.iter().step_by(2).copied().collect();
+        assert_eq!(left, [10000, 5000, 0, 0]);
+        assert!(!is_sound_playing(&mut audio, id));
+    }
+
+    #[test]
+    fn loud_overlaps_clamp_instead_of_wrapping() {
+        let mut sound = test_sound(48000, &[&[30000, -30000]]);
+        let mut audio = new_audio();
+
+        play_sound(&mut audio, &loaded(&mut sound), 1.0, 0.0, false).unwrap();
+        play_sound(&mut audio, &loaded(&mut sound), 1.0, 0.0, false).unwrap();
+
+        assert_eq!(mix(&mut audio, 2, 48000), [32767, 32767, -32768, -32768]);
+    }
+
+    #[test]
+    fn pitch_and_source_rate_resample_with_interpolation() {
+        let mut full_rate = test_sound(48000, &[&[0, 100, 200, 300]]);
+        let mut half_rate = test_sound(24000, &[&[0, 100, 200, 300]]);
+        let mut audio = new_audio();
+
+        // NOTE: Half speed and a half rate sound come out the same, holding
+        // the last sample where there is nothing left to interpolate to
+        let id = play_sound(&mut audio, &loaded(&mut full_rate), 1.0, -1.0, false).unwrap();
+        change_pitch(&mut audio, id, 0.5);
+        play_sound(&mut audio, &loaded(&mut half_rate), 1.0, 1.0, false).unwrap();
+
+        let samples = mix(&mut audio, 10, 48000);
+        let left: Vec<i16> = samples.iter().step_by(2).copied().collect();
+        let right: Vec<i16> = samples.iter().skip(1).step_by(2).copied().collect();
+        assert_eq!(left, [0, 50, 100, 150, 200, 250, 300, 300, 0, 0]);
+        assert_eq!(right, left);
+
+        // NOTE: Looping sounds interpolate from the last sample to the first
+        let mut looped = test_sound(48000, &[&[0, 100, 200, 300]]);
+        let id = play_sound(&mut audio, &loaded(&mut looped), 1.0, 0.0, true).unwrap();
+        change_pitch(&mut audio, id, 1.5);
+        let samples = mix(&mut audio, 7, 48000);
+        let left: Vec<i16> = samples.iter().step_by(2).copied().collect();
+        assert_eq!(left, [0, 150, 300, 50, 200, 150, 100]);
+    }
+
+    fn assert_near(samples: &[i16], expected: &[i16]) {
+        assert_eq!(samples.len(), expected.len());
+        for (&sample, &expected) in samples.iter().zip(expected) {
+            assert!((sample - expected).abs() <= 2, "{samples:?} {expected:?}");
+        }
+    }
+
+    #[test]
+    fn positioned_sounds_pan_and_fall_off_from_the_listener() {
+        let world = test_world();
+        let listener_p = centered_tile_point(5, 5);
+        let mut sound = test_sound(48000, &[&[10000; 4]]);
+        let sound = loaded(&mut sound);
+
+        // NOTE: Right on top, then 10m right, 10m left and too far away
+        let expected: [&[i16]; 4] = [&[10000, 10000], &[0, 4649], &[4649, 0], &[0, 0]];
+        for (x, expected) in [0.0, 10.0, -10.0, 30.0].into_iter().zip(expected) {
+            let mut audio = new_audio();
+            update_audio_listener(&mut audio, &world, listener_p);
+
+            let p = offset(&world, listener_p, v2(x, 0.0));
+            play_sound_at(&mut audio, &world, &sound, p, 1.0, false).unwrap();
+            assert_near(&mix(&mut audio, 1, 48000), expected);
+        }
+    }
+
+    #[test]
+    fn moving_the_listener_updates_playing_sounds() {
+        let world = test_world();
+        let listener_p = centered_tile_point(0, 0);
+        let mut sound = test_sound(48000, &[&[10000; 4]]);
+        let mut audio = new_audio();
+        update_audio_listener(&mut audio, &world, listener_p);
+
+        let p = offset(&world, listener_p, v2(-10.0, 0.0));
+        let id = play_sound_at(&mut audio, &world, &loaded(&mut sound), p, 1.0, true).unwrap();
+        assert_near(&mix(&mut audio, 1, 48000), &[4649, 0]);
+
+        update_audio_listener(&mut audio, &world, p);
+        assert_near(&mix(&mut audio, 1, 48000), &[10000, 10000]);
+
+        // NOTE: Sounds played without a position don't move with the listener
+        fade_out_sound(&mut audio, id, 0.0);
+        play_sound(&mut audio, &loaded(&mut sound), 1.0, 0.0, true).unwrap();
+        update_audio_listener(&mut audio, &world, centered_tile_point(100, 0));
+        assert_near(&mix(&mut audio, 1, 48000), &[10000, 10000]);
+    }
+
+    #[test]
+    fn sounds_on_other_floors_are_quieter_and_muffled() {
+        let world = test_world();
+        let listener_p = centered_tile_point(0, 0);
+        let below = WorldPosition {
+            abs_tile_z: -1,
+            ..listener_p
+        };
+
+        // NOTE: A steady level comes through at the floor volume once the
+        // filter settles, while a tone at the Nyquist rate mostly doesn't
+        let mut steady = test_sound(48000, &[&[10000; 4800]]);
+        let mut audio = new_audio();
+        update_audio_listener(&mut audio, &world, listener_p);
+        play_sound_at(&mut audio, &world, &loaded(&mut steady), below, 1.0, false).unwrap();
+        let samples = mix(&mut audio, 4800, 48000);
+        assert!(samples[1] < 1000);
+        assert_near(&samples[9598..], &[5000, 5000]);
+
+        let alternating: Vec<i16> = (0..4800)
+            .map(|i| if i % 2 == 0 { 10000 } else { -10000 })
+            .collect();
+        let mut tone = test_sound(48000, &[&alternating]);
+        let mut audio = new_audio();
+        update_audio_listener(&mut audio, &world, listener_p);
+        play_sound_at(&mut audio, &world, &loaded(&mut tone), below, 1.0, false).unwrap();
+        let samples = mix(&mut audio, 4800, 48000);
+        assert!(
+            samples[4800..].iter().all(|&s| s.abs() < 300),
+            "{samples:?}"
+        );
+    }
+
+    fn available_levels() -> Vec<SimdLevel> {
+        let mut levels = vec![SimdLevel::Scalar];
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            if is_x86_feature_detected!("sse2") {
+                levels.push(SimdLevel::Sse2);
+            }
+            if is_x86_feature_detected!("avx2") {
+                levels.push(SimdLevel::Avx2);
+            }
+        }
+        levels
+    }
+
+    #[test]
+    fn every_level_mixes_identical_samples() {
+        let mut series = Series(0xa0d1_0000_5eed_0042);
+
+        for _ in 0..200 {
+            let mut sounds: Vec<TestSound> = (0..4)
+                .map(|_| {
+                    let length = series.between(1, 40) as usize;
+                    let channels: Vec<Vec<i16>> = (0..series.between(1, 2))
+                        .map(|_| {
+                            (0..length)
+                                .map(|_| series.between(-32768, 32767) as i16)
+                                .collect()
+                        })
+                        .collect();
+                    let rates = [11025, 22050, 44100, 48000];
+                    TestSound {
+                        samples_per_second: rates[series.between(0, 3) as usize],
+                        channels,
+                    }
+                })
+                .collect();
+
+            let mut audio = new_audio();
+            for _ in 0..series.between(1, 12) {
+                let sound_index = series.between(0, 3) as usize;
+                let sound = loaded(&mut sounds[sound_index]);
+                let looping = series.between(0, 1) == 1;
+                let volume = 2.0 * series.unilateral();
+                let pan = series.bilateral();
+
+                let id = match series.between(0, 2) {
+                    0 => play_sound(&mut audio, &sound, volume, pan, looping),
+                    _ => {
+                        let fade = 0.001 * series.unilateral();
+                        fade_in_sound(&mut audio, &sound, fade, volume, pan, looping)
+                    }
+                }
+                .unwrap();
+                change_pitch(&mut audio, id, 0.25 + 2.75 * series.unilateral());
+                if series.between(0, 3) == 0 {
+                    fade_out_sound(&mut audio, id, 0.0005 * series.unilateral());
+                }
+            }
+
+            let sample_count = series.between(0, 67) as usize;
+            let mut expected = None;
+            for level in available_levels() {
+                let mut level_audio = *audio;
+                let mut left = vec![0.0f32; sample_count];
+                let mut right = vec![0.0f32; sample_count];
+                for _ in 0..2 {
+                    let mut mix = [&mut left[..], &mut right[..]];
+                    mix_playing_sounds(level, &mut level_audio, &mut mix, 48000);
+                }
+
+                let result: Vec<u32> = left.iter().chain(&right).map(|v| v.to_bits()).collect();
+                match &expected {
+                    None => expected = Some(result),
+                    Some(expected) => assert!(*expected == result, "{level:?} differs"),
+                }
+            }
+        }
+    }
+}
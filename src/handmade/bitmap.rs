@@ -0,0 +1,351 @@
+use std::mem;
+use std::slice;
+
+use super::memory::*;
+use super::png::*;
+
+#[derive(Clone, Copy, Debug)]
+pub struct LoadedBitmap {
+    pub width: i32,
+    pub height: i32,
+    // NOTE: In bytes, rows are stored top-down in the buffer's ARGB layout
+    pub pitch: i32,
+
+    // NOTE: Pixel inside the bitmap that lands on the drawing position,
+    // measured from the top-left corner
+    pub align_x: i32,
+    pub align_y: i32,
+
+    pub memory: *mut u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoadBitmapError {
+    Truncated,
+    Corrupt,
+    Unsupported,
+    OutOfMemory,
+}
+
+// NOTE: Anything larger than this is certainly not one of our assets
+const MAX_BITMAP_DIM: i32 = 1 << 14;
+
+pub fn allocate_bitmap(
+    arena: &mut MemoryArena,
+    width: i32,
+    height: i32,
+    align_x: i32,
+    align_y: i32,
+) -> Result<LoadedBitmap, LoadBitmapError> {
+    if width <= 0 || height <= 0 || width > MAX_BITMAP_DIM || height > MAX_BITMAP_DIM {
+        return Err(LoadBitmapError::Unsupported);
+    }
+
+    let memory =
+        push_array::<u32>(arena, (width * height) as usize).ok_or(LoadBitmapError::OutOfMemory)?;
+
+    Ok(LoadedBitmap {
+        width,
+        height,
+        pitch: width * mem::size_of::<u32>() as i32,
+        align_x,
+        align_y,
+        memory,
+    })
+}
+
+// NOTE: Bitmaps keep premultiplied alpha so blending is one multiply-add
+pub fn pack_premultiplied(r: u32, g: u32, b: u32, a: u32) -> u32 {
+    let premultiply = |c: u32| (c * a + 127) / 255;
+
+    (a << 24) | (premultiply(r) << 16) | (premultiply(g) << 8) | premultiply(b)
+}
+
+pub fn bitmap_row(bitmap: &LoadedBitmap, y: i32) -> &[u32] {
+    assert!(y >= 0 && y < bitmap.height);
+
+    let row_pixels = (bitmap.pitch as usize) / mem::size_of::<u32>();
+    unsafe {
+        slice::from_raw_parts(
+            bitmap.memory.add(y as usize * row_pixels),
+            bitmap.width as usize,
+        )
+    }
+}
+
+pub fn bitmap_row_mut(bitmap: &mut LoadedBitmap, y: i32) -> &mut [u32] {
+    assert!(y >= 0 && y < bitmap.height);
+
+    let row_pixels = (bitmap.pitch as usize) / mem::size_of::<u32>();
+    unsafe {
+        slice::from_raw_parts_mut(
+            bitmap.memory.add(y as usize * row_pixels),
+            bitmap.width as usize,
+        )
+    }
+}
+
+pub fn read_u16(data: &[u8], at: usize) -> Option<u16> {
+    let bytes = data.get(at..at.checked_add(2)?)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+pub fn read_u32(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at.checked_add(4)?)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+#[derive(Clone, Copy)]
+struct ChannelMask {
+    mask: u32,
+    shift: u32,
+    max: u32,
+}
+
+fn channel_mask(mask: u32) -> ChannelMask {
+    let shift = if mask == 0 { 0 } else { mask.trailing_zeros() };
+    let max = if mask == 0 { 0 } else { mask >> shift };
+
+    ChannelMask { mask, shift, max }
+}
+
+fn extract_channel(channel: ChannelMask, pixel: u32, missing: u32) -> u32 {
+    if channel.max == 0 {
+        return missing;
+    }
+
+    let value = ((pixel & channel.mask) >> channel.shift) as u64;
+    let max = channel.max as u64;
+
+    ((value * 255 + max / 2) / max) as u32
+}
+
+const BMP_FILE_HEADER_SIZE: usize = 14;
+
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+
+pub fn parse_bmp(
+    arena: &mut MemoryArena,
+    contents: &[u8],
+    align_x: i32,
+    align_y: i32,
+) -> Result<LoadedBitmap, LoadBitmapError> {
+    use LoadBitmapError::*;
+
+    if contents.len() < 2 {
+        return Err(Truncated);
+    }
+    if &contents[0..2] != b"BM" {
+        return Err(Corrupt);
+    }
+
+    let bitmap_offset = read_u32(contents, 10).ok_or(Truncated)? as usize;
+
+    let dib = BMP_FILE_HEADER_SIZE;
+    let header_size = read_u32(contents, dib).ok_or(Truncated)? as usize;
+    if header_size < 40 {
+        // NOTE: OS/2 core headers never show up in our pipeline
+        return Err(Unsupported);
+    }
+
+    let width = read_u32(contents, dib + 4).ok_or(Truncated)? as i32;
+    let raw_height = read_u32(contents, dib + 8).ok_or(Truncated)? as i32;
+    let bits_per_pixel = read_u16(contents, dib + 14).ok_or(Truncated)?;
+    let compression = read_u32(contents, dib + 16).ok_or(Truncated)?;
+
+    if width <= 0 || raw_height == 0 || raw_height == i32::MIN {
+        return Err(Corrupt);
+    }
+    let bottom_up = raw_height > 0;
+    let height = raw_height.abs();
+    if width > MAX_BITMAP_DIM || height > MAX_BITMAP_DIM {
+        return Err(Unsupported);
+    }
+
+    let bytes_per_pixel = match bits_per_pixel {
+        24 => 3,
+        32 => 4,
+        _ => return Err(Unsupported),
+    };
+
+    let (red_mask, green_mask, blue_mask, alpha_mask) = match compression {
+        BI_RGB => (0x00FF0000, 0x0000FF00, 0x000000FF, 0),
+        BI_BITFIELDS | BI_ALPHABITFIELDS if bytes_per_pixel == 4 => {
+            // NOTE: The masks sit right after the 40 byte header, which is
+            // also where V4/V5 headers keep them
+            let masks = dib + 40;
+            let has_alpha = compression == BI_ALPHABITFIELDS || header_size >= 56;
+
+            (
+                read_u32(contents, masks).ok_or(Truncated)?,
+                read_u32(contents, masks + 4).ok_or(Truncated)?,
+                read_u32(contents, masks + 8).ok_or(Truncated)?,
+                if has_alpha {
+                    read_u32(contents, masks + 12).ok_or(Truncated)?
+                } else {
+                    0
+                },
+            )
+        }
+        _ => return Err(Unsupported),
+    };
+
+    let red = channel_mask(red_mask);
+    let green = channel_mask(green_mask);
+    let blue = channel_mask(blue_mask);
+    let alpha = channel_mask(alpha_mask);
+
+    let row_size = (width as usize * bits_per_pixel as usize).div_ceil(32) * 4;
+    let last_row_end = bitmap_offset
+        .checked_add(row_size * (height as usize - 1))
+        .and_then(|offset| offset.checked_add(width as usize * bytes_per_pixel))
+        .ok_or(Corrupt)?;
+    if last_row_end > contents.len() {
+        return Err(Truncated);
+    }
+
+    let mut result = allocate_bitmap(arena, width, height, align_x, align_y)?;
+
+    for y in 0..height {
+        let source_y = if bottom_up { height - 1 - y } else { y };
+        let source_row = bitmap_offset + source_y as usize * row_size;
+        let dest_row = bitmap_row_mut(&mut result, y);
+
+        for (x, dest) in dest_row.iter_mut().enumerate() {
+            let at = source_row + x * bytes_per_pixel;
+            let pixel = if bytes_per_pixel == 4 {
+                u32::from_le_bytes([
+                    contents[at],
+                    contents[at + 1],
+                    contents[at + 2],
+                    contents[at + 3],
+                ])
+            } else {
+                u32::from_le_bytes([contents[at], contents[at + 1], contents[at + 2], 0])
+            };
+
+            let r = extract_channel(red, pixel, 0);
+            let g = extract_channel(green, pixel, 0);
+            let b = extract_channel(blue, pixel, 0);
+            let a = extract_channel(alpha, pixel, 255);
+
+            *dest = pack_premultiplied(r, g, b, a);
+        }
+    }
+
+    Ok(result)
+}
+
+// NOTE: Picks the decoder from the file signature rather than the extension
+pub fn parse_bitmap(
+    arena: &mut MemoryArena,
+    contents: &[u8],
+    align_x: i32,
+    align_y: i32,
+) -> Result<LoadedBitmap, LoadBitmapError> {
+    if contents.starts_with(&PNG_SIGNATURE) {
+        parse_png(arena, contents, align_x, align_y)
+    } else {
+        parse_bmp(arena, contents, align_x, align_y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::with_arena;
+    use super::*;
+
+    fn bmp_file(
+        width: i32,
+        height: i32,
+        bits_per_pixel: u16,
+        compression: u32,
+        masks: &[u32],
+        pixels: &[u8],
+    ) -> Vec<u8> {
+        let header_size = 40 + 4 * masks.len();
+        let bitmap_offset = (BMP_FILE_HEADER_SIZE + header_size) as u32;
+
+        let mut result = Vec::new();
+        result.extend_from_slice(b"BM");
+        result.extend_from_slice(&(bitmap_offset + pixels.len() as u32).to_le_bytes());
+        result.extend_from_slice(&0u32.to_le_bytes());
+        result.extend_from_slice(&bitmap_offset.to_le_bytes());
+
+        result.extend_from_slice(&(header_size as u32).to_le_bytes());
+        result.extend_from_slice(&width.to_le_bytes());
+        result.extend_from_slice(&height.to_le_bytes());
+        result.extend_from_slice(&1u16.to_le_bytes());
+        result.extend_from_slice(&bits_per_pixel.to_le_bytes());
+        result.extend_from_slice(&compression.to_le_bytes());
+        result.extend_from_slice(&[0; 20]);
+        for mask in masks {
+            result.extend_from_slice(&mask.to_le_bytes());
+        }
+
+        result.extend_from_slice(pixels);
+        result
+    }
+
+    #[test]
+    fn bottom_up_24_bit_rows_are_flipped_and_padded() {
+        // NOTE: Bottom row first, each 2 pixel row padded from 6 to 8 bytes
+        let pixels = [
+            0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0, 0, //
+            0xFF, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0, 0,
+        ];
+        let file = bmp_file(2, 2, 24, BI_RGB, &[], &pixels);
+
+        with_arena(64 * 1024, |arena| {
+            let bitmap = parse_bmp(arena, &file, 1, 2).unwrap();
+
+            assert_eq!((bitmap.width, bitmap.height, bitmap.pitch), (2, 2, 8));
+            assert_eq!((bitmap.align_x, bitmap.align_y), (1, 2));
+            assert_eq!(bitmap_row(&bitmap, 0), &[0xFF0000FF, 0xFFFFFFFF]);
+            assert_eq!(bitmap_row(&bitmap, 1), &[0xFFFF0000, 0xFF00FF00]);
+        });
+    }
+
+    #[test]
+    fn top_down_32_bit_bitfields_use_the_channel_masks() {
+        // NOTE: Bytes in memory are A, B, G, R with these masks
+        let masks = [0xFF000000, 0x00FF0000, 0x0000FF00, 0x000000FF];
+        let pixels = [0x80, 0x40, 0x20, 0x10];
+        let file = bmp_file(1, -1, 32, BI_ALPHABITFIELDS, &masks, &pixels);
+
+        with_arena(64 * 1024, |arena| {
+            let bitmap = parse_bmp(arena, &file, 0, 0).unwrap();
+
+            assert_eq!(bitmap_row(&bitmap, 0), &[0x80081020]);
+        });
+    }
+
+    #[test]
+    fn truncated_and_unsupported_files_are_rejected() {
+        let pixels = [0u8; 16];
+        let file = bmp_file(2, 2, 24, BI_RGB, &[], &pixels);
+
+        with_arena(64 * 1024, |arena| {
+            assert_eq!(
+                parse_bmp(arena, &file[..file.len() - 4], 0, 0).unwrap_err(),
+                LoadBitmapError::Truncated
+            );
+            assert_eq!(
+                parse_bmp(arena, &file[..20], 0, 0).unwrap_err(),
+                LoadBitmapError::Truncated
+            );
+            assert_eq!(
+                parse_bmp(arena, b"PNG!", 0, 0).unwrap_err(),
+                LoadBitmapError::Corrupt
+            );
+
+            let paletted = bmp_file(2, 2, 8, BI_RGB, &[], &pixels);
+            assert_eq!(
+                parse_bmp(arena, &paletted, 0, 0).unwrap_err(),
+                LoadBitmapError::Unsupported
+            );
+        });
+    }
+}
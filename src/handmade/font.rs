@@ -0,0 +1,308 @@
+use std::slice;
+
+use super::bitmap::*;
+use super::font_format::*;
+use super::memory::*;
+
+#[derive(Clone, Copy, Debug)]
+pub struct FontGlyph {
+    // NOTE: A view into the font's atlas, aligned on the pen position
+    pub bitmap: LoadedBitmap,
+    pub advance: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct KerningPair {
+    first: u32,
+    second: u32,
+    adjust: f32,
+}
+
+// NOTE: Metrics are in pixels at the size the font was baked at,
+// and the glyphs point into an atlas that lives in the same arena
+#[derive(Clone, Copy, Debug)]
+pub struct LoadedFont {
+    pub ascent: f32,
+    pub line_advance: f32,
+
+    first_codepoint: u32,
+    glyph_count: u32,
+    glyphs: *mut FontGlyph,
+
+    kerning_count: u32,
+    kerning: *mut KerningPair,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoadFontError {
+    Truncated,
+    Corrupt,
+    Unsupported,
+    OutOfMemory,
+}
+
+// NOTE: Anything larger than this is certainly not one of our fonts
+const MAX_FONT_GLYPH_COUNT: u32 = 1 << 16;
+const MAX_FONT_KERNING_COUNT: u32 = 1 << 20;
+const MAX_FONT_ATLAS_DIM: u32 = 1 << 13;
+
+fn glyphs(font: &LoadedFont) -> &[FontGlyph] {
+    if font.glyph_count == 0 {
+        return &[];
+    }
+    unsafe { slice::from_raw_parts(font.glyphs, font.glyph_count as usize) }
+}
+
+fn kerning(font: &LoadedFont) -> &[KerningPair] {
+    if font.kerning_count == 0 {
+        return &[];
+    }
+    unsafe { slice::from_raw_parts(font.kerning, font.kerning_count as usize) }
+}
+
+pub fn font_is_loaded(font: &LoadedFont) -> bool {
+    font.glyph_count > 0
+}
+
+pub fn get_glyph(font: &LoadedFont, codepoint: u32) -> Option<&FontGlyph> {
+    let index = codepoint.checked_sub(font.first_codepoint)?;
+    glyphs(font).get(index as usize)
+}
+
+pub fn get_kerning(font: &LoadedFont, first: u32, second: u32) -> f32 {
+    let pairs = kerning(font);
+    match pairs.binary_search_by(|pair| (pair.first, pair.second).cmp(&(first, second))) {
+        Ok(index) => pairs[index].adjust,
+        Err(_) => 0.0,
+    }
+}
+
+fn read_le<const N: usize>(data: &[u8], at: usize) -> Result<[u8; N], LoadFontError> {
+    data.get(at..at + N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(LoadFontError::Truncated)
+}
+
+fn read_u16(data: &[u8], at: usize) -> Result<u16, LoadFontError> {
+    read_le(data, at).map(u16::from_le_bytes)
+}
+
+fn read_i16(data: &[u8], at: usize) -> Result<i16, LoadFontError> {
+    read_le(data, at).map(i16::from_le_bytes)
+}
+
+fn read_u32(data: &[u8], at: usize) -> Result<u32, LoadFontError> {
+    read_le(data, at).map(u32::from_le_bytes)
+}
+
+fn read_f32(data: &[u8], at: usize) -> Result<f32, LoadFontError> {
+    let value = read_le(data, at).map(f32::from_le_bytes)?;
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err(LoadFontError::Corrupt)
+    }
+}
+
+pub fn parse_font(arena: &mut MemoryArena, contents: &[u8]) -> Result<LoadedFont, LoadFontError> {
+    if contents.len() < FONT_HEADER_SIZE {
+        return Err(LoadFontError::Truncated);
+    }
+    if contents[0..4] != FONT_MAGIC {
+        return Err(LoadFontError::Corrupt);
+    }
+    if read_u32(contents, 4)? != FONT_VERSION {
+        return Err(LoadFontError::Unsupported);
+    }
+
+    let first_codepoint = read_u32(contents, 8)?;
+    let glyph_count = read_u32(contents, 12)?;
+    let kerning_count = read_u32(contents, 16)?;
+    let atlas_width = read_u32(contents, 20)?;
+    let atlas_height = read_u32(contents, 24)?;
+    if glyph_count == 0
+        || glyph_count > MAX_FONT_GLYPH_COUNT
+        || kerning_count > MAX_FONT_KERNING_COUNT
+        || atlas_width == 0
+        || atlas_height == 0
+        || atlas_width > MAX_FONT_ATLAS_DIM
+        || atlas_height > MAX_FONT_ATLAS_DIM
+    {
+        return Err(LoadFontError::Unsupported);
+    }
+
+    let glyphs_at = FONT_HEADER_SIZE;
+    let kerning_at = glyphs_at + glyph_count as usize * FONT_GLYPH_SIZE;
+    let atlas_at = kerning_at + kerning_count as usize * FONT_KERNING_SIZE;
+    let atlas_size = (atlas_width * atlas_height) as usize;
+    if contents.len() < atlas_at + atlas_size {
+        return Err(LoadFontError::Truncated);
+    }
+
+    let mut atlas = allocate_bitmap(arena, atlas_width as i32, atlas_height as i32, 0, 0)
+        .map_err(|_| LoadFontError::OutOfMemory)?;
+    for y in 0..atlas.height {
+        let source_at = atlas_at + (y * atlas.width) as usize;
+        let source = &contents[source_at..source_at + atlas.width as usize];
+        for (dest, &coverage) in bitmap_row_mut(&mut atlas, y).iter_mut().zip(source) {
+            // NOTE: White, premultiplied by the coverage
+            *dest = u32::from_ne_bytes([coverage; 4]);
+        }
+    }
+
+    let glyphs =
+        push_array::<FontGlyph>(arena, glyph_count as usize).ok_or(LoadFontError::OutOfMemory)?;
+    let atlas_row_pixels = (atlas.pitch / 4) as usize;
+    for glyph_index in 0..glyph_count as usize {
+        let at = glyphs_at + glyph_index * FONT_GLYPH_SIZE;
+        let x = read_u16(contents, at)? as i32;
+        let y = read_u16(contents, at + 2)? as i32;
+        let width = read_u16(contents, at + 4)? as i32;
+        let height = read_u16(contents, at + 6)? as i32;
+        if x + width > atlas.width || y + height > atlas.height {
+            return Err(LoadFontError::Corrupt);
+        }
+
+        let glyph = FontGlyph {
+            bitmap: LoadedBitmap {
+                width,
+                height,
+                pitch: atlas.pitch,
+                align_x: read_i16(contents, at + 8)? as i32,
+                align_y: read_i16(contents, at + 10)? as i32,
+                memory: unsafe { atlas.memory.add(y as usize * atlas_row_pixels + x as usize) },
+            },
+            advance: read_f32(contents, at + 12)?,
+        };
+        unsafe { glyphs.add(glyph_index).write(glyph) };
+    }
+
+    let kerning = push_array::<KerningPair>(arena, kerning_count as usize)
+        .ok_or(LoadFontError::OutOfMemory)?;
+    let mut previous = None;
+    for pair_index in 0..kerning_count as usize {
+        let at = kerning_at + pair_index * FONT_KERNING_SIZE;
+        let pair = KerningPair {
+            first: read_u32(contents, at)?,
+            second: read_u32(contents, at + 4)?,
+            adjust: read_f32(contents, at + 8)?,
+        };
+
+        // NOTE: Lookups binary search, so the order is part of the format
+        let key = (pair.first, pair.second);
+        if previous.is_some_and(|previous| previous >= key) {
+            return Err(LoadFontError::Corrupt);
+        }
+        previous = Some(key);
+
+        unsafe { kerning.add(pair_index).write(pair) };
+    }
+
+    // NOTE: Descent is only there for tools, layout goes by the line advance
+    Ok(LoadedFont {
+        ascent: read_f32(contents, 28)?,
+        line_advance: read_f32(contents, 36)?,
+
+        first_codepoint,
+        glyph_count,
+        glyphs,
+
+        kerning_count,
+        kerning,
+    })
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::super::tests::with_arena;
+    use super::*;
+
+    // NOTE: A baked font file the way bake_font writes one
+    pub fn font_file(
+        first_codepoint: u32,
+        glyphs: &[(u16, u16, u16, u16, i16, i16, f32)],
+        kerning: &[(u32, u32, f32)],
+        atlas_width: u32,
+        atlas: &[u8],
+    ) -> Vec<u8> {
+        let mut result = Vec::new();
+        result.extend_from_slice(&FONT_MAGIC);
+        for value in [
+            FONT_VERSION,
+            first_codepoint,
+            glyphs.len() as u32,
+            kerning.len() as u32,
+            atlas_width,
+            atlas.len() as u32 / atlas_width,
+        ] {
+            result.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [8.0f32, 2.0, 12.0] {
+            result.extend_from_slice(&value.to_le_bytes());
+        }
+
+        for &(x, y, width, height, align_x, align_y, advance) in glyphs {
+            for value in [x, y, width, height] {
+                result.extend_from_slice(&value.to_le_bytes());
+            }
+            result.extend_from_slice(&align_x.to_le_bytes());
+            result.extend_from_slice(&align_y.to_le_bytes());
+            result.extend_from_slice(&advance.to_le_bytes());
+        }
+        for &(first, second, adjust) in kerning {
+            result.extend_from_slice(&first.to_le_bytes());
+            result.extend_from_slice(&second.to_le_bytes());
+            result.extend_from_slice(&adjust.to_le_bytes());
+        }
+        result.extend_from_slice(atlas);
+
+        result
+    }
+
+    #[test]
+    fn parse_font_reads_glyphs_and_kerning() {
+        with_arena(64 * 1024, |arena| {
+            #[rustfmt::skip]
+            let atlas = [
+                0x00, 0xFF, 0x00, 0x00,
+                0x00, 0x80, 0x00, 0x40,
+            ];
+            let contents = font_file(
+                b'A' as u32,
+                &[(1, 0, 1, 2, 0, 2, 3.0), (3, 1, 1, 1, 0, 1, 2.5)],
+                &[(b'A' as u32, b'B' as u32, -1.0)],
+                4,
+                &atlas,
+            );
+            let font = parse_font(arena, &contents).unwrap();
+
+            assert!(font_is_loaded(&font));
+            assert_eq!(font.line_advance, 12.0);
+            assert!(get_glyph(&font, b'@' as u32).is_none());
+            assert!(get_glyph(&font, b'C' as u32).is_none());
+
+            let a = get_glyph(&font, b'A' as u32).unwrap();
+            assert_eq!(a.advance, 3.0);
+            assert_eq!((a.bitmap.width, a.bitmap.height), (1, 2));
+            assert_eq!(bitmap_row(&a.bitmap, 0), [0xFFFFFFFF]);
+            assert_eq!(bitmap_row(&a.bitmap, 1), [0x80808080]);
+
+            let b = get_glyph(&font, b'B' as u32).unwrap();
+            assert_eq!(bitmap_row(&b.bitmap, 0), [0x40404040]);
+
+            assert_eq!(get_kerning(&font, b'A' as u32, b'B' as u32), -1.0);
+            assert_eq!(get_kerning(&font, b'B' as u32, b'A' as u32), 0.0);
+
+            // NOTE: Glyphs that poke out of the atlas are rejected
+            let contents = font_file(0, &[(3, 0, 2, 1, 0, 0, 1.0)], &[], 4, &atlas);
+            assert_eq!(
+                parse_font(arena, &contents).err(),
+                Some(LoadFontError::Corrupt)
+            );
+            assert_eq!(
+                parse_font(arena, &contents[..20]).err(),
+                Some(LoadFontError::Truncated)
+            );
+        });
+    }
+}
@@ -0,0 +1,19 @@
+// NOTE: The baked font file, shared with the bake_font tool so it has to
+// stay std only. Everything is little-endian:
+//
+//   header   magic, version, first codepoint, glyph count, kerning count,
+//            atlas width, atlas height (u32s), then ascent, descent and
+//            line advance (f32s, pixels below the baseline are positive)
+//   glyphs   one per codepoint from the first one on: atlas x, atlas y,
+//            width, height (u16s), align x, align y (i16s, the pen position
+//            on the baseline measured from the glyph's top-left), advance (f32)
+//   kerning  first codepoint, second codepoint (u32s), adjustment (f32),
+//            sorted by first then second codepoint
+//   atlas    one coverage byte per pixel, rows top-down
+
+pub const FONT_MAGIC: [u8; 4] = *b"HHFT";
+pub const FONT_VERSION: u32 = 1;
+
+pub const FONT_HEADER_SIZE: usize = 40;
+pub const FONT_GLYPH_SIZE: usize = 16;
+pub const FONT_KERNING_SIZE: usize = 12;
@@ -0,0 +1,425 @@
+// NOTE: A plain zlib/DEFLATE decoder (RFC 1950/1951), just enough for PNG
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InflateError {
+    Truncated,
+    Corrupt,
+    TooLarge,
+}
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    at: usize,
+    bit_buffer: u64,
+    bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader {
+            data,
+            at: 0,
+            bit_buffer: 0,
+            bit_count: 0,
+        }
+    }
+
+    fn bits(&mut self, count: u32) -> Result<u32, InflateError> {
+        assert!(count <= 32);
+
+        while self.bit_count < count {
+            let byte = *self.data.get(self.at).ok_or(InflateError::Truncated)?;
+            self.at += 1;
+            self.bit_buffer |= (byte as u64) << self.bit_count;
+            self.bit_count += 8;
+        }
+
+        let result = (self.bit_buffer & ((1u64 << count) - 1)) as u32;
+        self.bit_buffer >>= count;
+        self.bit_count -= count;
+
+        Ok(result)
+    }
+
+    fn align_to_byte(&mut self) {
+        let extra = self.bit_count % 8;
+        self.bit_buffer >>= extra;
+        self.bit_count -= extra;
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], InflateError> {
+        // NOTE: Only valid after align_to_byte, hand back whole buffered bytes first
+        assert!(self.bit_count.is_multiple_of(8));
+        self.at -= (self.bit_count / 8) as usize;
+        self.bit_buffer = 0;
+        self.bit_count = 0;
+
+        let end = self.at.checked_add(count).ok_or(InflateError::Corrupt)?;
+        let result = self.data.get(self.at..end).ok_or(InflateError::Truncated)?;
+        self.at = end;
+
+        Ok(result)
+    }
+
+    fn remaining(&mut self) -> &'a [u8] {
+        self.align_to_byte();
+        self.at -= (self.bit_count / 8) as usize;
+        self.bit_buffer = 0;
+        self.bit_count = 0;
+
+        &self.data[self.at..]
+    }
+}
+
+const MAX_CODE_LENGTH: usize = 15;
+
+// NOTE: Canonical Huffman table, decoded one length at a time
+struct Huffman {
+    counts: [u16; MAX_CODE_LENGTH + 1],
+    symbols: Vec<u16>,
+}
+
+fn build_huffman(lengths: &[u8]) -> Result<Huffman, InflateError> {
+    let mut counts = [0u16; MAX_CODE_LENGTH + 1];
+    for &length in lengths {
+        counts[length as usize] += 1;
+    }
+    counts[0] = 0;
+
+    // NOTE: Reject over-subscribed code sets; incomplete ones are legal
+    // (a single distance code, for instance)
+    let mut left: i32 = 1;
+    for &count in counts.iter().skip(1) {
+        left <<= 1;
+        left -= count as i32;
+        if left < 0 {
+            return Err(InflateError::Corrupt);
+        }
+    }
+
+    let mut offsets = [0u16; MAX_CODE_LENGTH + 2];
+    for length in 1..=MAX_CODE_LENGTH {
+        offsets[length + 1] = offsets[length] + counts[length];
+    }
+
+    let mut symbols = vec![0u16; offsets[MAX_CODE_LENGTH + 1] as usize];
+    for (symbol, &length) in lengths.iter().enumerate() {
+        if length != 0 {
+            symbols[offsets[length as usize] as usize] = symbol as u16;
+            offsets[length as usize] += 1;
+        }
+    }
+
+    Ok(Huffman { counts, symbols })
+}
+
+fn decode_symbol(reader: &mut BitReader, huffman: &Huffman) -> Result<u16, InflateError> {
+    let mut code: i32 = 0;
+    let mut first: i32 = 0;
+    let mut index: i32 = 0;
+
+    for length in 1..=MAX_CODE_LENGTH {
+        code |= reader.bits(1)? as i32;
+        let count = huffman.counts[length] as i32;
+        if code - count < first {
+            return Ok(huffman.symbols[(index + (code - first)) as usize]);
+        }
+        index += count;
+        first += count;
+        first <<= 1;
+        code <<= 1;
+    }
+
+    Err(InflateError::Corrupt)
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+// NOTE: Order in which code length code lengths are stored in a dynamic block
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+fn fixed_tables() -> Result<(Huffman, Huffman), InflateError> {
+    let mut lengths = [0u8; 288];
+    lengths[0..144].fill(8);
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths[280..288].fill(8);
+
+    Ok((build_huffman(&lengths)?, build_huffman(&[5; 30])?))
+}
+
+fn dynamic_tables(reader: &mut BitReader) -> Result<(Huffman, Huffman), InflateError> {
+    let literal_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_length_count = reader.bits(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err(InflateError::Corrupt);
+    }
+
+    let mut code_lengths = [0u8; 19];
+    for &index in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+        code_lengths[index] = reader.bits(3)? as u8;
+    }
+    let code_length_huffman = build_huffman(&code_lengths)?;
+
+    let mut lengths = [0u8; 286 + 30];
+    let total = literal_count + distance_count;
+    let mut index = 0;
+    while index < total {
+        let symbol = decode_symbol(reader, &code_length_huffman)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                if index == 0 {
+                    return Err(InflateError::Corrupt);
+                }
+                (lengths[index - 1], 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            18 => (0, 11 + reader.bits(7)? as usize),
+            _ => return Err(InflateError::Corrupt),
+        };
+
+        if index + repeat > total {
+            return Err(InflateError::Corrupt);
+        }
+        lengths[index..index + repeat].fill(value);
+        index += repeat;
+    }
+
+    if lengths[256] == 0 {
+        // NOTE: A block that cannot end is not a block
+        return Err(InflateError::Corrupt);
+    }
+
+    Ok((
+        build_huffman(&lengths[..literal_count])?,
+        build_huffman(&lengths[literal_count..total])?,
+    ))
+}
+
+fn inflate_block(
+    reader: &mut BitReader,
+    output: &mut Vec<u8>,
+    max_size: usize,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), InflateError> {
+    loop {
+        let symbol = decode_symbol(reader, literals)? as usize;
+        match symbol {
+            0..=255 => {
+                if output.len() >= max_size {
+                    return Err(InflateError::TooLarge);
+                }
+                output.push(symbol as u8);
+            }
+            256 => return Ok(()),
+            257..=285 => {
+                let length_index = symbol - 257;
+                let length = LENGTH_BASE[length_index] as usize
+                    + reader.bits(LENGTH_EXTRA[length_index] as u32)? as usize;
+
+                let distance_index = decode_symbol(reader, distances)? as usize;
+                if distance_index >= DISTANCE_BASE.len() {
+                    return Err(InflateError::Corrupt);
+                }
+                let distance = DISTANCE_BASE[distance_index] as usize
+                    + reader.bits(DISTANCE_EXTRA[distance_index] as u32)? as usize;
+
+                if distance > output.len() {
+                    return Err(InflateError::Corrupt);
+                }
+                if output.len() + length > max_size {
+                    return Err(InflateError::TooLarge);
+                }
+
+                // NOTE: Copies may overlap their own output, so go byte by byte
+                let start = output.len() - distance;
+                for offset in 0..length {
+                    let byte = output[start + offset];
+                    output.push(byte);
+                }
+            }
+            _ => return Err(InflateError::Corrupt),
+        }
+    }
+}
+
+fn inflate_raw<'a>(reader: &mut BitReader<'a>, max_size: usize) -> Result<Vec<u8>, InflateError> {
+    let mut output = Vec::new();
+
+    loop {
+        let is_final = reader.bits(1)? == 1;
+        let block_type = reader.bits(2)?;
+
+        match block_type {
+            0 => {
+                reader.align_to_byte();
+                let header = reader.bytes(4)?;
+                let length = u16::from_le_bytes([header[0], header[1]]);
+                let inverse_length = u16::from_le_bytes([header[2], header[3]]);
+                if length != !inverse_length {
+                    return Err(InflateError::Corrupt);
+                }
+                if output.len() + length as usize > max_size {
+                    return Err(InflateError::TooLarge);
+                }
+
+                output.extend_from_slice(reader.bytes(length as usize)?);
+            }
+            1 => {
+                let (literals, distances) = fixed_tables()?;
+                inflate_block(reader, &mut output, max_size, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_tables(reader)?;
+                inflate_block(reader, &mut output, max_size, &literals, &distances)?;
+            }
+            _ => return Err(InflateError::Corrupt),
+        }
+
+        if is_final {
+            return Ok(output);
+        }
+    }
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MOD_ADLER: u32 = 65521;
+
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    // NOTE: 5552 is the largest run that cannot overflow before the modulo
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD_ADLER;
+        b %= MOD_ADLER;
+    }
+
+    (b << 16) | a
+}
+
+// NOTE: Decompressing more than max_size bytes is an error rather than an
+// allocation, so a hostile file cannot balloon memory
+pub fn zlib_decompress(data: &[u8], max_size: usize) -> Result<Vec<u8>, InflateError> {
+    if data.len() < 2 {
+        return Err(InflateError::Truncated);
+    }
+
+    let cmf = data[0];
+    let flg = data[1];
+    let compression_method = cmf & 0x0F;
+    let window_bits = (cmf >> 4) + 8;
+    let has_dictionary = flg & 0x20 != 0;
+    if compression_method != 8
+        || window_bits > 15
+        || has_dictionary
+        || !(((cmf as u16) << 8) | flg as u16).is_multiple_of(31)
+    {
+        return Err(InflateError::Corrupt);
+    }
+
+    let mut reader = BitReader::new(&data[2..]);
+    let result = inflate_raw(&mut reader, max_size)?;
+
+    let trailer = reader.remaining();
+    if trailer.len() < 4 {
+        return Err(InflateError::Truncated);
+    }
+    let expected = u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    if expected != adler32(&result) {
+        return Err(InflateError::Corrupt);
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // NOTE: Both produced with Python's zlib.compress(data, 9); the first is
+    // a fixed Huffman block with a back reference, the second a dynamic block
+    const FIXED_HELLO: [u8; 16] = [
+        0x78, 0xda, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x27, 0x01, 0x68, 0x03, 0x08,
+        0xb1,
+    ];
+    const DYNAMIC_HIGH_BYTES: [u8; 25] = [
+        0x78, 0xda, 0x05, 0xc1, 0x01, 0x01, 0x00, 0x00, 0x00, 0x82, 0xa0, 0xff, 0xcf, 0x4c, 0x4f,
+        0x05, 0x90, 0x8b, 0xe9, 0x68, 0x76, 0x6b, 0x04, 0x0c, 0x99,
+    ];
+
+    #[test]
+    fn stored_block_round_trips() {
+        let payload = b"stored bytes";
+        let mut data = vec![0x78, 0x01, 0x01];
+        data.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+        data.extend_from_slice(&(!(payload.len() as u16)).to_le_bytes());
+        data.extend_from_slice(payload);
+        data.extend_from_slice(&adler32(payload).to_be_bytes());
+
+        assert_eq!(zlib_decompress(&data, 1024).unwrap(), payload);
+        assert_eq!(zlib_decompress(&data, 4), Err(InflateError::TooLarge));
+        assert_eq!(
+            zlib_decompress(&data[..data.len() - 2], 1024),
+            Err(InflateError::Truncated)
+        );
+    }
+
+    #[test]
+    fn fixed_huffman_block_with_back_reference() {
+        let mut data = FIXED_HELLO.to_vec();
+        assert_eq!(
+            zlib_decompress(&data, 1024).unwrap(),
+            b"hello hello hello hello"
+        );
+
+        let last = data.len() - 1;
+        data[last] ^= 1;
+        assert_eq!(zlib_decompress(&data, 1024), Err(InflateError::Corrupt));
+    }
+
+    #[test]
+    fn dynamic_huffman_block() {
+        let expected = [
+            200, 200, 203, 202, 201, 203, 200, 201, 202, 202, 201, 200, 203, 201, 202, 203,
+        ];
+        assert_eq!(
+            zlib_decompress(&DYNAMIC_HIGH_BYTES, 1024).unwrap(),
+            expected
+        );
+
+        for length in 0..DYNAMIC_HIGH_BYTES.len() {
+            assert!(zlib_decompress(&DYNAMIC_HIGH_BYTES[..length], 1024).is_err());
+        }
+    }
+
+    #[test]
+    fn bad_header_is_rejected() {
+        assert_eq!(zlib_decompress(&[0x78], 16), Err(InflateError::Truncated));
+        assert_eq!(
+            zlib_decompress(&[0x79, 0x9c, 0x03, 0x00], 16),
+            Err(InflateError::Corrupt)
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct V2 {
+    pub x: f32,
+    pub y: f32,
+}
+
+pub fn v2(x: f32, y: f32) -> V2 {
+    V2 { x, y }
+}
+
+impl Add for V2 {
+    type Output = V2;
+
+    fn add(self, b: V2) -> V2 {
+        v2(self.x + b.x, self.y + b.y)
+    }
+}
+
+impl AddAssign for V2 {
+    fn add_assign(&mut self, b: V2) {
+        *self = *self + b;
+    }
+}
+
+impl Sub for V2 {
+    type Output = V2;
+
+    fn sub(self, b: V2) -> V2 {
+        v2(self.x - b.x, self.y - b.y)
+    }
+}
+
+impl SubAssign for V2 {
+    fn sub_assign(&mut self, b: V2) {
+        *self = *self - b;
+    }
+}
+
+impl Neg for V2 {
+    type Output = V2;
+
+    fn neg(self) -> V2 {
+        v2(-self.x, -self.y)
+    }
+}
+
+impl Mul<V2> for f32 {
+    type Output = V2;
+
+    fn mul(self, b: V2) -> V2 {
+        v2(self * b.x, self * b.y)
+    }
+}
+
+impl Mul<f32> for V2 {
+    type Output = V2;
+
+    fn mul(self, b: f32) -> V2 {
+        b * self
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct V4 {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+pub fn v4(r: f32, g: f32, b: f32, a: f32) -> V4 {
+    V4 { r, g, b, a }
+}
+
+impl Add for V4 {
+    type Output = V4;
+
+    fn add(self, b: V4) -> V4 {
+        v4(self.r + b.r, self.g + b.g, self.b + b.b, self.a + b.a)
+    }
+}
+
+impl Mul<V4> for f32 {
+    type Output = V4;
+
+    fn mul(self, b: V4) -> V4 {
+        v4(self * b.r, self * b.g, self * b.b, self * b.a)
+    }
+}
+
+// NOTE: The z of the 3D cross product, positive when b is counterclockwise of a
+pub fn cross(a: V2, b: V2) -> f32 {
+    a.x * b.y - a.y * b.x
+}
+
+pub fn hadamard4(a: V4, b: V4) -> V4 {
+    v4(a.r * b.r, a.g * b.g, a.b * b.b, a.a * b.a)
+}
+
+pub fn lerp4(a: V4, t: f32, b: V4) -> V4 {
+    (1.0 - t) * a + t * b
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct V3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+pub fn v3(x: f32, y: f32, z: f32) -> V3 {
+    V3 { x, y, z }
+}
+
+impl Add for V3 {
+    type Output = V3;
+
+    fn add(self, b: V3) -> V3 {
+        v3(self.x + b.x, self.y + b.y, self.z + b.z)
+    }
+}
+
+impl Sub for V3 {
+    type Output = V3;
+
+    fn sub(self, b: V3) -> V3 {
+        v3(self.x - b.x, self.y - b.y, self.z - b.z)
+    }
+}
+
+impl Mul<V3> for f32 {
+    type Output = V3;
+
+    fn mul(self, b: V3) -> V3 {
+        v3(self * b.x, self * b.y, self * b.z)
+    }
+}
+
+pub fn inner3(a: V3, b: V3) -> f32 {
+    a.x * b.x + a.y * b.y + a.z * b.z
+}
+
+pub fn length3(a: V3) -> f32 {
+    inner3(a, a).sqrt()
+}
+
+// NOTE: Zero stays zero instead of turning into NaNs
+pub fn normalize_or_zero3(a: V3) -> V3 {
+    let length = length3(a);
+    if length > 0.0 {
+        (1.0 / length) * a
+    } else {
+        V3::default()
+    }
+}
+
+pub fn length(a: V2) -> f32 {
+    (a.x * a.x + a.y * a.y).sqrt()
+}
@@ -0,0 +1,58 @@
+use std::mem;
+use std::ptr;
+
+#[derive(Clone, Copy, Debug)]
+pub struct MemoryArena {
+    pub size: usize,
+    pub base: *mut u8,
+    pub used: usize,
+}
+
+pub fn initialize_arena(arena: &mut MemoryArena, size: usize, base: *mut u8) {
+    arena.size = size;
+    arena.base = base;
+    arena.used = 0;
+}
+
+pub fn push_size(arena: &mut MemoryArena, size: usize, align: usize) -> Option<*mut u8> {
+    let current = arena.base as usize + arena.used;
+    let alignment_offset = current.wrapping_neg() & (align - 1);
+
+    let end = arena
+        .used
+        .checked_add(alignment_offset)?
+        .checked_add(size)?;
+    if end > arena.size {
+        return None;
+    }
+
+    let result = unsafe { arena.base.add(arena.used + alignment_offset) };
+    arena.used = end;
+
+    Some(result)
+}
+
+// NOTE: Arena memory comes out zeroed, so only push types for which all
+// zero bytes is a valid value
+pub fn push_array<T: Copy>(arena: &mut MemoryArena, count: usize) -> Option<*mut T> {
+    let size = mem::size_of::<T>().checked_mul(count)?;
+    let result = push_size(arena, size, mem::align_of::<T>())?;
+
+    unsafe { ptr::write_bytes(result, 0, size) };
+
+    Some(result as *mut T)
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TemporaryMemory {
+    used: usize,
+}
+
+pub fn begin_temporary_memory(arena: &MemoryArena) -> TemporaryMemory {
+    TemporaryMemory { used: arena.used }
+}
+
+pub fn end_temporary_memory(arena: &mut MemoryArena, temp: TemporaryMemory) {
+    assert!(arena.used >= temp.used);
+    arena.used = temp.used;
+}
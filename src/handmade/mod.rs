@@ -2,9 +2,39 @@
 
 extern crate sdl2;
 
-use std::cmp::{max, min};
-use std::f32::consts::PI;
+mod asset;
+mod asset_format;
+mod audio;
+mod bitmap;
+mod font;
+mod font_format;
+mod inflate;
+mod math;
+mod memory;
+mod music;
+mod png;
+mod render_group;
+mod simd;
+mod sound;
+mod synth;
+mod work_queue;
+
+use std::cmp::max;
 use std::mem;
+use std::sync::Arc;
+
+use asset::*;
+use asset_format::*;
+use audio::*;
+use bitmap::*;
+use font::*;
+use math::*;
+use memory::*;
+use music::*;
+use render_group::*;
+use sound::*;
+use synth::*;
+pub use work_queue::{make_work_queue, PlatformWorkQueue};
 
 type bool32 = i32;
 
@@ -21,6 +51,15 @@ pub struct DebugReadFileResult {
     pub contents_size: u32,
     pub contents: Vec<u8>,
 }
+
+pub type DebugPlatformReadEntireFile = fn(&ThreadContext, &str) -> DebugReadFileResult;
+// NOTE: Fills as much of the buffer as the file has from the offset on and
+// returns how many bytes that was, zero if the file can't be read
+pub type DebugPlatformReadFileRange = fn(&ThreadContext, &str, u64, &mut [u8]) -> usize;
+// NOTE: Any value that changes whenever the file is written, zero if the file
+// can't be found
+pub type DebugPlatformGetFileWriteTime = fn(&ThreadContext, &str) -> u64;
+pub type DebugPlatformLog = fn(&ThreadContext, &str);
 #[derive(Clone)]
 pub struct GameOffscreenBuffer {
     pub memory: Vec<u8>,
@@ -30,10 +69,11 @@ pub struct GameOffscreenBuffer {
     pub bytes_per_pixel: i32,
 }
 
-struct GameSoundOutputBuffer<'a> {
-    samples_per_second: i32,
-    sample_count: i32,
-    samples: &'a mut [i16],
+// NOTE: Stereo, interleaved left then right, sample_count frames long
+pub struct GameSoundOutputBuffer<'a> {
+    pub samples_per_second: i32,
+    pub sample_count: i32,
+    pub samples: &'a mut [i16],
 }
 
 #[derive(Clone, Copy)]
@@ -44,13 +84,17 @@ pub struct GameButtonState {
 
 #[derive(Clone, Copy)]
 pub struct GameControllerInput {
-    // pub is_connected: bool,
+    pub is_connected: bool,
     pub is_analog: bool,
     pub stick_average_x: f32,
     pub stick_average_y: f32,
     pub buttons: [GameButtonState; 12],
 }
 
+pub const BUTTON_RIGHT_SHOULDER: usize = 9;
+pub const BUTTON_BACK: usize = 10;
+pub const BUTTON_START: usize = 11;
+
 #[derive(Clone, Copy)]
 pub struct GameInput {
     pub dt_for_frame: f32,
@@ -67,15 +111,24 @@ pub struct GameMemory {
 
     pub transient_storage_size: usize,
     pub transient_storage: Vec<u8>,
+
+    pub high_priority_queue: Option<Arc<PlatformWorkQueue>>,
+    // NOTE: For work that can take frames, like loading assets; nothing ever
+    // waits for it to finish
+    pub low_priority_queue: Option<Arc<PlatformWorkQueue>>,
+
     // Debug functions (optional)
     pub debug_platform_free_file_memory: Option<fn(&ThreadContext, &mut [u8])>,
-    pub debug_platform_read_entire_file: Option<fn(&ThreadContext, &str) -> DebugReadFileResult>,
+    pub debug_platform_read_entire_file: Option<DebugPlatformReadEntireFile>,
+    pub debug_platform_read_file_range: Option<DebugPlatformReadFileRange>,
     pub debug_platform_write_entire_file: Option<fn(&ThreadContext, &str, &[u8]) -> bool>,
+    pub debug_platform_get_file_write_time: Option<DebugPlatformGetFileWriteTime>,
+    pub debug_platform_log: Option<DebugPlatformLog>,
 }
 
 struct TileChunkPosition {
-    tile_chunk_x: u32,
-    tile_chunk_y: u32,
+    tile_chunk_x: i32,
+    tile_chunk_y: i32,
 
     rel_tile_x: u32,
     rel_tile_y: u32,
@@ -83,28 +136,27 @@ struct TileChunkPosition {
 
 #[derive(Clone, Copy, Debug)]
 struct WorldPosition {
-    /* TODO:
-
-       Take the tile map x and y
-       and the tile x and y
-       and pack them into single 32-bit value values for x and y
-       where there is some low bits for the tile index
-       and the high bits are the tile "page"
-
-       (NOTE: we can eliminate the need for floor)
-    */
-    abs_tile_x: u32,
-    abs_tile_y: u32,
-
-    /* TODO:
-
-      Should these be from the center of a tile?
-      Rename to offset X and Y
-    */
-    tile_rel_x: f32,
-    tile_rel_y: f32,
+    // NOTE: The high bits are the tile chunk index,
+    // the low bits are the tile index inside the chunk.
+    // Both are signed so the world extends on either side of the origin.
+    abs_tile_x: i32,
+    abs_tile_y: i32,
+    // NOTE: Which floor; the tile map only has the one so far, but sounds
+    // already get muffled through the floors in between
+    abs_tile_z: i32,
+
+    // NOTE: Offset from the center of the tile in fixed point tile units,
+    // always within [-TILE_OFFSET_HALF, TILE_OFFSET_HALF)
+    offset_x: i32,
+    offset_y: i32,
 }
 
+// NOTE: Offsets inside a tile are fixed point so that recanonicalizing is
+// exact; there is no float rounding that can push them out of the tile
+const TILE_OFFSET_SHIFT: u32 = 16;
+const TILE_OFFSET_ONE: i32 = 1 << TILE_OFFSET_SHIFT;
+const TILE_OFFSET_HALF: i32 = TILE_OFFSET_ONE / 2;
+
 #[derive(Debug)]
 struct TileChunk {
     tiles: Vec<u32>,
@@ -125,90 +177,163 @@ struct World {
     tile_chunk_count_y: i32,
 
     tile_chunks: Vec<TileChunk>,
+
+    // NOTE: Rooms that aren't listed have no music
+    room_music: Vec<RoomMusic>,
+}
+
+#[derive(Debug)]
+struct RoomMusic {
+    room_x: i32,
+    room_y: i32,
+    room_z: i32,
+    track: &'static str,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Entity {
+    exists: bool,
+    p: WorldPosition,
+    width: f32,
+    height: f32,
+    facing_direction: usize,
 }
 
+// NOTE: Also the facing_direction tag values the hero art carries in the
+// asset pack
+const FACING_RIGHT: usize = 0;
+const FACING_BACK: usize = 1;
+const FACING_LEFT: usize = 2;
+const FACING_FRONT: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CameraMode {
+    SmoothFollow,
+    RoomSnap,
+}
+
+const ROOM_TILES_X: i32 = 17;
+const ROOM_TILES_Y: i32 = 9;
+
+// NOTE: Exponential decay rate per second of the camera's distance to the
+// hero, so the follow looks the same at any frame rate
+const CAMERA_FOLLOW_RATE: f32 = 8.0;
+
+const MUSIC_CROSSFADE_SECONDS: f32 = 2.0;
+
+const ASSET_MEMORY_BUDGET: usize = 32 * 1024 * 1024;
+
 pub struct GameState {
-    pub player_p: WorldPosition,
+    world_arena: MemoryArena,
+    assets: Assets,
+    backdrop: BitmapId,
+    shadow: BitmapId,
+    // NOTE: Drawn instead when the pack has no shadow
+    fallback_shadow: LoadedBitmap,
+    tile_bitmap: LoadedBitmap,
+    tile_normal_map: LoadedBitmap,
+    debug_font: FontId,
+    debug_show_normals: bool,
+
+    audio: AudioState,
+    music: MusicState,
+    bloop: SoundId,
+    join_whoosh: LoadedSound,
+    leave_sweep: LoadedSound,
+    camera_blip: LoadedSound,
+    normals_blip: LoadedSound,
+
+    camera_mode: CameraMode,
+    camera_following_entity_index: usize,
+    camera_p: WorldPosition,
+
+    // NOTE: 0 means the controller has no hero, entity slot 0 is never used
+    player_index_for_controller: [usize; 5],
+    entities: [Entity; 256],
 }
 
-fn game_output_sound(
-    _game_state: &mut GameState,
-    sound_buffer: &mut GameSoundOutputBuffer,
-    _tone_hz: i32,
-) {
-    let tone_volume: i16 = 3000;
-    let _wave_period = sound_buffer.samples_per_second / _tone_hz;
+// NOTE: A soft black ellipse, meant to be stretched under whatever casts it
+fn make_shadow_bitmap(
+    arena: &mut MemoryArena,
+    width: i32,
+    height: i32,
+) -> Result<LoadedBitmap, LoadBitmapError> {
+    let mut bitmap = allocate_bitmap(arena, width, height, 0, 0)?;
+
+    for y in 0..height {
+        let ny = 2.0 * (y as f32 + 0.5) / height as f32 - 1.0;
+        let row = bitmap_row_mut(&mut bitmap, y);
+        for (x, pixel) in row.iter_mut().enumerate() {
+            let nx = 2.0 * (x as f32 + 0.5) / width as f32 - 1.0;
+            let falloff = (1.0 - (nx * nx + ny * ny)).max(0.0);
+            *pixel = pack_premultiplied(0, 0, 0, round_real32_to_uint32(255.0 * falloff));
+        }
+    }
+
+    Ok(bitmap)
+}
+
+fn make_solid_bitmap(arena: &mut MemoryArena, color: u32) -> Result<LoadedBitmap, LoadBitmapError> {
+    let mut bitmap = allocate_bitmap(arena, 1, 1, 0, 0)?;
+    bitmap_row_mut(&mut bitmap, 0)[0] = color;
 
-    let samples = &mut sound_buffer.samples;
-    let sample_count = sound_buffer.sample_count as usize;
+    Ok(bitmap)
+}
 
-    for sample_index in 0..sample_count {
-        // TODO: Draw this out for people
-        // The original code has sine wave generation commented out
-        let sample_value: i16 = 0;
+// NOTE: Flat in the middle, tilting outwards over the last bevel texels
+// towards each edge so the edges catch light from that side
+fn make_bevel_normal_map(
+    arena: &mut MemoryArena,
+    width: i32,
+    height: i32,
+    bevel: i32,
+) -> Result<LoadedBitmap, LoadBitmapError> {
+    let mut bitmap = allocate_bitmap(arena, width, height, 0, 0)?;
+
+    let tilt = |at: i32, count: i32| {
+        let from_low = (bevel - at) as f32 / bevel as f32;
+        let from_high = (at + 1 - (count - bevel)) as f32 / bevel as f32;
+        from_high.max(0.0) - from_low.max(0.0)
+    };
 
-        let index = sample_index * 2;
-        if index + 1 < samples.len() {
-            samples[index] = sample_value;
-            samples[index + 1] = sample_value;
+    for y in 0..height {
+        // NOTE: Rows run top down, normal map y runs up
+        let ny = -tilt(y, height);
+        let row = bitmap_row_mut(&mut bitmap, y);
+        for (x, pixel) in row.iter_mut().enumerate() {
+            let nx = tilt(x as i32, width);
+            let n = normalize_or_zero3(v3(nx, ny, 1.0));
+            let encode = |value: f32| round_real32_to_uint32(255.0 * (0.5 + 0.5 * value));
+            *pixel = pack_premultiplied(encode(n.x), encode(n.y), encode(n.z), 255);
         }
     }
+
+    Ok(bitmap)
+}
+
+struct TransientState {
+    is_initialized: bool,
+    tran_arena: MemoryArena,
 }
 
+const LAYER_BACKDROP: i32 = -1;
+const LAYER_TILES: i32 = 0;
+const LAYER_SHADOWS: i32 = 1;
+const LAYER_ENTITIES: i32 = 2;
+const LAYER_DEBUG: i32 = 100;
+
 fn round_real32_to_int32(value: f32) -> i32 {
-    (value + 0.5) as i32
+    value.round() as i32
 }
 
 fn round_real32_to_uint32(value: f32) -> u32 {
     (value + 0.5) as u32
 }
 
-fn floor_real32_to_int32(value: f32) -> i32 {
-    value.floor() as i32
-}
-
 fn truncate_real32_to_int32(value: f32) -> i32 {
     value as i32
 }
 
-fn draw_rectangle(
-    buffer: &mut GameOffscreenBuffer,
-    real_min_x: f32,
-    real_min_y: f32,
-    real_max_x: f32,
-    real_max_y: f32,
-    r: f32,
-    g: f32,
-    b: f32,
-) {
-    let mut min_x = round_real32_to_int32(real_min_x);
-    let mut min_y = round_real32_to_int32(real_min_y);
-    let mut max_x = round_real32_to_int32(real_max_x);
-    let mut max_y = round_real32_to_int32(real_max_y);
-
-    min_x = max(0, min_x);
-    min_y = max(0, min_y);
-    max_x = min(buffer.width, max_x);
-    max_y = min(buffer.height, max_y);
-
-    let color = ((round_real32_to_uint32(r * 255.0) << 16)
-        | (round_real32_to_uint32(g * 255.0) << 8)
-        | (round_real32_to_uint32(b * 255.0) << 0)) as u32;
-
-    let bytes_per_pixel = buffer.bytes_per_pixel as usize;
-    let pitch = buffer.pitch as usize;
-
-    for y in min_y..max_y {
-        let row_start = (y as usize) * pitch + (min_x as usize) * bytes_per_pixel;
-        for x in min_x..max_x {
-            let pixel_index = row_start + (x as usize - min_x as usize) * bytes_per_pixel;
-            if pixel_index + 3 < buffer.memory.len() {
-                buffer.memory[pixel_index..pixel_index + 4].copy_from_slice(&color.to_le_bytes());
-            }
-        }
-    }
-}
-
 fn get_tile_chunk<'a>(
     world: &'a World,
     tile_chunk_x: i32,
@@ -246,63 +371,96 @@ fn get_tile_chunk_value(
     tile_chunk: Option<&TileChunk>,
     test_tile_x: u32,
     test_tile_y: u32,
-) -> u32 {
-    let mut tile_chunk_value: u32 = 0;
+) -> Option<u32> {
+    let mut tile_chunk_value = None;
 
     if let Some(tile_chunk) = tile_chunk {
-        tile_chunk_value = get_tile_value_unchecked(world, tile_chunk, test_tile_x, test_tile_y);
+        tile_chunk_value = Some(get_tile_value_unchecked(
+            world,
+            tile_chunk,
+            test_tile_x,
+            test_tile_y,
+        ));
     }
 
     tile_chunk_value
 }
 
-fn recanonicalize_coord(world: &World, tile: &mut u32, tile_rel: &mut f32) {
-    let offset: i32 = floor_real32_to_int32(*tile_rel / world.tile_side_in_meters);
-
-    if offset != 0 {
-        if offset < 0 {
-            *tile = tile.wrapping_sub((-offset) as u32);
-        } else {
-            *tile = tile.wrapping_add(offset as u32);
-        }
-        *tile_rel -= offset as f32 * world.tile_side_in_meters;
-    }
+fn recanonicalize_coord(tile: &mut i32, tile_offset: &mut i32, delta: i64) {
+    // NOTE: Shifting by half a tile first turns the centered offset into a
+    // plain [0, TILE_OFFSET_ONE) remainder, and the arithmetic shift floors
+    // toward negative infinity on both sides of the origin
+    let total = *tile_offset as i64 + delta + TILE_OFFSET_HALF as i64;
+    let tile_delta = total >> TILE_OFFSET_SHIFT;
+
+    // NOTE: Movement is only accepted onto tiles inside a chunk, so nothing
+    // that moves gets anywhere near the ends of the i32 range; the clamp just
+    // keeps a bogus delta from wrapping around
+    *tile = (*tile as i64 + tile_delta).clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+    *tile_offset = (total & (TILE_OFFSET_ONE as i64 - 1)) as i32 - TILE_OFFSET_HALF;
+}
 
-    assert!(*tile_rel >= 0.0);
-    assert!(*tile_rel <= world.tile_side_in_meters);
+fn meters_to_tile_offset(world: &World, meters: f32) -> i64 {
+    (meters * (TILE_OFFSET_ONE as f32 / world.tile_side_in_meters)).round() as i64
 }
 
-fn recanonicalize_position(world: &World, pos: WorldPosition) -> WorldPosition {
-    let mut result = pos;
+fn offset(world: &World, p: WorldPosition, delta: V2) -> WorldPosition {
+    let mut result = p;
 
-    recanonicalize_coord(world, &mut result.abs_tile_x, &mut result.tile_rel_x);
-    recanonicalize_coord(world, &mut result.abs_tile_y, &mut result.tile_rel_y);
+    recanonicalize_coord(
+        &mut result.abs_tile_x,
+        &mut result.offset_x,
+        meters_to_tile_offset(world, delta.x),
+    );
+    recanonicalize_coord(
+        &mut result.abs_tile_y,
+        &mut result.offset_y,
+        meters_to_tile_offset(world, delta.y),
+    );
 
     result
 }
 
-fn get_chunk_position_for(world: &World, abs_tile_x: u32, abs_tile_y: u32) -> TileChunkPosition {
+fn subtract(world: &World, a: WorldPosition, b: WorldPosition) -> V2 {
+    let d_x = ((a.abs_tile_x as i64 - b.abs_tile_x as i64) << TILE_OFFSET_SHIFT)
+        + (a.offset_x - b.offset_x) as i64;
+    let d_y = ((a.abs_tile_y as i64 - b.abs_tile_y as i64) << TILE_OFFSET_SHIFT)
+        + (a.offset_y - b.offset_y) as i64;
+
+    (world.tile_side_in_meters / TILE_OFFSET_ONE as f32) * v2(d_x as f32, d_y as f32)
+}
+
+fn centered_tile_point(abs_tile_x: i32, abs_tile_y: i32) -> WorldPosition {
+    WorldPosition {
+        abs_tile_x,
+        abs_tile_y,
+        abs_tile_z: 0,
+        offset_x: 0,
+        offset_y: 0,
+    }
+}
+
+fn get_chunk_position_for(world: &World, abs_tile_x: i32, abs_tile_y: i32) -> TileChunkPosition {
+    // NOTE: The arithmetic shift rounds toward negative infinity, so tile -1
+    // lands in chunk -1 and the mask gives its last tile
     let result = TileChunkPosition {
         tile_chunk_x: abs_tile_x >> world.chunk_shift,
         tile_chunk_y: abs_tile_y >> world.chunk_shift,
 
-        rel_tile_x: abs_tile_x & world.chunk_mask,
-        rel_tile_y: abs_tile_y & world.chunk_mask,
+        rel_tile_x: (abs_tile_x as u32) & world.chunk_mask,
+        rel_tile_y: (abs_tile_y as u32) & world.chunk_mask,
     };
 
     result
 }
 
-fn get_tile_value(world: &World, abs_tile_x: u32, abs_tile_y: u32) -> u32 {
+// NOTE: Returns None for tiles outside of every tile chunk
+fn get_tile_value(world: &World, abs_tile_x: i32, abs_tile_y: i32) -> Option<u32> {
     let mut empty = false;
 
     let chunk_pos = get_chunk_position_for(world, abs_tile_x, abs_tile_y);
     // let tile_map = get_tile_map(world, can_pos.tile_map_x, can_pos.tile_map_y);
-    let tile_map = get_tile_chunk(
-        world,
-        chunk_pos.tile_chunk_x as i32,
-        chunk_pos.tile_chunk_y as i32,
-    );
+    let tile_map = get_tile_chunk(world, chunk_pos.tile_chunk_x, chunk_pos.tile_chunk_y);
     let tile_chunk_value =
         get_tile_chunk_value(world, tile_map, chunk_pos.rel_tile_x, chunk_pos.rel_tile_y);
 
@@ -310,8 +468,9 @@ fn get_tile_value(world: &World, abs_tile_x: u32, abs_tile_y: u32) -> u32 {
 }
 
 fn is_world_point_empty(world: &World, pos: WorldPosition) -> bool {
+    // NOTE: The edge of the world counts as solid
     let tile_value = get_tile_value(world, pos.abs_tile_x, pos.abs_tile_y);
-    return tile_value == 0;
+    return tile_value == Some(0);
 }
 
 fn create_tilemap() -> [[u32; TILE_MAP_COUNT_X as usize]; TILE_MAP_COUNT_Y as usize] {
@@ -401,15 +560,137 @@ fn create_tilemap() -> [[u32; TILE_MAP_COUNT_X as usize]; TILE_MAP_COUNT_Y as us
     tiles
 }
 
+fn get_entity(game_state: &GameState, entity_index: usize) -> Option<&Entity> {
+    if entity_index > 0 && entity_index < game_state.entities.len() {
+        let entity = &game_state.entities[entity_index];
+        if entity.exists {
+            return Some(entity);
+        }
+    }
+
+    None
+}
+
+fn add_player(game_state: &mut GameState) -> Option<usize> {
+    let entity_index = (1..game_state.entities.len())
+        .find(|&entity_index| !game_state.entities[entity_index].exists)?;
+
+    let height: f32 = 1.4;
+    let entity = &mut game_state.entities[entity_index];
+    entity.exists = true;
+    entity.p = centered_tile_point(3, 3);
+    entity.height = height;
+    entity.width = 0.75 * height;
+    entity.facing_direction = FACING_FRONT;
+
+    if get_entity(game_state, game_state.camera_following_entity_index).is_none() {
+        game_state.camera_following_entity_index = entity_index;
+    }
+
+    Some(entity_index)
+}
+
+fn remove_entity(game_state: &mut GameState, entity_index: usize) {
+    game_state.entities[entity_index].exists = false;
+
+    if game_state.camera_following_entity_index == entity_index {
+        game_state.camera_following_entity_index = (1..game_state.entities.len())
+            .find(|&other_index| game_state.entities[other_index].exists)
+            .unwrap_or(0);
+    }
+}
+
+// NOTE: Whichever axis the hero moves along most wins, standing still
+// keeps the last direction
+fn facing_direction_for(delta: V2, current: usize) -> usize {
+    if delta.x == 0.0 && delta.y == 0.0 {
+        current
+    } else if delta.x.abs() > delta.y.abs() {
+        if delta.x > 0.0 {
+            FACING_RIGHT
+        } else {
+            FACING_LEFT
+        }
+    } else if delta.y > 0.0 {
+        FACING_BACK
+    } else {
+        FACING_FRONT
+    }
+}
+
+fn move_player(world: &World, entity: &mut Entity, delta: V2) {
+    entity.facing_direction = facing_direction_for(delta, entity.facing_direction);
+
+    let new_player_p = offset(world, entity.p, delta);
+    let player_left = offset(world, new_player_p, v2(-0.5 * entity.width, 0.0));
+    let player_right = offset(world, new_player_p, v2(0.5 * entity.width, 0.0));
+
+    if is_world_point_empty(world, new_player_p)
+        && is_world_point_empty(world, player_left)
+        && is_world_point_empty(world, player_right)
+    {
+        entity.p = new_player_p;
+    }
+}
+
+fn was_pressed(button: &GameButtonState) -> bool {
+    button.half_transition_count > 1 || (button.half_transition_count == 1 && button.ended_down)
+}
+
+fn music_for_room(world: &World, p: WorldPosition) -> Option<&'static str> {
+    let room_x = p.abs_tile_x.div_euclid(ROOM_TILES_X);
+    let room_y = p.abs_tile_y.div_euclid(ROOM_TILES_Y);
+
+    world
+        .room_music
+        .iter()
+        .find(|room| room.room_x == room_x && room.room_y == room_y && room.room_z == p.abs_tile_z)
+        .map(|room| room.track)
+}
+
+fn update_camera(world: &World, game_state: &mut GameState, dt: f32) {
+    let Some(camera_entity) = get_entity(game_state, game_state.camera_following_entity_index)
+    else {
+        return;
+    };
+    let target_p = camera_entity.p;
+
+    match game_state.camera_mode {
+        CameraMode::SmoothFollow => {
+            let diff = subtract(world, target_p, game_state.camera_p);
+
+            let t = 1.0 - (-CAMERA_FOLLOW_RATE * dt).exp();
+            game_state.camera_p = offset(world, game_state.camera_p, t * diff);
+            // NOTE: Floors don't blend, the camera is on the hero's floor
+            game_state.camera_p.abs_tile_z = target_p.abs_tile_z;
+        }
+        CameraMode::RoomSnap => {
+            let room_x = target_p.abs_tile_x.div_euclid(ROOM_TILES_X);
+            let room_y = target_p.abs_tile_y.div_euclid(ROOM_TILES_Y);
+
+            game_state.camera_p = WorldPosition {
+                abs_tile_z: target_p.abs_tile_z,
+                ..centered_tile_point(
+                    room_x * ROOM_TILES_X + ROOM_TILES_X / 2,
+                    room_y * ROOM_TILES_Y + ROOM_TILES_Y / 2,
+                )
+            };
+        }
+    }
+}
+
 pub fn game_update_and_render(
     memory: &mut GameMemory,
     input: &GameInput,
     buffer: &mut GameOffscreenBuffer,
 ) {
-    if memory.permanent_storage.len() < mem::size_of::<GameState>() {
-        memory
-            .permanent_storage
-            .resize(mem::size_of::<GameState>(), 0);
+    let permanent_storage_size = max(memory.permanent_storage_size, mem::size_of::<GameState>());
+    let transient_storage_size = max(
+        memory.transient_storage_size,
+        mem::size_of::<TransientState>(),
+    );
+    if memory.permanent_storage.len() < permanent_storage_size {
+        memory.permanent_storage.resize(permanent_storage_size, 0);
     }
 
     // Define the tile maps
@@ -435,10 +716,28 @@ pub fn game_update_and_render(
         meters_to_pixels: TILE_SIDE_IN_PIXELS as f32 / TILE_SIDE_IN_METERS,
 
         tile_chunks: vec![tile_chunk],
-    };
 
-    let player_height: f32 = 1.4;
-    let player_width = 0.75 * player_height;
+        room_music: vec![
+            RoomMusic {
+                room_x: 0,
+                room_y: 0,
+                room_z: 0,
+                track: "test/music_test.wav",
+            },
+            RoomMusic {
+                room_x: 1,
+                room_y: 0,
+                room_z: 0,
+                track: "test/music_room_1.wav",
+            },
+            RoomMusic {
+                room_x: 0,
+                room_y: 1,
+                room_z: 0,
+                track: "test/music_room_2.wav",
+            },
+        ],
+    };
 
     let _lower_left_x = -world.tile_side_in_pixels as f32 / 2.0;
     let _lower_left_y = -buffer.height;
@@ -448,130 +747,886 @@ pub fn game_update_and_render(
     let game_state = unsafe { &mut *game_state_ptr };
 
     if !memory.is_initialized {
-        game_state.player_p.abs_tile_x = 3;
-        game_state.player_p.abs_tile_y = 3;
-        game_state.player_p.tile_rel_x = 5.0;
-        game_state.player_p.tile_rel_y = 5.0;
+        initialize_arena(
+            &mut game_state.world_arena,
+            memory.permanent_storage.len() - mem::size_of::<GameState>(),
+            unsafe { game_state_ptr.add(1) as *mut u8 },
+        );
+
+        if let Some(read_file_range) = memory.debug_platform_read_file_range {
+            let thread = ThreadContext { placeholder: 0 };
+            if let Ok(assets) = load_asset_pack(
+                &thread,
+                read_file_range,
+                &mut game_state.world_arena,
+                "test/test_assets.hha",
+                ASSET_MEMORY_BUDGET,
+            ) {
+                game_state.assets = assets;
+            }
+        }
+
+        // NOTE: Whatever the pack is missing, or hasn't loaded yet, stays
+        // empty: bitmaps are skipped when drawing, sounds don't play and debug
+        // text falls back to boxes
+        let assets = &game_state.assets;
+        game_state.backdrop = first_bitmap(assets, ASSET_TYPE_BACKDROP);
+        game_state.shadow = first_bitmap(assets, ASSET_TYPE_SHADOW);
+        game_state.debug_font = first_font(assets, ASSET_TYPE_DEBUG_FONT);
+        game_state.bloop = first_sound(assets, ASSET_TYPE_BLOOP);
+        // NOTE: Heroes can join on the very first frame, long before a
+        // streamed load would land, so the bloop loads right here
+        prefetch_sound(&mut game_state.assets, game_state.bloop);
+        update_assets(&mut game_state.assets, &game_state.audio, None);
+
+        // NOTE: Synthesized, so these play even without any sound files
+        let blip_envelope = Envelope {
+            attack: 0.005,
+            decay: 0.03,
+            sustain_level: 0.6,
+            sustain: 0.04,
+            release: 0.05,
+        };
+        let patches = [
+            (
+                &mut game_state.join_whoosh,
+                SynthPatch {
+                    waveform: Waveform::Noise,
+                    start_hz: 2000.0,
+                    end_hz: 9000.0,
+                    envelope: Envelope {
+                        attack: 0.08,
+                        decay: 0.0,
+                        sustain_level: 1.0,
+                        sustain: 0.0,
+                        release: 0.2,
+                    },
+                    volume: 0.15,
+                },
+            ),
+            (
+                &mut game_state.leave_sweep,
+                SynthPatch {
+                    waveform: Waveform::Sine,
+                    start_hz: 660.0,
+                    end_hz: 165.0,
+                    envelope: Envelope {
+                        attack: 0.01,
+                        decay: 0.1,
+                        sustain_level: 0.7,
+                        sustain: 0.15,
+                        release: 0.15,
+                    },
+                    volume: 0.4,
+                },
+            ),
+            (
+                &mut game_state.camera_blip,
+                SynthPatch {
+                    waveform: Waveform::Square,
+                    start_hz: 440.0,
+                    end_hz: 880.0,
+                    envelope: blip_envelope,
+                    volume: 0.1,
+                },
+            ),
+            (
+                &mut game_state.normals_blip,
+                SynthPatch {
+                    waveform: Waveform::Saw,
+                    start_hz: 330.0,
+                    end_hz: 220.0,
+                    envelope: blip_envelope,
+                    volume: 0.15,
+                },
+            ),
+        ];
+        for (sound, patch) in patches {
+            if let Ok(synthesized) = synthesize_sound(&mut game_state.world_arena, &patch) {
+                *sound = synthesized;
+            }
+        }
+
+        // NOTE: Without the shadow art, a soft ellipse a bit wider than the
+        // hero's feet stands in for it
+        if game_state.shadow == BitmapId::default() {
+            if let Ok(mut shadow) = make_shadow_bitmap(&mut game_state.world_arena, 56, 18) {
+                shadow.align_x = shadow.width / 2;
+                shadow.align_y = shadow.height / 2;
+                game_state.fallback_shadow = shadow;
+            }
+        }
+
+        if let Ok(tile_bitmap) = make_solid_bitmap(&mut game_state.world_arena, 0xFFFFFFFF) {
+            game_state.tile_bitmap = tile_bitmap;
+        }
+        if let Ok(normal_map) = make_bevel_normal_map(&mut game_state.world_arena, 32, 32, 5) {
+            game_state.tile_normal_map = normal_map;
+        }
+
+        // NOTE: If there's no room left for the stream buffers the game just
+        // runs without music
+        initialize_music(&mut game_state.music, &mut game_state.world_arena);
+
+        game_state.camera_mode = CameraMode::SmoothFollow;
+        game_state.camera_p = centered_tile_point(ROOM_TILES_X / 2, ROOM_TILES_Y / 2);
+        update_audio_listener(&mut game_state.audio, &world, game_state.camera_p);
 
         memory.is_initialized = true;
     }
 
-    for controller in input.controllers.iter() {
+    for (controller_index, controller) in input.controllers.iter().enumerate() {
+        let entity_index = game_state.player_index_for_controller[controller_index];
+
+        if !controller.is_connected {
+            if entity_index != 0 {
+                play_sound_at(
+                    &mut game_state.audio,
+                    &world,
+                    &game_state.leave_sweep,
+                    game_state.entities[entity_index].p,
+                    1.0,
+                    false,
+                );
+                remove_entity(game_state, entity_index);
+                game_state.player_index_for_controller[controller_index] = 0;
+            }
+            continue;
+        }
+
+        if entity_index == 0 {
+            if controller.buttons[BUTTON_START].ended_down {
+                if let Some(new_index) = add_player(game_state) {
+                    game_state.player_index_for_controller[controller_index] = new_index;
+                    let new_p = game_state.entities[new_index].p;
+                    play_sound_at(
+                        &mut game_state.audio,
+                        &world,
+                        &game_state.join_whoosh,
+                        new_p,
+                        1.0,
+                        false,
+                    );
+                    // NOTE: Each hero joins with its own pitch off the same sample
+                    let bloop = get_sound(&mut game_state.assets, game_state.bloop);
+                    let bloop = bloop.and_then(|bloop| {
+                        play_sound_at(&mut game_state.audio, &world, &bloop, new_p, 0.5, false)
+                    });
+                    if let Some(bloop) = bloop {
+                        let d_sample = 0.8 + 0.1 * (new_index % 5) as f32;
+                        change_pitch(&mut game_state.audio, bloop, d_sample);
+                    }
+                }
+            }
+            continue;
+        }
+
+        if was_pressed(&controller.buttons[BUTTON_RIGHT_SHOULDER]) {
+            game_state.debug_show_normals = !game_state.debug_show_normals;
+            play_sound(
+                &mut game_state.audio,
+                &game_state.normals_blip,
+                1.0,
+                0.0,
+                false,
+            );
+        }
+
+        if was_pressed(&controller.buttons[BUTTON_BACK]) {
+            game_state.camera_mode = match game_state.camera_mode {
+                CameraMode::SmoothFollow => CameraMode::RoomSnap,
+                CameraMode::RoomSnap => CameraMode::SmoothFollow,
+            };
+            play_sound(
+                &mut game_state.audio,
+                &game_state.camera_blip,
+                1.0,
+                0.0,
+                false,
+            );
+        }
+
         if controller.is_analog {
             // Handle analog input
         } else {
             // Digital movement
-            let mut dplayer_x = 0.0;
-            let mut dplayer_y = 0.0;
+            let mut dplayer = V2::default();
 
             if controller.buttons[0].ended_down {
-                dplayer_y = 1.0;
+                dplayer.y = 1.0;
             }
             if controller.buttons[1].ended_down {
-                dplayer_y = -1.0;
+                dplayer.y = -1.0;
             }
             if controller.buttons[2].ended_down {
-                dplayer_x = -1.0;
+                dplayer.x = -1.0;
             }
             if controller.buttons[3].ended_down {
-                dplayer_x = 1.0;
+                dplayer.x = 1.0;
             }
 
-            dplayer_x *= 2.0;
-            dplayer_y *= 2.0;
+            dplayer = 2.0 * dplayer;
 
-            let mut new_player_p: WorldPosition = game_state.player_p;
-            new_player_p.tile_rel_x += input.dt_for_frame * dplayer_x;
-            new_player_p.tile_rel_y += input.dt_for_frame * dplayer_y;
-            new_player_p = recanonicalize_position(&world, new_player_p);
+            move_player(
+                &world,
+                &mut game_state.entities[entity_index],
+                input.dt_for_frame * dplayer,
+            );
+        }
+    }
 
-            let mut player_left = new_player_p;
-            player_left.tile_rel_x -= 0.5 * player_width;
-            player_left = recanonicalize_position(&world, player_left);
+    // NOTE: With several heroes on screen the camera sticks to the one that
+    // joined first; when it leaves, the next hero in the entity list takes over
+    update_camera(&world, game_state, input.dt_for_frame);
+    update_audio_listener(&mut game_state.audio, &world, game_state.camera_p);
+
+    // NOTE: Music plays while anyone is in the game, and follows the camera
+    // from room to room
+    if let Some(read_file_range) = memory.debug_platform_read_file_range {
+        let any_hero = game_state.entities.iter().any(|entity| entity.exists);
+        let track = if any_hero {
+            music_for_room(&world, game_state.camera_p)
+        } else {
+            None
+        };
+
+        let thread = ThreadContext { placeholder: 0 };
+        update_music(
+            &mut game_state.music,
+            &mut game_state.audio,
+            &thread,
+            read_file_range,
+            track,
+            MUSIC_CROSSFADE_SECONDS,
+        );
+    }
+
+    // Render background
+    if memory.transient_storage.len() < transient_storage_size {
+        // NOTE: vec! hands back lazily zeroed pages, resize would touch all of them
+        memory.transient_storage = vec![0; transient_storage_size];
+    }
 
-            let mut player_right = new_player_p;
-            player_right.tile_rel_x += 0.5 * player_width;
-            player_right = recanonicalize_position(&world, player_right);
+    let tran_state_ptr = memory.transient_storage.as_mut_ptr() as *mut TransientState;
+    let tran_state = unsafe { &mut *tran_state_ptr };
+    if !tran_state.is_initialized {
+        initialize_arena(
+            &mut tran_state.tran_arena,
+            memory.transient_storage.len() - mem::size_of::<TransientState>(),
+            unsafe { tran_state_ptr.add(1) as *mut u8 },
+        );
 
-            if is_world_point_empty(&world, new_player_p)
-                && is_world_point_empty(&world, player_left)
-                && is_world_point_empty(&world, player_right)
-            {
-                game_state.player_p = new_player_p;
-            }
-        }
+        tran_state.is_initialized = true;
     }
 
-    // Render background
-    draw_rectangle(
-        buffer,
-        0.0,
-        0.0,
-        buffer.width as f32,
-        buffer.height as f32,
-        1.0,
-        0.0,
-        0.1,
-    );
+    let render_memory = begin_temporary_memory(&tran_state.tran_arena);
+    let mut render_group = allocate_render_group(
+        &mut tran_state.tran_arena,
+        4096,
+        4096,
+        world.meters_to_pixels,
+    )
+    .expect("transient storage is too small for the render group");
+
+    push_clear(&mut render_group, v4(1.0, 0.0, 0.1, 1.0));
+
+    render_group.lighting.sky_color = v3(0.35, 0.35, 0.45);
+    render_group.lighting.ground_color = v3(0.15, 0.12, 0.1);
+    render_group.lighting.debug_show_normals = game_state.debug_show_normals;
+    render_group.font =
+        get_font(&mut game_state.assets, game_state.debug_font).filter(font_is_loaded);
+
+    if let Some(backdrop) = get_bitmap(&mut game_state.assets, game_state.backdrop) {
+        // NOTE: Pinned to the top-left corner of the screen
+        let screen_top_left = v2(
+            -0.5 * buffer.width as f32 / world.meters_to_pixels,
+            0.5 * buffer.height as f32 / world.meters_to_pixels,
+        );
+        push_bitmap(
+            &mut render_group,
+            &backdrop,
+            screen_top_left,
+            1.0,
+            LAYER_BACKDROP,
+        );
+    }
 
     // Render tiles
-    let center_x = 0.5 * buffer.width as f32;
-    let center_y = 0.5 * buffer.height as f32;
+    let camera_p = game_state.camera_p;
+    let highlight_p =
+        get_entity(game_state, game_state.camera_following_entity_index).map(|entity| entity.p);
+    let tile_dim = v2(world.tile_side_in_meters, world.tile_side_in_meters);
 
     for rel_row in -10..10 {
         for rel_column in -20..20 {
-            let column = game_state.player_p.abs_tile_x as i32 + rel_column;
-            let row = game_state.player_p.abs_tile_y as i32 + rel_row;
-            let tile_id = get_tile_value(&world, column as u32, row as u32);
+            let column = camera_p.abs_tile_x.wrapping_add(rel_column);
+            let row = camera_p.abs_tile_y.wrapping_add(rel_row);
+            let Some(tile_id) = get_tile_value(&world, column, row) else {
+                continue;
+            };
             let mut gray: f32 = 0.5;
 
             if tile_id == 1 {
                 gray = 1.0;
             }
 
-            if column as u32 == game_state.player_p.abs_tile_x
-                && row as u32 == game_state.player_p.abs_tile_y
-            {
+            if highlight_p.is_some_and(|highlight_p| {
+                column == highlight_p.abs_tile_x && row == highlight_p.abs_tile_y
+            }) {
                 gray = 0.0;
             }
 
-            let min_x = center_x + (rel_column * world.tile_side_in_pixels) as f32;
-            let min_y = center_y - (rel_row * world.tile_side_in_pixels) as f32;
+            let tile_center = subtract(&world, centered_tile_point(column, row), camera_p);
+            let color = v4(gray, gray, gray, 1.0);
 
-            let max_x = min_x + world.tile_side_in_pixels as f32;
-            let max_y = min_y - world.tile_side_in_pixels as f32;
-
-            draw_rectangle(buffer, min_x, max_y, max_x, min_y, gray, gray, gray);
+            // NOTE: Only the walls have bevels to catch the light, the floor
+            // stays on the plain rectangle fill
+            if tile_id == 1 {
+                push_textured_quad(
+                    &mut render_group,
+                    TexturedQuad {
+                        origin: tile_center - 0.5 * tile_dim,
+                        x_axis: v2(tile_dim.x, 0.0),
+                        y_axis: v2(0.0, tile_dim.y),
+                        color,
+                        bitmap: game_state.tile_bitmap,
+                        normal_map: Some(game_state.tile_normal_map),
+                    },
+                    LAYER_TILES,
+                );
+            } else {
+                push_rectangle(&mut render_group, tile_center, tile_dim, color, LAYER_TILES);
+            }
         }
     }
 
-    // Render player
-    let player_r = 1.0;
-    let player_g = 1.0;
-    let player_b = 0.0;
+    // Render players
+    let player_color = v4(1.0, 1.0, 0.0, 1.0);
+
+    for entity in game_state.entities.iter().skip(1) {
+        if !entity.exists {
+            continue;
+        }
+
+        let diff = subtract(&world, entity.p, camera_p);
+
+        let shadow = if game_state.shadow == BitmapId::default() {
+            Some(game_state.fallback_shadow)
+        } else {
+            get_bitmap(&mut game_state.assets, game_state.shadow)
+        };
+        if let Some(shadow) = shadow.filter(|shadow| shadow.width > 0) {
+            push_bitmap(&mut render_group, &shadow, diff, 0.6, LAYER_SHADOWS);
+        }
 
-    let player_left = center_x + world.meters_to_pixels * game_state.player_p.tile_rel_x
-        - 0.5 * player_width * world.meters_to_pixels;
+        // NOTE: Every hero carries a lantern a little above their head
+        push_light(
+            &mut render_group,
+            PointLight {
+                p: diff,
+                height: 1.2 * entity.height,
+                color: v3(1.0, 0.85, 0.6),
+                radius: 8.0,
+            },
+        );
+
+        // NOTE: Layers at the same spot keep their push order, so the head
+        // ends up on top of the cape on top of the torso. They all share the
+        // alignment point at the hero's feet. Art for the right facing wins
+        // over art for the right height.
+        let mut match_vector = AssetVector::default();
+        let mut weight_vector = AssetVector::default();
+        match_vector.e[ASSET_TAG_FACING_DIRECTION as usize] = entity.facing_direction as f32;
+        weight_vector.e[ASSET_TAG_FACING_DIRECTION as usize] = 10.0;
+        match_vector.e[ASSET_TAG_HEIGHT as usize] = entity.height;
+        weight_vector.e[ASSET_TAG_HEIGHT as usize] = 1.0;
+        let layers = [
+            ASSET_TYPE_HERO_TORSO,
+            ASSET_TYPE_HERO_CAPE,
+            ASSET_TYPE_HERO_HEAD,
+        ]
+        .map(|type_id| {
+            best_match_bitmap(&game_state.assets, type_id, &match_vector, &weight_vector)
+        });
+        if layers.iter().all(|&layer| layer == BitmapId::default()) {
+            // NOTE: No hero art at all, so the hero doesn't go invisible
+            push_rectangle(
+                &mut render_group,
+                diff + v2(0.0, 0.5 * entity.height),
+                v2(entity.width, entity.height),
+                player_color,
+                LAYER_ENTITIES,
+            );
+            continue;
+        }
+        // NOTE: Art still loading just isn't drawn for those few frames
+        for layer in layers {
+            if let Some(layer) = get_bitmap(&mut game_state.assets, layer) {
+                push_bitmap(&mut render_group, &layer, diff, 1.0, LAYER_ENTITIES);
+            }
+        }
+    }
 
-    let player_top = center_y
-        - world.meters_to_pixels * game_state.player_p.tile_rel_y
-        - world.meters_to_pixels * player_height;
+    let camera_mode_name = match game_state.camera_mode {
+        CameraMode::SmoothFollow => "camera: smooth follow",
+        CameraMode::RoomSnap => "camera: room snap",
+    };
+    let asset_stats = get_asset_load_stats(&game_state.assets);
+    let debug_text = format!(
+        "{}\nassets: {} loaded, {} loading, {} unloaded, {} failed, {:.1} of {:.1} MB",
+        camera_mode_name,
+        asset_stats.loaded,
+        asset_stats.queued,
+        asset_stats.unloaded,
+        asset_stats.failed,
+        asset_stats.memory_used as f32 / (1024.0 * 1024.0),
+        asset_stats.memory_budget as f32 / (1024.0 * 1024.0),
+    );
+    push_text(
+        &mut render_group,
+        v2(10.0, 10.0),
+        &debug_text,
+        1.0,
+        v4(1.0, 1.0, 1.0, 1.0),
+        None,
+        LAYER_DEBUG,
+    );
 
-    draw_rectangle(
-        buffer,
-        player_left,
-        player_top,
-        player_left + world.meters_to_pixels * player_width,
-        player_top + world.meters_to_pixels * player_height,
-        player_r,
-        player_g,
-        player_b,
+    match &memory.high_priority_queue {
+        Some(queue) => tiled_render_group_to_output(queue, &mut render_group, buffer),
+        None => render_group_to_output(&mut render_group, buffer),
+    }
+    end_temporary_memory(&mut tran_state.tran_arena, render_memory);
+
+    // NOTE: Last, once nothing fetched this frame is still being drawn
+    if let (Some(get_file_write_time), Some(read_entire_file), Some(log)) = (
+        memory.debug_platform_get_file_write_time,
+        memory.debug_platform_read_entire_file,
+        memory.debug_platform_log,
+    ) {
+        let thread = ThreadContext { placeholder: 0 };
+        reload_changed_assets(
+            &mut game_state.assets,
+            &game_state.audio,
+            &thread,
+            get_file_write_time,
+            read_entire_file,
+            log,
+        );
+    }
+    update_assets(
+        &mut game_state.assets,
+        &game_state.audio,
+        memory.low_priority_queue.as_deref(),
     );
 }
 
-fn game_get_sound_samples(memory: &mut GameMemory, sound_buffer: &mut GameSoundOutputBuffer) {
+pub fn game_get_sound_samples(memory: &mut GameMemory, sound_buffer: &mut GameSoundOutputBuffer) {
+    // NOTE: Nothing to mix until the first update has set up both storages
+    if !memory.is_initialized || memory.transient_storage.len() < mem::size_of::<TransientState>() {
+        sound_buffer.samples.fill(0);
+        return;
+    }
+
     let game_state_ptr = memory.permanent_storage.as_mut_ptr() as *mut GameState;
     let game_state = unsafe { &mut *game_state_ptr };
+    let tran_state_ptr = memory.transient_storage.as_mut_ptr() as *mut TransientState;
+    let tran_state = unsafe { &mut *tran_state_ptr };
+
+    output_playing_sounds(
+        &mut game_state.audio,
+        sound_buffer,
+        &mut tran_state.tran_arena,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::asset::tests::{pack_file, PackAsset};
+    use super::sound::tests::wav_file;
+    use super::*;
+
+    pub(super) fn test_world() -> World {
+        World {
+            chunk_shift: 8,
+            chunk_mask: (1 << 8) - 1,
+            chunk_dim: 256,
+
+            tile_side_in_meters: 1.4,
+            tile_side_in_pixels: 60,
+            meters_to_pixels: 60.0 / 1.4,
+
+            tile_chunk_count_x: 0,
+            tile_chunk_count_y: 0,
 
-    game_output_sound(game_state, sound_buffer, 400);
+            tile_chunks: Vec::new(),
+
+            room_music: Vec::new(),
+        }
+    }
+
+    // NOTE: The arena's memory only lives as long as the call
+    pub(super) fn with_arena<R>(size: usize, f: impl FnOnce(&mut MemoryArena) -> R) -> R {
+        let mut storage = vec![0u8; size];
+        let mut arena = MemoryArena {
+            size: 0,
+            base: std::ptr::null_mut(),
+            used: 0,
+        };
+        initialize_arena(&mut arena, storage.len(), storage.as_mut_ptr());
+
+        f(&mut arena)
+    }
+
+    fn is_canonical_offset(tile_offset: i32) -> bool {
+        (-TILE_OFFSET_HALF..TILE_OFFSET_HALF).contains(&tile_offset)
+    }
+
+    fn absolute_units(tile: i32, tile_offset: i32) -> i64 {
+        ((tile as i64) << TILE_OFFSET_SHIFT) + tile_offset as i64
+    }
+
+    // NOTE: xorshift64*, good enough to drive property tests deterministically
+    pub(super) struct Series(pub u64);
+
+    impl Series {
+        pub fn next_u32(&mut self) -> u32 {
+            self.0 ^= self.0 >> 12;
+            self.0 ^= self.0 << 25;
+            self.0 ^= self.0 >> 27;
+            (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 32) as u32
+        }
+
+        pub fn between(&mut self, min: i32, max: i32) -> i32 {
+            let range = (max as i64 - min as i64 + 1) as u64;
+            (min as i64 + (self.next_u32() as u64 % range) as i64) as i32
+        }
+
+        pub fn unilateral(&mut self) -> f32 {
+            self.next_u32() as f32 / u32::MAX as f32
+        }
+
+        pub fn bilateral(&mut self) -> f32 {
+            2.0 * self.unilateral() - 1.0
+        }
+    }
+
+    #[test]
+    fn recanonicalize_every_offset_across_tile_edges() {
+        let one = TILE_OFFSET_ONE as i64;
+        let half = TILE_OFFSET_HALF as i64;
+        let deltas = [
+            0,
+            1,
+            -1,
+            half - 1,
+            half,
+            half + 1,
+            -half + 1,
+            -half,
+            -half - 1,
+            one - 1,
+            one,
+            one + 1,
+            -one + 1,
+            -one,
+            -one - 1,
+            3 * one + half,
+            -3 * one - half,
+        ];
+
+        for start_tile in [-257, -256, -1, 0, 1, 255, 256] {
+            for start_offset in -TILE_OFFSET_HALF..TILE_OFFSET_HALF {
+                for delta in deltas {
+                    let mut tile = start_tile;
+                    let mut tile_offset = start_offset;
+                    recanonicalize_coord(&mut tile, &mut tile_offset, delta);
+
+                    assert!(is_canonical_offset(tile_offset));
+                    assert_eq!(
+                        absolute_units(tile, tile_offset),
+                        absolute_units(start_tile, start_offset) + delta
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn recanonicalize_clamps_at_the_ends_of_the_tile_range() {
+        let mut tile = i32::MAX;
+        let mut tile_offset = TILE_OFFSET_HALF - 1;
+        recanonicalize_coord(&mut tile, &mut tile_offset, 1);
+        assert_eq!(tile, i32::MAX);
+        assert!(is_canonical_offset(tile_offset));
+
+        let mut tile = i32::MIN;
+        let mut tile_offset = -TILE_OFFSET_HALF;
+        recanonicalize_coord(&mut tile, &mut tile_offset, -1);
+        assert_eq!(tile, i32::MIN);
+        assert!(is_canonical_offset(tile_offset));
+    }
+
+    #[test]
+    fn offset_lands_on_tile_edges_in_meters() {
+        let world = test_world();
+        let origin = centered_tile_point(0, 0);
+
+        for tile_count in -20..=20 {
+            let edge = (tile_count as f32 + 0.5) * world.tile_side_in_meters;
+            // NOTE: A tenth of a millimeter is several offset units, so which
+            // side of the edge it lands on is exact
+            for (nudge, past_edge) in [(-1.0e-4, false), (1.0e-4, true)] {
+                let p = offset(&world, origin, v2(edge + nudge, -(edge + nudge)));
+                let expected = if past_edge {
+                    tile_count + 1
+                } else {
+                    tile_count
+                };
+
+                assert!(is_canonical_offset(p.offset_x));
+                assert!(is_canonical_offset(p.offset_y));
+                assert_eq!(p.abs_tile_x, expected);
+                // NOTE: Mirrored, the tile past the edge is one further down
+                let expected_y = if past_edge {
+                    -tile_count - 1
+                } else {
+                    -tile_count
+                };
+                assert_eq!(p.abs_tile_y, expected_y);
+            }
+
+            // NOTE: Closer than an offset unit to the edge either side will do
+            for nudge in [-f32::EPSILON, 0.0, f32::EPSILON] {
+                let p = offset(&world, origin, v2(edge + nudge, -(edge + nudge)));
+
+                assert!(is_canonical_offset(p.offset_x));
+                assert!(is_canonical_offset(p.offset_y));
+                assert!((p.abs_tile_x - tile_count).abs() <= 1);
+                assert!((p.abs_tile_y + tile_count).abs() <= 1);
+            }
+        }
+    }
+
+    #[test]
+    fn offset_and_subtract_agree_for_random_positions() {
+        let world = test_world();
+        let unit_in_meters = world.tile_side_in_meters / TILE_OFFSET_ONE as f32;
+        let mut series = Series(0x1234_5678_9abc_def0);
+
+        for _ in 0..100_000 {
+            let p = WorldPosition {
+                abs_tile_x: series.between(-1_000_000, 1_000_000),
+                abs_tile_y: series.between(-1_000_000, 1_000_000),
+                abs_tile_z: 0,
+                offset_x: series.between(-TILE_OFFSET_HALF, TILE_OFFSET_HALF - 1),
+                offset_y: series.between(-TILE_OFFSET_HALF, TILE_OFFSET_HALF - 1),
+            };
+            let delta = 50.0 * v2(series.bilateral(), series.bilateral());
+
+            let q = offset(&world, p, delta);
+            assert!(is_canonical_offset(q.offset_x));
+            assert!(is_canonical_offset(q.offset_y));
+
+            let tolerance = unit_in_meters + 1.0e-5;
+            let d = subtract(&world, q, p);
+            assert!((d.x - delta.x).abs() <= tolerance, "{:?} {:?}", d, delta);
+            assert!((d.y - delta.y).abs() <= tolerance, "{:?} {:?}", d, delta);
+
+            let back = subtract(&world, p, q);
+            assert_eq!(back, -d);
+
+            let round_trip = offset(&world, q, -delta);
+            assert!((subtract(&world, round_trip, p).x).abs() <= 2.0 * unit_in_meters);
+            assert!((subtract(&world, round_trip, p).y).abs() <= 2.0 * unit_in_meters);
+        }
+    }
+
+    #[test]
+    fn tiles_left_of_and_below_the_origin_land_in_negative_chunks() {
+        let world = test_world();
+
+        let p = get_chunk_position_for(&world, -1, -1);
+        assert_eq!((p.tile_chunk_x, p.tile_chunk_y), (-1, -1));
+        assert_eq!(
+            (p.rel_tile_x, p.rel_tile_y),
+            (world.chunk_mask, world.chunk_mask)
+        );
+
+        let p = get_chunk_position_for(&world, -(world.chunk_dim as i32), 0);
+        assert_eq!((p.tile_chunk_x, p.tile_chunk_y), (-1, 0));
+        assert_eq!((p.rel_tile_x, p.rel_tile_y), (0, 0));
+
+        let p = get_chunk_position_for(&world, -(world.chunk_dim as i32) - 1, 0);
+        assert_eq!((p.tile_chunk_x, p.rel_tile_x), (-2, world.chunk_mask));
+    }
+
+    #[test]
+    fn movement_past_the_origin_stops_at_the_edge_of_the_world() {
+        // NOTE: One open chunk at (0, 0), so tile -1 is off the world
+        let mut world = test_world();
+        world.tile_chunk_count_x = 1;
+        world.tile_chunk_count_y = 1;
+        world.tile_chunks = vec![TileChunk {
+            tiles: vec![0; (world.chunk_dim * world.chunk_dim) as usize],
+        }];
+
+        let start = centered_tile_point(0, 5);
+        let mut entity = Entity {
+            exists: true,
+            p: start,
+            width: 0.5,
+            height: 1.0,
+            facing_direction: FACING_FRONT,
+        };
+
+        // NOTE: The step itself lands on tile -1 rather than wrapping
+        let step = v2(-world.tile_side_in_meters, 0.0);
+        assert_eq!(offset(&world, start, step).abs_tile_x, -1);
+        assert_eq!(get_tile_value(&world, -1, 5), None);
+
+        move_player(&world, &mut entity, step);
+        assert_eq!(entity.p.abs_tile_x, 0);
+        assert_eq!(entity.p.offset_x, 0);
+
+        // NOTE: Same going down past row 0
+        entity.p = centered_tile_point(5, 0);
+        move_player(&world, &mut entity, v2(0.0, -world.tile_side_in_meters));
+        assert_eq!((entity.p.abs_tile_x, entity.p.abs_tile_y), (5, 0));
+    }
+
+    #[test]
+    fn facing_direction_follows_the_dominant_axis() {
+        assert_eq!(
+            facing_direction_for(v2(1.0, 0.0), FACING_FRONT),
+            FACING_RIGHT
+        );
+        assert_eq!(
+            facing_direction_for(v2(-1.0, 0.5), FACING_FRONT),
+            FACING_LEFT
+        );
+        assert_eq!(
+            facing_direction_for(v2(0.5, 1.0), FACING_FRONT),
+            FACING_BACK
+        );
+        assert_eq!(
+            facing_direction_for(v2(0.0, -1.0), FACING_RIGHT),
+            FACING_FRONT
+        );
+
+        // NOTE: Diagonals favor the vertical art, stopping keeps the last one
+        assert_eq!(
+            facing_direction_for(v2(1.0, -1.0), FACING_RIGHT),
+            FACING_FRONT
+        );
+        assert_eq!(facing_direction_for(v2(0.0, 0.0), FACING_LEFT), FACING_LEFT);
+    }
+
+    fn read_bloop_pack(
+        _thread: &ThreadContext,
+        filename: &str,
+        offset: u64,
+        dest: &mut [u8],
+    ) -> usize {
+        if filename != "test/test_assets.hha" {
+            return 0;
+        }
+        let loud: Vec<u8> = (0..4800).flat_map(|_| 20000i16.to_le_bytes()).collect();
+        let pack = pack_file(&[PackAsset {
+            type_id: ASSET_TYPE_BLOOP,
+            kind: ASSET_KIND_SOUND,
+            tags: Vec::new(),
+            align: (0, 0),
+            data: wav_file(48000, 1, 16, &loud),
+            source_path: "",
+            source_write_time: 0,
+        }]);
+        let start = (offset as usize).min(pack.len());
+        let count = dest.len().min(pack.len() - start);
+        dest[..count].copy_from_slice(&pack[start..start + count]);
+        count
+    }
+
+    // NOTE: What the game mixes right after a hero joins on its first frame
+    fn first_frame_samples(read_file_range: Option<DebugPlatformReadFileRange>) -> Vec<i16> {
+        let mut memory = GameMemory {
+            permanent_storage_size: 64 * 1024 * 1024,
+            transient_storage_size: 64 * 1024 * 1024,
+            debug_platform_read_file_range: read_file_range,
+            ..Default::default()
+        };
+        let mut input = GameInput {
+            dt_for_frame: 1.0 / 30.0,
+            controllers: [GameControllerInput {
+                is_connected: false,
+                is_analog: false,
+                stick_average_x: 0.0,
+                stick_average_y: 0.0,
+                buttons: [GameButtonState {
+                    half_transition_count: 0,
+                    ended_down: false,
+                }; 12],
+            }; 5],
+        };
+        input.controllers[0].is_connected = true;
+        input.controllers[0].buttons[BUTTON_START] = GameButtonState {
+            half_transition_count: 1,
+            ended_down: true,
+        };
+        let mut buffer = GameOffscreenBuffer {
+            memory: vec![0; 64 * 64 * 4],
+            width: 64,
+            height: 64,
+            pitch: 64 * 4,
+            bytes_per_pixel: 4,
+        };
+        game_update_and_render(&mut memory, &input, &mut buffer);
+
+        let mut samples = vec![0i16; 2 * 1600];
+        let mut sound_buffer = GameSoundOutputBuffer {
+            samples_per_second: 48000,
+            sample_count: 1600,
+            samples: &mut samples,
+        };
+        game_get_sound_samples(&mut memory, &mut sound_buffer);
+        samples
+    }
+
+    #[test]
+    fn a_hero_joining_on_the_first_frame_bloops() {
+        let with_bloop = first_frame_samples(Some(read_bloop_pack));
+        let without_bloop = first_frame_samples(None);
+
+        // NOTE: Everything else that plays is synthesized the same both times
+        let loudest = with_bloop
+            .iter()
+            .zip(&without_bloop)
+            .map(|(&a, &b)| (a as i32 - b as i32).abs())
+            .max();
+        assert!(loudest.unwrap() > 1000, "{loudest:?}");
+    }
+
+    #[test]
+    fn smooth_follow_does_not_depend_on_frame_rate() {
+        let world = test_world();
+        let follow = |frame_count: usize| {
+            // NOTE: Zeroed the way the game state is, which is smooth follow
+            let mut game_state: Box<GameState> = unsafe { Box::new(mem::zeroed()) };
+            game_state.entities[1].exists = true;
+            game_state.entities[1].p = centered_tile_point(10, 4);
+            game_state.camera_following_entity_index = 1;
+            game_state.camera_p = centered_tile_point(2, 4);
+            for _ in 0..frame_count {
+                update_camera(&world, &mut game_state, 0.25 / frame_count as f32);
+            }
+            subtract(&world, game_state.entities[1].p, game_state.camera_p).x
+        };
+
+        // NOTE: A quarter second at 8 per second leaves e^-2 of the distance,
+        // even when one frame takes all of it
+        let expected = 8.0 * world.tile_side_in_meters * (-2.0f32).exp();
+        for frame_count in [1, 8, 60] {
+            assert!((follow(frame_count) - expected).abs() < 0.01);
+        }
+    }
 }
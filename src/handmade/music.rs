@@ -0,0 +1,365 @@
+use std::slice;
+
+use super::audio::*;
+use super::memory::*;
+use super::sound::*;
+use super::{DebugPlatformReadFileRange, ThreadContext};
+
+// NOTE: Samples per chunk at the track's own rate; a chunk has to outlast
+// the most the platform ever mixes between two updates, since the next one
+// is only read in on the update after the previous one starts playing
+pub const MUSIC_CHUNK_SAMPLE_COUNT: u32 = 1 << 16;
+const MUSIC_CHUNK_COUNT: usize = 2;
+// NOTE: One track fading in and one fading out
+const MUSIC_STREAM_COUNT: usize = 2;
+const MAX_MUSIC_FILENAME_LENGTH: usize = 64;
+
+const MUSIC_VOLUME: f32 = 0.5;
+
+// NOTE: A track being read off disk into a pair of chunk buffers; one plays
+// while the other is queued behind it, and whichever has finished playing
+// gets the next piece of the file
+#[derive(Clone, Copy)]
+struct MusicStream {
+    filename: [u8; MAX_MUSIC_FILENAME_LENGTH],
+    filename_length: usize,
+    wav: WavStream,
+    // NOTE: Where in the track the next chunk starts
+    next_sample: u32,
+
+    chunks: [LoadedSound; MUSIC_CHUNK_COUNT],
+    next_chunk: usize,
+    id: PlayingSoundId,
+}
+
+// NOTE: Lives in the game state, so all zeroes has to mean no music and no
+// buffers yet
+#[derive(Clone, Copy)]
+pub struct MusicState {
+    streams: [MusicStream; MUSIC_STREAM_COUNT],
+    current_stream: usize,
+
+    // NOTE: The track asked for last, even if it failed to open, so a
+    // missing file isn't looked for again every frame
+    track: [u8; MAX_MUSIC_FILENAME_LENGTH],
+    track_length: usize,
+
+    staging: *mut u8,
+}
+
+const MUSIC_STAGING_SIZE: usize = MUSIC_CHUNK_SAMPLE_COUNT as usize * MAX_WAV_FRAME_SIZE;
+
+// NOTE: Everything streaming needs is set aside up front, so playing music
+// never allocates; returns false if the arena is too small, and music stays off
+pub fn initialize_music(music: &mut MusicState, arena: &mut MemoryArena) -> bool {
+    let Some(staging) = push_array::<u8>(arena, MUSIC_STAGING_SIZE) else {
+        return false;
+    };
+
+    for stream in music.streams.iter_mut() {
+        for chunk in stream.chunks.iter_mut() {
+            for channel in chunk.samples.iter_mut() {
+                let Some(samples) = push_array::<i16>(arena, MUSIC_CHUNK_SAMPLE_COUNT as usize)
+                else {
+                    return false;
+                };
+                *channel = samples;
+            }
+        }
+    }
+
+    music.staging = staging;
+    true
+}
+
+fn stream_filename(stream: &MusicStream) -> &str {
+    std::str::from_utf8(&stream.filename[..stream.filename_length]).unwrap_or("")
+}
+
+// NOTE: Wraps back to the start of the track at the end, tracks always loop
+fn read_next_chunk(
+    thread: &ThreadContext,
+    read_file_range: DebugPlatformReadFileRange,
+    staging: &mut [u8],
+    stream: &mut MusicStream,
+) -> Option<LoadedSound> {
+    let mut chunk = stream.chunks[stream.next_chunk];
+    let sample_count = read_wav_stream(
+        thread,
+        read_file_range,
+        stream_filename(stream),
+        &stream.wav,
+        stream.next_sample,
+        staging,
+        &mut chunk,
+    );
+    if sample_count == 0 {
+        return None;
+    }
+
+    stream.next_sample += sample_count;
+    // NOTE: A short read means the file ended before its header said it would
+    if sample_count < MUSIC_CHUNK_SAMPLE_COUNT || stream.next_sample >= stream.wav.sample_count {
+        stream.next_sample = 0;
+    }
+
+    stream.chunks[stream.next_chunk] = chunk;
+    stream.next_chunk = (stream.next_chunk + 1) % MUSIC_CHUNK_COUNT;
+
+    Some(chunk)
+}
+
+fn start_stream(
+    audio: &mut AudioState,
+    thread: &ThreadContext,
+    read_file_range: DebugPlatformReadFileRange,
+    staging: &mut [u8],
+    stream: &mut MusicStream,
+    filename: &str,
+    fade_duration: f32,
+) -> Option<PlayingSoundId> {
+    stream.filename_length = 0;
+    stream.wav = open_wav_stream(thread, read_file_range, filename).ok()?;
+    stream.filename[..filename.len()].copy_from_slice(filename.as_bytes());
+    stream.filename_length = filename.len();
+    stream.next_sample = 0;
+    stream.next_chunk = 0;
+
+    let first = read_next_chunk(thread, read_file_range, staging, stream)?;
+    let id = fade_in_sound(audio, &first, fade_duration, MUSIC_VOLUME, 0.0, false)?;
+    if let Some(second) = read_next_chunk(thread, read_file_range, staging, stream) {
+        queue_next_sound(audio, id, &second);
+    }
+
+    Some(id)
+}
+
+// NOTE: Called once a frame with the track that should be playing, None for
+// silence; a change crossfades over fade_duration seconds. Also keeps every
+// playing track fed with the next chunk from disk.
+pub fn update_music(
+    music: &mut MusicState,
+    audio: &mut AudioState,
+    thread: &ThreadContext,
+    read_file_range: DebugPlatformReadFileRange,
+    track: Option<&str>,
+    fade_duration: f32,
+) {
+    if music.staging.is_null() {
+        return;
+    }
+    let staging = unsafe { slice::from_raw_parts_mut(music.staging, MUSIC_STAGING_SIZE) };
+
+    // NOTE: Names too long to keep are treated as no track at all
+    let track = track.filter(|track| track.len() <= MAX_MUSIC_FILENAME_LENGTH);
+    let requested = track.unwrap_or("");
+    if requested.as_bytes() != &music.track[..music.track_length] {
+        music.track[..requested.len()].copy_from_slice(requested.as_bytes());
+        music.track_length = requested.len();
+
+        let current = &music.streams[music.current_stream];
+        fade_out_sound(audio, current.id, fade_duration);
+
+        if let Some(track) = track {
+            // NOTE: Whatever was still fading out in the other slot gets cut
+            music.current_stream = (music.current_stream + 1) % MUSIC_STREAM_COUNT;
+            let stream = &mut music.streams[music.current_stream];
+            fade_out_sound(audio, stream.id, 0.0);
+
+            if let Some(id) = start_stream(
+                audio,
+                thread,
+                read_file_range,
+                staging,
+                stream,
+                track,
+                fade_duration,
+            ) {
+                stream.id = id;
+            }
+        }
+    } else if track.is_some() {
+        // NOTE: A hitch longer than a chunk starves the voice and it stops;
+        // the track then starts over rather than staying silent
+        let stream = &mut music.streams[music.current_stream];
+        if stream.filename_length != 0 && !is_sound_playing(audio, stream.id) {
+            let filename = stream.filename;
+            let filename = std::str::from_utf8(&filename[..stream.filename_length]).unwrap_or("");
+            if let Some(id) = start_stream(
+                audio,
+                thread,
+                read_file_range,
+                staging,
+                stream,
+                filename,
+                fade_duration,
+            ) {
+                stream.id = id;
+            }
+        }
+    }
+
+    for stream in music.streams.iter_mut() {
+        if needs_next_sound(audio, stream.id) {
+            if let Some(chunk) = read_next_chunk(thread, read_file_range, staging, stream) {
+                queue_next_sound(audio, stream.id, &chunk);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::sound::tests::wav_file;
+    use super::super::tests::with_arena;
+    use super::super::GameSoundOutputBuffer;
+    use super::*;
+
+    // NOTE: Longer than two chunks, so playing it through needs a refill
+    // and a wrap back to the start
+    const TRACK_SAMPLE_COUNT: u32 = 2 * MUSIC_CHUNK_SAMPLE_COUNT + 1000;
+
+    fn track_sample(track: u8, sample_index: u32) -> i16 {
+        (track as i32 * 1000 + (sample_index % 1000) as i32) as i16
+    }
+
+    fn track_file(track: u8) -> Vec<u8> {
+        let mut data = Vec::new();
+        for sample_index in 0..TRACK_SAMPLE_COUNT {
+            data.extend_from_slice(&track_sample(track, sample_index).to_le_bytes());
+        }
+        wav_file(48000, 1, 16, &data)
+    }
+
+    fn read_test_file_range(
+        _thread: &ThreadContext,
+        filename: &str,
+        offset: u64,
+        dest: &mut [u8],
+    ) -> usize {
+        let file = match filename {
+            "one.wav" => track_file(1),
+            "two.wav" => track_file(2),
+            _ => return 0,
+        };
+        let start = (offset as usize).min(file.len());
+        let count = dest.len().min(file.len() - start);
+        dest[..count].copy_from_slice(&file[start..start + count]);
+        count
+    }
+
+    struct TestMusic<'a> {
+        audio: Box<AudioState>,
+        music: Box<MusicState>,
+        arena: &'a mut MemoryArena,
+    }
+
+    fn test_music(arena: &mut MemoryArena) -> TestMusic<'_> {
+        // NOTE: Zeroed the way the game state is
+        let mut music: Box<MusicState> = unsafe { Box::new(std::mem::zeroed()) };
+        assert!(initialize_music(&mut music, arena));
+
+        TestMusic {
+            audio: unsafe { Box::new(std::mem::zeroed()) },
+            music,
+            arena,
+        }
+    }
+
+    fn update(test: &mut TestMusic, track: Option<&str>, fade_duration: f32) {
+        let thread = ThreadContext { placeholder: 0 };
+        update_music(
+            &mut test.music,
+            &mut test.audio,
+            &thread,
+            read_test_file_range,
+            track,
+            fade_duration,
+        );
+    }
+
+    // NOTE: Returns the left channel, the music is centered
+    fn mix(test: &mut TestMusic, sample_count: usize) -> Vec<i16> {
+        let mut samples = vec![0i16; AUDIO_CHANNEL_COUNT * sample_count];
+        let mut buffer = GameSoundOutputBuffer {
+            samples_per_second: 48000,
+            sample_count: sample_count as i32,
+            samples: &mut samples,
+        };
+        output_playing_sounds(&mut test.audio, &mut buffer, test.arena);
+
+        samples.iter().step_by(2).copied().collect()
+    }
+
+    #[test]
+    fn tracks_stream_through_chunks_and_loop() {
+        with_arena(4 * 1024 * 1024, |arena| {
+            let mut test = test_music(arena);
+            update(&mut test, Some("one.wav"), 0.0);
+
+            // NOTE: Mixed a frame's worth at a time, updating in between the way
+            // the game does, for a bit more than the whole track
+            let mut left = Vec::new();
+            while left.len() < TRACK_SAMPLE_COUNT as usize + 2000 {
+                left.extend(mix(&mut test, 4000));
+                update(&mut test, Some("one.wav"), 0.0);
+            }
+
+            for (sample_index, &sample) in left.iter().enumerate() {
+                let expected = track_sample(1, sample_index as u32 % TRACK_SAMPLE_COUNT);
+                let expected = (MUSIC_VOLUME * expected as f32).round() as i16;
+                assert_eq!(sample, expected, "{sample_index}");
+            }
+        });
+    }
+
+    #[test]
+    fn changing_tracks_crossfades() {
+        with_arena(4 * 1024 * 1024, |arena| {
+            let mut test = test_music(arena);
+            update(&mut test, Some("one.wav"), 0.0);
+            mix(&mut test, 100);
+
+            // NOTE: A quarter second at 48kHz, so 12000 samples of overlap
+            update(&mut test, Some("two.wav"), 0.25);
+            let old = test.music.streams[1 - test.music.current_stream].id;
+            let new = test.music.streams[test.music.current_stream].id;
+            let ids = [old, new];
+            assert!(ids.iter().all(|&id| is_sound_playing(&mut test.audio, id)));
+
+            let left = mix(&mut test, 6000);
+            update(&mut test, Some("two.wav"), 0.25);
+            left.iter().for_each(|&sample| assert!(sample > 0));
+            // NOTE: Halfway, both tracks at half of the music volume
+            let halfway =
+                0.5 * MUSIC_VOLUME * (track_sample(1, 6099) + track_sample(2, 5999)) as f32;
+            assert!((left[5999] as f32 - halfway).abs() <= 2.0, "{}", left[5999]);
+
+            // NOTE: A few extra samples, the fade can land a float step late
+            mix(&mut test, 6010);
+            update(&mut test, Some("two.wav"), 0.25);
+            assert!(!is_sound_playing(&mut test.audio, ids[0]));
+            assert!(is_sound_playing(&mut test.audio, ids[1]));
+
+            let left = mix(&mut test, 10);
+            let expected = (MUSIC_VOLUME * track_sample(2, 12010) as f32).round() as i16;
+            assert_eq!(left[0], expected);
+
+            // NOTE: Mixing past both chunks without an update starves the track,
+            // the next update starts it over
+            mix(&mut test, 2 * MUSIC_CHUNK_SAMPLE_COUNT as usize);
+            assert!(!is_sound_playing(&mut test.audio, ids[1]));
+            update(&mut test, Some("two.wav"), 0.0);
+            let left = mix(&mut test, 10);
+            let expected = (MUSIC_VOLUME * track_sample(2, 0) as f32).round() as i16;
+            assert_eq!(left[0], expected);
+
+            // NOTE: No track fades everything out, a missing one stays silent
+            let id = test.music.streams[test.music.current_stream].id;
+            update(&mut test, None, 0.0);
+            assert!(!is_sound_playing(&mut test.audio, id));
+            update(&mut test, Some("missing.wav"), 0.0);
+            assert_eq!(mix(&mut test, 10), [0; 10]);
+        });
+    }
+}
@@ -0,0 +1,547 @@
+use super::bitmap::*;
+use super::inflate::*;
+use super::memory::*;
+
+pub const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+
+const COLOR_TYPE_GRAY: u8 = 0;
+const COLOR_TYPE_RGB: u8 = 2;
+const COLOR_TYPE_PALETTE: u8 = 3;
+const COLOR_TYPE_GRAY_ALPHA: u8 = 4;
+const COLOR_TYPE_RGBA: u8 = 6;
+
+const fn make_crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+const CRC_TABLE: [u32; 256] = make_crc_table();
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut c = 0xFFFFFFFFu32;
+    for &byte in data {
+        c = CRC_TABLE[((c ^ byte as u32) & 0xFF) as usize] ^ (c >> 8);
+    }
+    c ^ 0xFFFFFFFF
+}
+
+fn read_u32_be(data: &[u8], at: usize) -> Option<u32> {
+    let bytes = data.get(at..at.checked_add(4)?)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+struct PngHeader {
+    width: i32,
+    height: i32,
+    bit_depth: u8,
+    color_type: u8,
+}
+
+impl PngHeader {
+    fn channels(&self) -> usize {
+        match self.color_type {
+            COLOR_TYPE_GRAY | COLOR_TYPE_PALETTE => 1,
+            COLOR_TYPE_GRAY_ALPHA => 2,
+            COLOR_TYPE_RGB => 3,
+            _ => 4,
+        }
+    }
+
+    fn bits_per_pixel(&self) -> usize {
+        self.channels() * self.bit_depth as usize
+    }
+
+    fn row_size(&self) -> usize {
+        (self.width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+}
+
+fn parse_header(data: &[u8]) -> Result<PngHeader, LoadBitmapError> {
+    use LoadBitmapError::*;
+
+    if data.len() != 13 {
+        return Err(Corrupt);
+    }
+
+    let width = read_u32_be(data, 0).ok_or(Corrupt)?;
+    let height = read_u32_be(data, 4).ok_or(Corrupt)?;
+    let bit_depth = data[8];
+    let color_type = data[9];
+    let compression_method = data[10];
+    let filter_method = data[11];
+    let interlace_method = data[12];
+
+    if width == 0 || height == 0 || width > i32::MAX as u32 || height > i32::MAX as u32 {
+        return Err(Corrupt);
+    }
+
+    let valid_depth = match color_type {
+        COLOR_TYPE_GRAY => matches!(bit_depth, 1 | 2 | 4 | 8 | 16),
+        COLOR_TYPE_PALETTE => matches!(bit_depth, 1 | 2 | 4 | 8),
+        COLOR_TYPE_RGB | COLOR_TYPE_GRAY_ALPHA | COLOR_TYPE_RGBA => matches!(bit_depth, 8 | 16),
+        _ => false,
+    };
+    if !valid_depth || compression_method != 0 || filter_method != 0 || interlace_method > 1 {
+        return Err(Corrupt);
+    }
+    if interlace_method != 0 {
+        // TODO: Adam7, if an artist ever hands us an interlaced file
+        return Err(Unsupported);
+    }
+
+    Ok(PngHeader {
+        width: width as i32,
+        height: height as i32,
+        bit_depth,
+        color_type,
+    })
+}
+
+fn paeth_predictor(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// NOTE: Undoes the per-scanline filters in place, leaving just the raw rows
+// packed back to back without their filter type bytes
+fn unfilter(header: &PngHeader, data: &mut Vec<u8>) -> Result<(), LoadBitmapError> {
+    let row_size = header.row_size();
+    let stride = row_size + 1;
+    let bytes_per_pixel = header.bits_per_pixel().div_ceil(8);
+
+    if data.len() != stride * header.height as usize {
+        return Err(LoadBitmapError::Truncated);
+    }
+
+    for y in 0..header.height as usize {
+        let filter_type = data[y * stride];
+        let (previous, current) = data.split_at_mut(y * stride + 1);
+        let current = &mut current[..row_size];
+        let prior = if y > 0 {
+            Some(&previous[(y - 1) * stride + 1..(y - 1) * stride + 1 + row_size])
+        } else {
+            None
+        };
+
+        for x in 0..row_size {
+            let a = if x >= bytes_per_pixel {
+                current[x - bytes_per_pixel]
+            } else {
+                0
+            };
+            let b = prior.map_or(0, |prior| prior[x]);
+            let c = if x >= bytes_per_pixel {
+                prior.map_or(0, |prior| prior[x - bytes_per_pixel])
+            } else {
+                0
+            };
+
+            current[x] = current[x].wrapping_add(match filter_type {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth_predictor(a, b, c),
+                _ => return Err(LoadBitmapError::Corrupt),
+            });
+        }
+    }
+
+    // NOTE: Squeeze out the filter type bytes now that every row is decoded
+    for y in 0..header.height as usize {
+        data.copy_within(y * stride + 1..y * stride + 1 + row_size, y * row_size);
+    }
+    data.truncate(row_size * header.height as usize);
+
+    Ok(())
+}
+
+fn sample(row: &[u8], bit_depth: u8, index: usize) -> u16 {
+    match bit_depth {
+        16 => u16::from_be_bytes([row[2 * index], row[2 * index + 1]]),
+        8 => row[index] as u16,
+        _ => {
+            let per_byte = 8 / bit_depth as usize;
+            let byte = row[index / per_byte];
+            let shift = 8 - bit_depth as usize * (index % per_byte + 1);
+            ((byte >> shift) & ((1u8 << bit_depth) - 1)) as u16
+        }
+    }
+}
+
+fn scale_to_8_bits(value: u16, bit_depth: u8) -> u32 {
+    match bit_depth {
+        16 => (value >> 8) as u32,
+        8 => value as u32,
+        _ => value as u32 * 255 / ((1u32 << bit_depth) - 1),
+    }
+}
+
+pub fn parse_png(
+    arena: &mut MemoryArena,
+    contents: &[u8],
+    align_x: i32,
+    align_y: i32,
+) -> Result<LoadedBitmap, LoadBitmapError> {
+    use LoadBitmapError::*;
+
+    if contents.len() < PNG_SIGNATURE.len() {
+        return Err(Truncated);
+    }
+    if contents[..PNG_SIGNATURE.len()] != PNG_SIGNATURE {
+        return Err(Corrupt);
+    }
+
+    let mut header: Option<PngHeader> = None;
+    let mut palette: Vec<[u8; 3]> = Vec::new();
+    let mut transparency: Vec<u8> = Vec::new();
+    let mut compressed: Vec<u8> = Vec::new();
+    let mut seen_end = false;
+
+    let mut at = PNG_SIGNATURE.len();
+    while !seen_end {
+        let length = read_u32_be(contents, at).ok_or(Truncated)? as usize;
+        let chunk_end = (at + 8)
+            .checked_add(length)
+            .and_then(|end| end.checked_add(4))
+            .ok_or(Corrupt)?;
+        if chunk_end > contents.len() {
+            return Err(Truncated);
+        }
+
+        let chunk_type = &contents[at + 4..at + 8];
+        let data = &contents[at + 8..at + 8 + length];
+        let crc = read_u32_be(contents, at + 8 + length).ok_or(Truncated)?;
+        if crc != crc32(&contents[at + 4..at + 8 + length]) {
+            return Err(Corrupt);
+        }
+
+        match chunk_type {
+            b"IHDR" => header = Some(parse_header(data)?),
+            b"PLTE" => {
+                if !length.is_multiple_of(3) || length / 3 > 256 {
+                    return Err(Corrupt);
+                }
+                palette = data
+                    .chunks_exact(3)
+                    .map(|entry| [entry[0], entry[1], entry[2]])
+                    .collect();
+            }
+            b"tRNS" => transparency = data.to_vec(),
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => seen_end = true,
+            _ => {
+                // NOTE: Bit 5 of the first byte clear means the chunk is
+                // critical, and we do not know how to draw without it
+                if chunk_type[0] & 0x20 == 0 {
+                    return Err(Unsupported);
+                }
+            }
+        }
+
+        at = chunk_end;
+    }
+
+    let header = header.ok_or(Corrupt)?;
+    if header.width > 1 << 14 || header.height > 1 << 14 {
+        return Err(Unsupported);
+    }
+    if header.color_type == COLOR_TYPE_PALETTE && palette.is_empty() {
+        return Err(Corrupt);
+    }
+
+    let expected_size = (header.row_size() + 1) * header.height as usize;
+    let mut pixels = zlib_decompress(&compressed, expected_size).map_err(|error| match error {
+        InflateError::Truncated => Truncated,
+        InflateError::Corrupt | InflateError::TooLarge => Corrupt,
+    })?;
+    unfilter(&header, &mut pixels)?;
+
+    // NOTE: Color key transparency for gray and RGB images, stored at full sample depth
+    let color_key: Option<[u16; 3]> = match header.color_type {
+        COLOR_TYPE_GRAY if transparency.len() >= 2 => {
+            let gray = u16::from_be_bytes([transparency[0], transparency[1]]);
+            Some([gray, gray, gray])
+        }
+        COLOR_TYPE_RGB if transparency.len() >= 6 => Some([
+            u16::from_be_bytes([transparency[0], transparency[1]]),
+            u16::from_be_bytes([transparency[2], transparency[3]]),
+            u16::from_be_bytes([transparency[4], transparency[5]]),
+        ]),
+        _ => None,
+    };
+
+    let mut result = allocate_bitmap(arena, header.width, header.height, align_x, align_y)?;
+    let row_size = header.row_size();
+    let channels = header.channels();
+    let depth = header.bit_depth;
+
+    for y in 0..header.height {
+        let row = &pixels[y as usize * row_size..(y as usize + 1) * row_size];
+        let dest_row = bitmap_row_mut(&mut result, y);
+
+        for (x, dest) in dest_row.iter_mut().enumerate() {
+            let (r, g, b, a) = match header.color_type {
+                COLOR_TYPE_PALETTE => {
+                    let index = sample(row, depth, x) as usize;
+                    let entry = palette.get(index).ok_or(Corrupt)?;
+                    let alpha = transparency.get(index).copied().unwrap_or(255);
+
+                    (
+                        entry[0] as u32,
+                        entry[1] as u32,
+                        entry[2] as u32,
+                        alpha as u32,
+                    )
+                }
+                COLOR_TYPE_GRAY | COLOR_TYPE_RGB => {
+                    let raw = if channels == 1 {
+                        let gray = sample(row, depth, x);
+                        [gray, gray, gray]
+                    } else {
+                        [
+                            sample(row, depth, 3 * x),
+                            sample(row, depth, 3 * x + 1),
+                            sample(row, depth, 3 * x + 2),
+                        ]
+                    };
+                    let alpha = if color_key == Some(raw) { 0 } else { 255 };
+
+                    (
+                        scale_to_8_bits(raw[0], depth),
+                        scale_to_8_bits(raw[1], depth),
+                        scale_to_8_bits(raw[2], depth),
+                        alpha,
+                    )
+                }
+                COLOR_TYPE_GRAY_ALPHA => {
+                    let gray = scale_to_8_bits(sample(row, depth, 2 * x), depth);
+                    let alpha = scale_to_8_bits(sample(row, depth, 2 * x + 1), depth);
+
+                    (gray, gray, gray, alpha)
+                }
+                _ => (
+                    scale_to_8_bits(sample(row, depth, 4 * x), depth),
+                    scale_to_8_bits(sample(row, depth, 4 * x + 1), depth),
+                    scale_to_8_bits(sample(row, depth, 4 * x + 2), depth),
+                    scale_to_8_bits(sample(row, depth, 4 * x + 3), depth),
+                ),
+            };
+
+            *dest = pack_premultiplied(r, g, b, a);
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::with_arena;
+    use super::*;
+
+    fn zlib_stored(data: &[u8]) -> Vec<u8> {
+        let mut result = vec![0x78, 0x01];
+        let chunks: Vec<&[u8]> = data.chunks(0xFFFF).collect();
+        for (index, chunk) in chunks.iter().enumerate() {
+            result.push((index + 1 == chunks.len()) as u8);
+            result.extend_from_slice(&(chunk.len() as u16).to_le_bytes());
+            result.extend_from_slice(&(!(chunk.len() as u16)).to_le_bytes());
+            result.extend_from_slice(chunk);
+        }
+
+        let (mut a, mut b) = (1u32, 0u32);
+        for &byte in data {
+            a = (a + byte as u32) % 65521;
+            b = (b + a) % 65521;
+        }
+        result.extend_from_slice(&((b << 16) | a).to_be_bytes());
+        result
+    }
+
+    fn chunk(file: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+        file.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let start = file.len();
+        file.extend_from_slice(chunk_type);
+        file.extend_from_slice(data);
+        let crc = crc32(&file[start..]);
+        file.extend_from_slice(&crc.to_be_bytes());
+    }
+
+    fn png_file(
+        width: u32,
+        height: u32,
+        bit_depth: u8,
+        color_type: u8,
+        extra: &[(&[u8; 4], Vec<u8>)],
+        scanlines: &[u8],
+    ) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&width.to_be_bytes());
+        header.extend_from_slice(&height.to_be_bytes());
+        header.extend_from_slice(&[bit_depth, color_type, 0, 0, 0]);
+
+        let mut file = PNG_SIGNATURE.to_vec();
+        chunk(&mut file, b"IHDR", &header);
+        for (chunk_type, data) in extra {
+            chunk(&mut file, chunk_type, data);
+        }
+
+        // NOTE: Split the image data to make sure IDAT chunks get joined
+        let compressed = zlib_stored(scanlines);
+        let (first, second) = compressed.split_at(compressed.len() / 2);
+        chunk(&mut file, b"IDAT", first);
+        chunk(&mut file, b"IDAT", second);
+        chunk(&mut file, b"IEND", &[]);
+        file
+    }
+
+    fn filter_rows(rows: &[Vec<u8>], bytes_per_pixel: usize) -> Vec<u8> {
+        let mut result = Vec::new();
+        for (y, row) in rows.iter().enumerate() {
+            let filter_type = (y % 5) as u8;
+            result.push(filter_type);
+            for x in 0..row.len() {
+                let a = if x >= bytes_per_pixel {
+                    row[x - bytes_per_pixel]
+                } else {
+                    0
+                };
+                let b = if y > 0 { rows[y - 1][x] } else { 0 };
+                let c = if y > 0 && x >= bytes_per_pixel {
+                    rows[y - 1][x - bytes_per_pixel]
+                } else {
+                    0
+                };
+                let predicted = match filter_type {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    _ => paeth_predictor(a, b, c),
+                };
+                result.push(row[x].wrapping_sub(predicted));
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn rgba_rows_with_every_filter_type() {
+        let rows: Vec<Vec<u8>> = (0..5u8)
+            .map(|y| {
+                (0..3u8)
+                    .flat_map(|x| [x * 80 + y, 255 - y * 40, x * y * 7, 128 + x + y])
+                    .collect()
+            })
+            .collect();
+        let file = png_file(3, 5, 8, COLOR_TYPE_RGBA, &[], &filter_rows(&rows, 4));
+
+        with_arena(64 * 1024, |arena| {
+            let bitmap = parse_png(arena, &file, 0, 0).unwrap();
+            assert_eq!((bitmap.width, bitmap.height), (3, 5));
+
+            for (y, row) in rows.iter().enumerate() {
+                let expected: Vec<u32> = row
+                    .chunks_exact(4)
+                    .map(|p| pack_premultiplied(p[0] as u32, p[1] as u32, p[2] as u32, p[3] as u32))
+                    .collect();
+                assert_eq!(bitmap_row(&bitmap, y as i32), &expected[..]);
+            }
+        });
+    }
+
+    #[test]
+    fn two_bit_palette_with_transparency() {
+        let palette = vec![255, 0, 0, 0, 255, 0, 0, 0, 255, 10, 20, 30];
+        let alpha = vec![0, 128];
+        // NOTE: Indices 0 1 2 3 | 3 2 packed four to a byte, MSB first
+        let scanlines = [0, 0b00_01_10_11, 0b11_10_00_00];
+        let file = png_file(
+            6,
+            1,
+            2,
+            COLOR_TYPE_PALETTE,
+            &[(b"PLTE", palette), (b"tRNS", alpha)],
+            &scanlines,
+        );
+
+        with_arena(64 * 1024, |arena| {
+            let bitmap = parse_png(arena, &file, 0, 0).unwrap();
+            assert_eq!(
+                bitmap_row(&bitmap, 0),
+                &[0x00000000, 0x80008000, 0xFF0000FF, 0xFF0A141E, 0xFF0A141E, 0xFF0000FF]
+            );
+        });
+    }
+
+    #[test]
+    fn sixteen_bit_gray_with_color_key() {
+        let scanlines = [0, 0x12, 0x34, 0xAB, 0xCD];
+        let key = vec![0x12, 0x34];
+        let file = png_file(2, 1, 16, COLOR_TYPE_GRAY, &[(b"tRNS", key)], &scanlines);
+
+        with_arena(64 * 1024, |arena| {
+            let bitmap = parse_png(arena, &file, 0, 0).unwrap();
+            assert_eq!(bitmap_row(&bitmap, 0), &[0x00000000, 0xFFABABAB]);
+        });
+    }
+
+    #[test]
+    fn truncated_and_corrupt_files_are_rejected() {
+        let scanlines = [0, 1, 2, 3, 0, 4, 5, 6];
+        let file = png_file(1, 2, 8, COLOR_TYPE_RGB, &[], &scanlines);
+
+        with_arena(64 * 1024, |arena| {
+            assert!(parse_png(arena, &file, 0, 0).is_ok());
+
+            for length in 0..file.len() {
+                assert!(parse_png(arena, &file[..length], 0, 0).is_err());
+            }
+
+            let mut bad_crc = file.clone();
+            bad_crc[30] ^= 0x40;
+            assert_eq!(
+                parse_png(arena, &bad_crc, 0, 0).unwrap_err(),
+                LoadBitmapError::Corrupt
+            );
+
+            let bad_filter = png_file(1, 2, 8, COLOR_TYPE_RGB, &[], &[7, 1, 2, 3, 0, 4, 5, 6]);
+            assert_eq!(
+                parse_png(arena, &bad_filter, 0, 0).unwrap_err(),
+                LoadBitmapError::Corrupt
+            );
+
+            let short_data = png_file(1, 2, 8, COLOR_TYPE_RGB, &[], &scanlines[..6]);
+            assert_eq!(
+                parse_png(arena, &short_data, 0, 0).unwrap_err(),
+                LoadBitmapError::Truncated
+            );
+        });
+    }
+}
@@ -0,0 +1,1892 @@
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+use std::cmp::{max, min};
+use std::slice;
+use std::str;
+
+use super::bitmap::*;
+use super::font::*;
+use super::math::*;
+use super::memory::*;
+use super::simd::*;
+use super::work_queue::*;
+use super::{round_real32_to_int32, round_real32_to_uint32, GameOffscreenBuffer};
+
+// NOTE: The offscreen buffer as tile jobs see it; every job only touches
+// the pixels inside its own clip rect, so they can share the memory
+#[derive(Clone, Copy)]
+struct RenderTarget {
+    memory: *mut u8,
+    width: i32,
+    height: i32,
+    pitch: i32,
+    simd_level: SimdLevel,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rectangle2i {
+    pub min_x: i32,
+    pub min_y: i32,
+    pub max_x: i32,
+    pub max_y: i32,
+}
+
+fn intersect(a: Rectangle2i, b: Rectangle2i) -> Rectangle2i {
+    Rectangle2i {
+        min_x: max(a.min_x, b.min_x),
+        min_y: max(a.min_y, b.min_y),
+        max_x: min(a.max_x, b.max_x),
+        max_y: min(a.max_y, b.max_y),
+    }
+}
+
+fn render_target(buffer: &mut GameOffscreenBuffer) -> RenderTarget {
+    assert_eq!(buffer.bytes_per_pixel, 4);
+    let width = max(0, buffer.width);
+    let height = max(0, buffer.height);
+    assert!(buffer.pitch >= 4 * width);
+    assert!(buffer.memory.len() >= height as usize * buffer.pitch as usize);
+
+    RenderTarget {
+        memory: buffer.memory.as_mut_ptr(),
+        width,
+        height,
+        pitch: buffer.pitch,
+        simd_level: detect_simd_level(),
+    }
+}
+
+// NOTE: Callers clip to the target first, this does no bounds checking
+unsafe fn target_pixel(target: RenderTarget, x: i32, y: i32) -> *mut u32 {
+    target
+        .memory
+        .add(y as usize * target.pitch as usize + 4 * x as usize) as *mut u32
+}
+
+fn draw_rectangle(target: RenderTarget, v_min: V2, v_max: V2, color: V4, clip: Rectangle2i) {
+    let min_x = max(clip.min_x, round_real32_to_int32(v_min.x));
+    let min_y = max(clip.min_y, round_real32_to_int32(v_min.y));
+    let max_x = min(clip.max_x, round_real32_to_int32(v_max.x));
+    let max_y = min(clip.max_y, round_real32_to_int32(v_max.y));
+
+    let a = color.a.clamp(0.0, 1.0);
+    let color = (round_real32_to_uint32(a * 255.0) << 24)
+        | (round_real32_to_uint32(a * color.r * 255.0) << 16)
+        | (round_real32_to_uint32(a * color.g * 255.0) << 8)
+        | round_real32_to_uint32(a * color.b * 255.0);
+    let is_opaque = a >= 1.0;
+
+    if min_x >= max_x {
+        return;
+    }
+
+    let count = (max_x - min_x) as usize;
+    for y in min_y..max_y {
+        unsafe {
+            let row = target_pixel(target, min_x, y);
+            if is_opaque {
+                fill_row(target.simd_level, row, count, color);
+            } else {
+                blend_solid_row(target.simd_level, row, count, color);
+            }
+        }
+    }
+}
+
+fn draw_bitmap(
+    target: RenderTarget,
+    bitmap: &LoadedBitmap,
+    real_x: f32,
+    real_y: f32,
+    c_alpha: f32,
+    clip: Rectangle2i,
+) {
+    let origin_x = round_real32_to_int32(real_x - bitmap.align_x as f32);
+    let origin_y = round_real32_to_int32(real_y - bitmap.align_y as f32);
+
+    let min_x = max(clip.min_x, origin_x);
+    let min_y = max(clip.min_y, origin_y);
+    let max_x = min(clip.max_x, origin_x.saturating_add(bitmap.width));
+    let max_y = min(clip.max_y, origin_y.saturating_add(bitmap.height));
+
+    if min_x >= max_x {
+        return;
+    }
+
+    let count = (max_x - min_x) as usize;
+    for y in min_y..max_y {
+        let source_row = &bitmap_row(bitmap, y - origin_y)[(min_x - origin_x) as usize..];
+        assert!(source_row.len() >= count);
+        unsafe {
+            let row = target_pixel(target, min_x, y);
+            blend_row(target.simd_level, row, source_row.as_ptr(), count, c_alpha);
+        }
+    }
+}
+
+fn unpack4(packed: u32) -> V4 {
+    v4(
+        ((packed >> 16) & 0xFF) as f32,
+        ((packed >> 8) & 0xFF) as f32,
+        (packed & 0xFF) as f32,
+        ((packed >> 24) & 0xFF) as f32,
+    )
+}
+
+fn pack4(color: V4) -> u32 {
+    let channel = |value: f32| round_real32_to_uint32(value.clamp(0.0, 255.0));
+
+    (channel(color.a) << 24) | (channel(color.r) << 16) | (channel(color.g) << 8) | channel(color.b)
+}
+
+// NOTE: tx and ty are in texels with texel centers on the half, samples past
+// the edges clamp to the border texels
+fn sample_bilinear(bitmap: &LoadedBitmap, tx: f32, ty: f32) -> V4 {
+    let floor_x = tx.floor();
+    let floor_y = ty.floor();
+    let fx = tx - floor_x;
+    let fy = ty - floor_y;
+
+    let x0 = (floor_x as i32).clamp(0, bitmap.width - 1);
+    let x1 = (floor_x as i32 + 1).clamp(0, bitmap.width - 1);
+    let y0 = (floor_y as i32).clamp(0, bitmap.height - 1);
+    let y1 = (floor_y as i32 + 1).clamp(0, bitmap.height - 1);
+
+    let row0 = bitmap_row(bitmap, y0);
+    let row1 = bitmap_row(bitmap, y1);
+    let top = lerp4(unpack4(row0[x0 as usize]), fx, unpack4(row0[x1 as usize]));
+    let bottom = lerp4(unpack4(row1[x0 as usize]), fx, unpack4(row1[x1 as usize]));
+
+    lerp4(top, fy, bottom)
+}
+
+// NOTE: origin, x_axis and y_axis are in meters relative to the camera when
+// pushed, and in screen pixels by the time the quad is drawn
+#[derive(Clone, Copy, Debug)]
+pub struct TexturedQuad {
+    pub origin: V2,
+    pub x_axis: V2,
+    pub y_axis: V2,
+    pub color: V4,
+    pub bitmap: LoadedBitmap,
+    // NOTE: Opaque, with x right, y up and z out of the bitmap stored in
+    // r, g and b as 0 for -1 through 255 for 1
+    pub normal_map: Option<LoadedBitmap>,
+}
+
+pub const MAX_LIGHT_COUNT: usize = 4;
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PointLight {
+    // NOTE: In meters relative to the camera, height is above the ground plane
+    pub p: V2,
+    pub height: f32,
+    pub color: V3,
+    pub radius: f32,
+}
+
+// NOTE: Only quads with a normal map are lit; sky is what normals facing up
+// the screen pick up, ground is what normals facing down pick up
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Lighting {
+    pub sky_color: V3,
+    pub ground_color: V3,
+    pub light_count: usize,
+    pub lights: [PointLight; MAX_LIGHT_COUNT],
+    pub debug_show_normals: bool,
+}
+
+// NOTE: Lighting with the lights moved to screen pixels, where z is out of the screen
+struct ScreenLighting {
+    sky_color: V3,
+    ground_color: V3,
+    light_count: usize,
+    light_p: [V3; MAX_LIGHT_COUNT],
+    light_color: [V3; MAX_LIGHT_COUNT],
+    light_radius: [f32; MAX_LIGHT_COUNT],
+    debug_show_normals: bool,
+}
+
+// NOTE: Returns the screen space normal at the sample, with the normal
+// map's x and y turned to follow the quad's axes
+fn sample_normal(normal_map: &LoadedBitmap, u: f32, v: f32, x_dir: V2, y_dir: V2) -> V3 {
+    let tx = u * normal_map.width as f32 - 0.5;
+    let ty = (1.0 - v) * normal_map.height as f32 - 0.5;
+    let encoded = sample_bilinear(normal_map, tx, ty);
+
+    let decode = |value: f32| 2.0 * (value / 255.0) - 1.0;
+    let n = v3(decode(encoded.r), decode(encoded.g), decode(encoded.b));
+    let xy = n.x * x_dir + n.y * y_dir;
+
+    normalize_or_zero3(v3(xy.x, xy.y, n.z))
+}
+
+fn light_texel(texel: V4, n: V3, p: V3, lighting: &ScreenLighting) -> V4 {
+    if lighting.debug_show_normals {
+        // NOTE: Shown the way normal maps store them, y up the screen
+        let coverage = texel.a / 255.0;
+        let encode = |value: f32| coverage * 255.0 * (0.5 + 0.5 * value);
+        return v4(encode(n.x), encode(-n.y), encode(n.z), texel.a);
+    }
+
+    let up = 0.5 - 0.5 * n.y;
+    let mut light = (1.0 - up) * lighting.ground_color + up * lighting.sky_color;
+
+    for light_index in 0..lighting.light_count {
+        let to_light = lighting.light_p[light_index] - p;
+        let distance = length3(to_light);
+        let falloff = (1.0 - distance / lighting.light_radius[light_index]).max(0.0);
+        let facing = inner3(n, normalize_or_zero3(to_light)).max(0.0);
+
+        light = light + (facing * falloff * falloff) * lighting.light_color[light_index];
+    }
+
+    // NOTE: Premultiplied, so no channel can end up brighter than the alpha
+    v4(
+        (light.x * texel.r).min(texel.a),
+        (light.y * texel.g).min(texel.a),
+        (light.z * texel.b).min(texel.a),
+        texel.a,
+    )
+}
+
+// NOTE: Everything the per-pixel shading of one textured quad needs, worked
+// out once per quad so the scalar and wide paths start from the same numbers
+struct QuadShader<'a> {
+    origin: V2,
+    x_axis: V2,
+    y_axis: V2,
+    inv_det: f32,
+    bitmap: &'a LoadedBitmap,
+    tint: V4,
+    // NOTE: Only set when the quad has a normal map and lighting to go with it
+    lit: Option<(&'a LoadedBitmap, &'a ScreenLighting)>,
+    x_dir: V2,
+    y_dir: V2,
+}
+
+// NOTE: Returns None for pixels whose center is outside the quad
+fn shade_quad_pixel(shader: &QuadShader, x: i32, y: i32, dest: u32) -> Option<u32> {
+    let screen_p = v2(x as f32 + 0.5, y as f32 + 0.5);
+    let d = screen_p - shader.origin;
+    let u = cross(d, shader.y_axis) * shader.inv_det;
+    let v = cross(shader.x_axis, d) * shader.inv_det;
+
+    if !(0.0..1.0).contains(&u) || !(0.0..1.0).contains(&v) {
+        return None;
+    }
+
+    let bitmap = shader.bitmap;
+    let tx = u * bitmap.width as f32 - 0.5;
+    let ty = (1.0 - v) * bitmap.height as f32 - 0.5;
+    let mut texel = sample_bilinear(bitmap, tx, ty);
+
+    if let Some((normal_map, lighting)) = shader.lit {
+        let n = sample_normal(normal_map, u, v, shader.x_dir, shader.y_dir);
+        texel = light_texel(texel, n, v3(screen_p.x, screen_p.y, 0.0), lighting);
+    }
+    let texel = hadamard4(texel, shader.tint);
+
+    let result = (1.0 - texel.a / 255.0) * unpack4(dest) + texel;
+    Some(pack4(result))
+}
+
+// NOTE: Same contract as the rows in simd.rs, the wide paths shade whole
+// groups of pixels and the scalar loop picks up whatever is left
+unsafe fn shade_quad_row(
+    level: SimdLevel,
+    shader: &QuadShader,
+    row: *mut u32,
+    x: i32,
+    y: i32,
+    count: usize,
+) {
+    let mut done = 0;
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    match level {
+        SimdLevel::Avx2 => done = quad_avx2::shade_quad_row(shader, row, x, y, count),
+        SimdLevel::Sse2 => done = quad_sse2::shade_quad_row(shader, row, x, y, count),
+        SimdLevel::Scalar => {}
+    }
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+    let _ = level;
+
+    for index in done..count {
+        let pixel = row.add(index);
+        let dest = u32::from_le(pixel.read_unaligned());
+        if let Some(result) = shade_quad_pixel(shader, x + index as i32, y, dest) {
+            pixel.write_unaligned(result.to_le());
+        }
+    }
+}
+
+// NOTE: Fills the parallelogram origin + u*x_axis + v*y_axis for u and v in
+// [0, 1), in screen pixels. A pixel is in when its center is, so edges land
+// wherever the float corners put them. v = 1 is the top row of the bitmap.
+fn draw_textured_quad(
+    target: RenderTarget,
+    quad: &TexturedQuad,
+    lighting: Option<&ScreenLighting>,
+    clip: Rectangle2i,
+) {
+    let TexturedQuad {
+        origin,
+        x_axis,
+        y_axis,
+        color,
+        ref bitmap,
+        ref normal_map,
+    } = *quad;
+
+    let det = cross(x_axis, y_axis);
+    if det == 0.0 || bitmap.width <= 0 || bitmap.height <= 0 {
+        return;
+    }
+
+    let corners = [
+        origin,
+        origin + x_axis,
+        origin + y_axis,
+        origin + x_axis + y_axis,
+    ];
+    let min_corner_x = corners.iter().fold(f32::MAX, |m, p| m.min(p.x));
+    let min_corner_y = corners.iter().fold(f32::MAX, |m, p| m.min(p.y));
+    let max_corner_x = corners.iter().fold(f32::MIN, |m, p| m.max(p.x));
+    let max_corner_y = corners.iter().fold(f32::MIN, |m, p| m.max(p.y));
+
+    let min_x = max(clip.min_x, min_corner_x.floor() as i32);
+    let min_y = max(clip.min_y, min_corner_y.floor() as i32);
+    let max_x = min(clip.max_x, max_corner_x.ceil() as i32);
+    let max_y = min(clip.max_y, max_corner_y.ceil() as i32);
+
+    if min_x >= max_x {
+        return;
+    }
+
+    // NOTE: Premultiply the tint so it scales texels the same way alpha does
+    let a = color.a.clamp(0.0, 1.0);
+    let normal_map = normal_map
+        .as_ref()
+        .filter(|map| map.width > 0 && map.height > 0);
+    let shader = QuadShader {
+        origin,
+        x_axis,
+        y_axis,
+        inv_det: 1.0 / det,
+        bitmap,
+        tint: v4(color.r * a, color.g * a, color.b * a, a),
+        lit: normal_map.zip(lighting),
+        x_dir: (1.0 / length(x_axis)) * x_axis,
+        y_dir: (1.0 / length(y_axis)) * y_axis,
+    };
+
+    let count = (max_x - min_x) as usize;
+    for y in min_y..max_y {
+        unsafe {
+            let row = target_pixel(target, min_x, y);
+            shade_quad_row(target.simd_level, &shader, row, min_x, y, count);
+        }
+    }
+}
+
+// NOTE: The wide quad shaders mirror shade_quad_pixel one operation at a
+// time, one lane per pixel, so every level lands on the same pixels. The
+// macro writes the body once and each level fills in its own intrinsics.
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+macro_rules! wide_quad_shader {
+    (
+        mod $module:ident, feature = $feature:literal, lanes = $lanes:literal,
+        $ps:ident, $si:ident,
+        set1_ps: $set1_ps:ident,
+        set1_epi32: $set1_epi32:ident,
+        add_ps: $add_ps:ident,
+        sub_ps: $sub_ps:ident,
+        mul_ps: $mul_ps:ident,
+        div_ps: $div_ps:ident,
+        min_ps: $min_ps:ident,
+        max_ps: $max_ps:ident,
+        sqrt_ps: $sqrt_ps:ident,
+        cmplt_ps: $cmplt_ps:ident,
+        cmple_ps: $cmple_ps:ident,
+        and_ps: $and_ps:ident,
+        movemask_ps: $movemask_ps:ident,
+        cvtepi32_ps: $cvtepi32_ps:ident,
+        cvttps_epi32: $cvttps_epi32:ident,
+        castps_si: $castps_si:ident,
+        add_epi32: $add_epi32:ident,
+        and_si: $and_si:ident,
+        or_si: $or_si:ident,
+        andnot_si: $andnot_si:ident,
+        srli_epi32: $srli_epi32:ident,
+        slli_epi32: $slli_epi32:ident,
+        loadu_si: $loadu_si:ident,
+        storeu_si: $storeu_si:ident,
+    ) => {
+        mod $module {
+            #[cfg(target_arch = "x86")]
+            use std::arch::x86::*;
+            #[cfg(target_arch = "x86_64")]
+            use std::arch::x86_64::*;
+
+            use super::*;
+
+            const LANES: usize = $lanes;
+
+            #[derive(Clone, Copy)]
+            struct Wide3 {
+                x: $ps,
+                y: $ps,
+                z: $ps,
+            }
+
+            #[derive(Clone, Copy)]
+            struct Wide4 {
+                r: $ps,
+                g: $ps,
+                b: $ps,
+                a: $ps,
+            }
+
+            #[target_feature(enable = $feature)]
+            #[inline]
+            unsafe fn unpack4(packed: $si) -> Wide4 {
+                let mask_ff = $set1_epi32(0xFF);
+                Wide4 {
+                    r: $cvtepi32_ps($and_si($srli_epi32::<16>(packed), mask_ff)),
+                    g: $cvtepi32_ps($and_si($srli_epi32::<8>(packed), mask_ff)),
+                    b: $cvtepi32_ps($and_si(packed, mask_ff)),
+                    a: $cvtepi32_ps($srli_epi32::<24>(packed)),
+                }
+            }
+
+            #[target_feature(enable = $feature)]
+            #[inline]
+            unsafe fn pack4(color: Wide4) -> $si {
+                let zero = $set1_ps(0.0);
+                let half = $set1_ps(0.5);
+                let two_fifty_five = $set1_ps(255.0);
+                let channel = |value: $ps| {
+                    let clamped = $min_ps($max_ps(value, zero), two_fifty_five);
+                    $cvttps_epi32($add_ps(clamped, half))
+                };
+
+                $or_si(
+                    $or_si(
+                        $slli_epi32::<24>(channel(color.a)),
+                        $slli_epi32::<16>(channel(color.r)),
+                    ),
+                    $or_si($slli_epi32::<8>(channel(color.g)), channel(color.b)),
+                )
+            }
+
+            // NOTE: Only good for values that fit an i32, which texel
+            // coordinates always do
+            #[target_feature(enable = $feature)]
+            #[inline]
+            unsafe fn floor(value: $ps) -> $ps {
+                let truncated = $cvtepi32_ps($cvttps_epi32(value));
+                let one = $set1_ps(1.0);
+                $sub_ps(truncated, $and_ps($cmplt_ps(value, truncated), one))
+            }
+
+            #[target_feature(enable = $feature)]
+            #[inline]
+            unsafe fn lerp4(a: Wide4, t: $ps, b: Wide4) -> Wide4 {
+                let inv_t = $sub_ps($set1_ps(1.0), t);
+                let lerp = |a: $ps, b: $ps| $add_ps($mul_ps(inv_t, a), $mul_ps(t, b));
+                Wide4 {
+                    r: lerp(a.r, b.r),
+                    g: lerp(a.g, b.g),
+                    b: lerp(a.b, b.b),
+                    a: lerp(a.a, b.a),
+                }
+            }
+
+            #[target_feature(enable = $feature)]
+            #[inline]
+            unsafe fn sample_bilinear(bitmap: &LoadedBitmap, tx: $ps, ty: $ps) -> Wide4 {
+                let floor_x = floor(tx);
+                let floor_y = floor(ty);
+                let fx = $sub_ps(tx, floor_x);
+                let fy = $sub_ps(ty, floor_y);
+
+                // NOTE: Clamping the whole numbers as floats gives the same
+                // indices as clamping them as ints
+                let zero = $set1_ps(0.0);
+                let one = $set1_ps(1.0);
+                let last_x = $set1_ps((bitmap.width - 1) as f32);
+                let last_y = $set1_ps((bitmap.height - 1) as f32);
+                let index = |value: $ps, last: $ps| {
+                    let mut lanes = [0i32; LANES];
+                    let clamped = $min_ps($max_ps(value, zero), last);
+                    $storeu_si(lanes.as_mut_ptr() as *mut $si, $cvttps_epi32(clamped));
+                    lanes
+                };
+                let x0 = index(floor_x, last_x);
+                let x1 = index($add_ps(floor_x, one), last_x);
+                let y0 = index(floor_y, last_y);
+                let y1 = index($add_ps(floor_y, one), last_y);
+
+                let row_pixels = (bitmap.pitch as usize) / 4;
+                let fetch = |x: [i32; LANES], y: [i32; LANES]| {
+                    let mut texels = [0u32; LANES];
+                    for lane in 0..LANES {
+                        let at = y[lane] as usize * row_pixels + x[lane] as usize;
+                        texels[lane] = bitmap.memory.add(at).read();
+                    }
+                    unpack4($loadu_si(texels.as_ptr() as *const $si))
+                };
+
+                let top = lerp4(fetch(x0, y0), fx, fetch(x1, y0));
+                let bottom = lerp4(fetch(x0, y1), fx, fetch(x1, y1));
+                lerp4(top, fy, bottom)
+            }
+
+            #[target_feature(enable = $feature)]
+            #[inline]
+            unsafe fn inner3(a: Wide3, b: Wide3) -> $ps {
+                $add_ps(
+                    $add_ps($mul_ps(a.x, b.x), $mul_ps(a.y, b.y)),
+                    $mul_ps(a.z, b.z),
+                )
+            }
+
+            #[target_feature(enable = $feature)]
+            #[inline]
+            unsafe fn normalize_or_zero3(a: Wide3) -> Wide3 {
+                let length = $sqrt_ps(inner3(a, a));
+                let inv_length = $div_ps($set1_ps(1.0), length);
+                let nonzero = $cmplt_ps($set1_ps(0.0), length);
+                Wide3 {
+                    x: $and_ps(nonzero, $mul_ps(inv_length, a.x)),
+                    y: $and_ps(nonzero, $mul_ps(inv_length, a.y)),
+                    z: $and_ps(nonzero, $mul_ps(inv_length, a.z)),
+                }
+            }
+
+            #[target_feature(enable = $feature)]
+            #[inline]
+            unsafe fn sample_normal(
+                normal_map: &LoadedBitmap,
+                u: $ps,
+                v: $ps,
+                x_dir: V2,
+                y_dir: V2,
+            ) -> Wide3 {
+                let one = $set1_ps(1.0);
+                let half = $set1_ps(0.5);
+                let width = $set1_ps(normal_map.width as f32);
+                let height = $set1_ps(normal_map.height as f32);
+                let tx = $sub_ps($mul_ps(u, width), half);
+                let ty = $sub_ps($mul_ps($sub_ps(one, v), height), half);
+                let encoded = sample_bilinear(normal_map, tx, ty);
+
+                let two = $set1_ps(2.0);
+                let two_fifty_five = $set1_ps(255.0);
+                let decode =
+                    |value: $ps| $sub_ps($mul_ps(two, $div_ps(value, two_fifty_five)), one);
+                let n = Wide3 {
+                    x: decode(encoded.r),
+                    y: decode(encoded.g),
+                    z: decode(encoded.b),
+                };
+                let turn = |x_dir: f32, y_dir: f32| {
+                    $add_ps($mul_ps(n.x, $set1_ps(x_dir)), $mul_ps(n.y, $set1_ps(y_dir)))
+                };
+
+                normalize_or_zero3(Wide3 {
+                    x: turn(x_dir.x, y_dir.x),
+                    y: turn(x_dir.y, y_dir.y),
+                    z: n.z,
+                })
+            }
+
+            #[target_feature(enable = $feature)]
+            #[inline]
+            unsafe fn light_texel(
+                texel: Wide4,
+                n: Wide3,
+                p: Wide3,
+                lighting: &ScreenLighting,
+            ) -> Wide4 {
+                let zero = $set1_ps(0.0);
+                let one = $set1_ps(1.0);
+                let half = $set1_ps(0.5);
+                let two_fifty_five = $set1_ps(255.0);
+
+                if lighting.debug_show_normals {
+                    let coverage = $mul_ps($div_ps(texel.a, two_fifty_five), two_fifty_five);
+                    let encode =
+                        |value: $ps| $mul_ps(coverage, $add_ps(half, $mul_ps(half, value)));
+                    return Wide4 {
+                        r: encode(n.x),
+                        g: encode($sub_ps(zero, n.y)),
+                        b: encode(n.z),
+                        a: texel.a,
+                    };
+                }
+
+                let up = $sub_ps(half, $mul_ps(half, n.y));
+                let down = $sub_ps(one, up);
+                let environment = |ground: f32, sky: f32| {
+                    $add_ps($mul_ps(down, $set1_ps(ground)), $mul_ps(up, $set1_ps(sky)))
+                };
+                let mut light = Wide3 {
+                    x: environment(lighting.ground_color.x, lighting.sky_color.x),
+                    y: environment(lighting.ground_color.y, lighting.sky_color.y),
+                    z: environment(lighting.ground_color.z, lighting.sky_color.z),
+                };
+
+                for light_index in 0..lighting.light_count {
+                    let light_p = lighting.light_p[light_index];
+                    let to_light = Wide3 {
+                        x: $sub_ps($set1_ps(light_p.x), p.x),
+                        y: $sub_ps($set1_ps(light_p.y), p.y),
+                        z: $sub_ps($set1_ps(light_p.z), p.z),
+                    };
+                    let distance = $sqrt_ps(inner3(to_light, to_light));
+                    let radius = $set1_ps(lighting.light_radius[light_index]);
+                    let falloff = $max_ps($sub_ps(one, $div_ps(distance, radius)), zero);
+                    let facing = $max_ps(inner3(n, normalize_or_zero3(to_light)), zero);
+
+                    let amount = $mul_ps($mul_ps(facing, falloff), falloff);
+                    let color = lighting.light_color[light_index];
+                    light.x = $add_ps(light.x, $mul_ps(amount, $set1_ps(color.x)));
+                    light.y = $add_ps(light.y, $mul_ps(amount, $set1_ps(color.y)));
+                    light.z = $add_ps(light.z, $mul_ps(amount, $set1_ps(color.z)));
+                }
+
+                Wide4 {
+                    r: $min_ps($mul_ps(light.x, texel.r), texel.a),
+                    g: $min_ps($mul_ps(light.y, texel.g), texel.a),
+                    b: $min_ps($mul_ps(light.z, texel.b), texel.a),
+                    a: texel.a,
+                }
+            }
+
+            // NOTE: Returns how many pixels it handled, the scalar loop does the rest
+            #[target_feature(enable = $feature)]
+            pub(super) unsafe fn shade_quad_row(
+                shader: &QuadShader,
+                row: *mut u32,
+                x: i32,
+                y: i32,
+                count: usize,
+            ) -> usize {
+                let zero = $set1_ps(0.0);
+                let one = $set1_ps(1.0);
+                let half = $set1_ps(0.5);
+                let two_fifty_five = $set1_ps(255.0);
+                let inv_det = $set1_ps(shader.inv_det);
+                let texture_width = $set1_ps(shader.bitmap.width as f32);
+                let texture_height = $set1_ps(shader.bitmap.height as f32);
+                let lane_offsets = $loadu_si([0i32, 1, 2, 3, 4, 5, 6, 7].as_ptr() as *const $si);
+
+                let screen_y = $set1_ps(y as f32 + 0.5);
+                let dy = $sub_ps(screen_y, $set1_ps(shader.origin.y));
+
+                let mut index = 0;
+                while index + LANES <= count {
+                    let dest_ptr = row.add(index) as *mut $si;
+                    let lane_x = $add_epi32($set1_epi32(x + index as i32), lane_offsets);
+                    let screen_x = $add_ps($cvtepi32_ps(lane_x), half);
+                    let dx = $sub_ps(screen_x, $set1_ps(shader.origin.x));
+                    let u = $mul_ps(
+                        $sub_ps(
+                            $mul_ps(dx, $set1_ps(shader.y_axis.y)),
+                            $mul_ps(dy, $set1_ps(shader.y_axis.x)),
+                        ),
+                        inv_det,
+                    );
+                    let v = $mul_ps(
+                        $sub_ps(
+                            $mul_ps($set1_ps(shader.x_axis.x), dy),
+                            $mul_ps($set1_ps(shader.x_axis.y), dx),
+                        ),
+                        inv_det,
+                    );
+
+                    let inside = $and_ps(
+                        $and_ps($cmple_ps(zero, u), $cmplt_ps(u, one)),
+                        $and_ps($cmple_ps(zero, v), $cmplt_ps(v, one)),
+                    );
+                    if $movemask_ps(inside) == 0 {
+                        index += LANES;
+                        continue;
+                    }
+
+                    // NOTE: Lanes outside the quad sample the corner instead
+                    // of reading past the bitmap, their pixels are kept below
+                    let u = $and_ps(inside, u);
+                    let v = $and_ps(inside, v);
+
+                    let tx = $sub_ps($mul_ps(u, texture_width), half);
+                    let ty = $sub_ps($mul_ps($sub_ps(one, v), texture_height), half);
+                    let mut texel = sample_bilinear(shader.bitmap, tx, ty);
+
+                    if let Some((normal_map, lighting)) = shader.lit {
+                        let n = sample_normal(normal_map, u, v, shader.x_dir, shader.y_dir);
+                        let p = Wide3 {
+                            x: screen_x,
+                            y: screen_y,
+                            z: zero,
+                        };
+                        texel = light_texel(texel, n, p, lighting);
+                    }
+                    let tint = shader.tint;
+                    let texel = Wide4 {
+                        r: $mul_ps(texel.r, $set1_ps(tint.r)),
+                        g: $mul_ps(texel.g, $set1_ps(tint.g)),
+                        b: $mul_ps(texel.b, $set1_ps(tint.b)),
+                        a: $mul_ps(texel.a, $set1_ps(tint.a)),
+                    };
+
+                    let dest_packed = $loadu_si(dest_ptr);
+                    let dest = unpack4(dest_packed);
+                    let inv_a = $sub_ps(one, $div_ps(texel.a, two_fifty_five));
+                    let blend = |d: $ps, t: $ps| $add_ps($mul_ps(inv_a, d), t);
+                    let result = pack4(Wide4 {
+                        r: blend(dest.r, texel.r),
+                        g: blend(dest.g, texel.g),
+                        b: blend(dest.b, texel.b),
+                        a: blend(dest.a, texel.a),
+                    });
+
+                    let inside = $castps_si(inside);
+                    let merged = $or_si($and_si(inside, result), $andnot_si(inside, dest_packed));
+                    $storeu_si(dest_ptr, merged);
+
+                    index += LANES;
+                }
+
+                index
+            }
+        }
+    };
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+wide_quad_shader! {
+    mod quad_sse2, feature = "sse2", lanes = 4,
+    __m128, __m128i,
+    set1_ps: _mm_set1_ps,
+    set1_epi32: _mm_set1_epi32,
+    add_ps: _mm_add_ps,
+    sub_ps: _mm_sub_ps,
+    mul_ps: _mm_mul_ps,
+    div_ps: _mm_div_ps,
+    min_ps: _mm_min_ps,
+    max_ps: _mm_max_ps,
+    sqrt_ps: _mm_sqrt_ps,
+    cmplt_ps: _mm_cmplt_ps,
+    cmple_ps: _mm_cmple_ps,
+    and_ps: _mm_and_ps,
+    movemask_ps: _mm_movemask_ps,
+    cvtepi32_ps: _mm_cvtepi32_ps,
+    cvttps_epi32: _mm_cvttps_epi32,
+    castps_si: _mm_castps_si128,
+    add_epi32: _mm_add_epi32,
+    and_si: _mm_and_si128,
+    or_si: _mm_or_si128,
+    andnot_si: _mm_andnot_si128,
+    srli_epi32: _mm_srli_epi32,
+    slli_epi32: _mm_slli_epi32,
+    loadu_si: _mm_loadu_si128,
+    storeu_si: _mm_storeu_si128,
+}
+
+// NOTE: AVX only has the general compare, these give it the SSE2 shape
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+#[inline]
+unsafe fn mm256_cmplt_ps(a: __m256, b: __m256) -> __m256 {
+    _mm256_cmp_ps::<_CMP_LT_OQ>(a, b)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+#[inline]
+unsafe fn mm256_cmple_ps(a: __m256, b: __m256) -> __m256 {
+    _mm256_cmp_ps::<_CMP_LE_OQ>(a, b)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+wide_quad_shader! {
+    mod quad_avx2, feature = "avx2", lanes = 8,
+    __m256, __m256i,
+    set1_ps: _mm256_set1_ps,
+    set1_epi32: _mm256_set1_epi32,
+    add_ps: _mm256_add_ps,
+    sub_ps: _mm256_sub_ps,
+    mul_ps: _mm256_mul_ps,
+    div_ps: _mm256_div_ps,
+    min_ps: _mm256_min_ps,
+    max_ps: _mm256_max_ps,
+    sqrt_ps: _mm256_sqrt_ps,
+    cmplt_ps: mm256_cmplt_ps,
+    cmple_ps: mm256_cmple_ps,
+    and_ps: _mm256_and_ps,
+    movemask_ps: _mm256_movemask_ps,
+    cvtepi32_ps: _mm256_cvtepi32_ps,
+    cvttps_epi32: _mm256_cvttps_epi32,
+    castps_si: _mm256_castps_si256,
+    add_epi32: _mm256_add_epi32,
+    and_si: _mm256_and_si256,
+    or_si: _mm256_or_si256,
+    andnot_si: _mm256_andnot_si256,
+    srli_epi32: _mm256_srli_epi32,
+    slli_epi32: _mm256_slli_epi32,
+    loadu_si: _mm256_loadu_si256,
+    storeu_si: _mm256_storeu_si256,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum RenderEntry {
+    Clear {
+        color: V4,
+    },
+    // NOTE: p is the center of the rectangle, in meters relative to the camera
+    Rectangle {
+        p: V2,
+        dim: V2,
+        color: V4,
+    },
+    // NOTE: p is where the bitmap's alignment point lands, in meters relative to the camera
+    Bitmap {
+        bitmap: LoadedBitmap,
+        p: V2,
+        alpha: f32,
+    },
+    TexturedQuad(TexturedQuad),
+    // NOTE: p is the top-left of the first line in screen pixels, for overlays and debug output
+    Text {
+        p: V2,
+        text_start: usize,
+        text_length: usize,
+        scale: f32,
+        color: V4,
+        clip: Option<Rectangle2i>,
+    },
+}
+
+#[derive(Clone, Copy, Debug)]
+struct SortEntry {
+    layer: i32,
+    y: f32,
+    index: u32,
+}
+
+pub const CLEAR_LAYER: i32 = i32::MIN;
+
+pub struct RenderGroup<'a> {
+    pub meters_to_pixels: f32,
+    pub lighting: Lighting,
+    pub font: Option<LoadedFont>,
+
+    entry_count: usize,
+    entries: &'a mut [RenderEntry],
+    sort_entries: &'a mut [SortEntry],
+
+    text_used: usize,
+    text: &'a mut [u8],
+}
+
+pub fn allocate_render_group(
+    arena: &mut MemoryArena,
+    max_entry_count: usize,
+    max_text_size: usize,
+    meters_to_pixels: f32,
+) -> Option<RenderGroup<'_>> {
+    let entries = push_array::<RenderEntry>(arena, max_entry_count)?;
+    let sort_entries = push_array::<SortEntry>(arena, max_entry_count)?;
+    let text = push_array::<u8>(arena, max_text_size)?;
+
+    // NOTE: Zeroed memory is not a valid enum, so fill the slots before
+    // handing them out as a slice
+    for index in 0..max_entry_count {
+        unsafe {
+            entries.add(index).write(RenderEntry::Clear {
+                color: V4::default(),
+            })
+        };
+    }
+
+    unsafe {
+        Some(RenderGroup {
+            meters_to_pixels,
+            // NOTE: Full white all around leaves normal mapped quads unlit
+            lighting: Lighting {
+                sky_color: v3(1.0, 1.0, 1.0),
+                ground_color: v3(1.0, 1.0, 1.0),
+                ..Lighting::default()
+            },
+            font: None,
+
+            entry_count: 0,
+            entries: slice::from_raw_parts_mut(entries, max_entry_count),
+            sort_entries: slice::from_raw_parts_mut(sort_entries, max_entry_count),
+
+            text_used: 0,
+            text: slice::from_raw_parts_mut(text, max_text_size),
+        })
+    }
+}
+
+// NOTE: Entries draw in ascending layer order, back to front by y inside a
+// layer, and in push order when both match
+fn push_entry(group: &mut RenderGroup, layer: i32, y: f32, entry: RenderEntry) {
+    if group.entry_count == group.entries.len() {
+        // TODO: Grow the push buffer instead of dropping entries on the floor
+        return;
+    }
+
+    let index = group.entry_count;
+    group.entries[index] = entry;
+    group.sort_entries[index] = SortEntry {
+        layer,
+        y,
+        index: index as u32,
+    };
+    group.entry_count += 1;
+}
+
+pub fn push_clear(group: &mut RenderGroup, color: V4) {
+    push_entry(group, CLEAR_LAYER, 0.0, RenderEntry::Clear { color });
+}
+
+pub fn push_rectangle(group: &mut RenderGroup, p: V2, dim: V2, color: V4, layer: i32) {
+    push_entry(group, layer, p.y, RenderEntry::Rectangle { p, dim, color });
+}
+
+pub fn push_bitmap(group: &mut RenderGroup, bitmap: &LoadedBitmap, p: V2, alpha: f32, layer: i32) {
+    push_entry(
+        group,
+        layer,
+        p.y,
+        RenderEntry::Bitmap {
+            bitmap: *bitmap,
+            p,
+            alpha,
+        },
+    );
+}
+
+pub fn push_textured_quad(group: &mut RenderGroup, quad: TexturedQuad, layer: i32) {
+    push_entry(group, layer, quad.origin.y, RenderEntry::TexturedQuad(quad));
+}
+
+// NOTE: Lights past MAX_LIGHT_COUNT are dropped
+pub fn push_light(group: &mut RenderGroup, light: PointLight) {
+    let lighting = &mut group.lighting;
+    if lighting.light_count < MAX_LIGHT_COUNT {
+        lighting.lights[lighting.light_count] = light;
+        lighting.light_count += 1;
+    }
+}
+
+// NOTE: Scale is relative to the size the font was baked at, and clip is in
+// screen pixels like p
+pub fn push_text(
+    group: &mut RenderGroup,
+    p: V2,
+    text: &str,
+    scale: f32,
+    color: V4,
+    clip: Option<Rectangle2i>,
+    layer: i32,
+) {
+    let text_start = group.text_used;
+    let text_end = text_start + text.len();
+    if text_end > group.text.len() {
+        return;
+    }
+
+    group.text[text_start..text_end].copy_from_slice(text.as_bytes());
+    group.text_used = text_end;
+
+    push_entry(
+        group,
+        layer,
+        0.0,
+        RenderEntry::Text {
+            p,
+            text_start,
+            text_length: text.len(),
+            scale,
+            color,
+            clip,
+        },
+    );
+}
+
+fn sort_entries(group: &mut RenderGroup) {
+    group.sort_entries[..group.entry_count].sort_unstable_by(|a, b| {
+        a.layer
+            .cmp(&b.layer)
+            .then_with(|| b.y.total_cmp(&a.y))
+            .then_with(|| a.index.cmp(&b.index))
+    });
+}
+
+// NOTE: Line height of the boxes at scale 1, about what the debug font is baked at
+const PLACEHOLDER_LINE_HEIGHT: f32 = 16.0;
+
+// NOTE: Without a font every character is a box, so debug text at least
+// shows its layout
+fn draw_text_placeholder(
+    target: RenderTarget,
+    p: V2,
+    text: &[u8],
+    scale: f32,
+    color: V4,
+    clip: Rectangle2i,
+) {
+    let scale = PLACEHOLDER_LINE_HEIGHT * scale;
+    let mut at = p;
+    for &character in text {
+        if character == b'\n' {
+            at = v2(p.x, at.y + scale);
+            continue;
+        }
+
+        if character != b' ' {
+            draw_rectangle(
+                target,
+                at + v2(0.0, 0.2 * scale),
+                at + v2(0.5 * scale, 0.9 * scale),
+                color,
+                clip,
+            );
+        }
+        at.x += 0.6 * scale;
+    }
+}
+
+// NOTE: p is the top-left of the first line. Glyphs snap to whole pixels,
+// which keeps text at scale 1 exactly as it was baked.
+fn draw_text(
+    target: RenderTarget,
+    font: &LoadedFont,
+    p: V2,
+    text: &[u8],
+    scale: f32,
+    color: V4,
+    clip: Rectangle2i,
+) {
+    let text = str::from_utf8(text).unwrap_or_default();
+
+    let mut pen = v2(p.x, p.y + scale * font.ascent);
+    let mut previous = None;
+    for character in text.chars() {
+        if character == '\n' {
+            pen = v2(p.x, pen.y + scale * font.line_advance);
+            previous = None;
+            continue;
+        }
+
+        let codepoint = character as u32;
+        if let Some(previous) = previous {
+            pen.x += scale * get_kerning(font, previous, codepoint);
+        }
+        previous = Some(codepoint);
+
+        // TODO: Draw a missing glyph box once fonts carry one
+        let Some(glyph) = get_glyph(font, codepoint) else {
+            continue;
+        };
+
+        let bitmap = glyph.bitmap;
+        let dim = scale * v2(bitmap.width as f32, bitmap.height as f32);
+        let top_left = v2(
+            (pen.x - scale * bitmap.align_x as f32).round(),
+            (pen.y - scale * bitmap.align_y as f32).round(),
+        );
+        pen.x += scale * glyph.advance;
+
+        if top_left.x >= clip.max_x as f32
+            || top_left.y >= clip.max_y as f32
+            || top_left.x + dim.x <= clip.min_x as f32
+            || top_left.y + dim.y <= clip.min_y as f32
+        {
+            continue;
+        }
+
+        let quad = TexturedQuad {
+            origin: top_left + v2(0.0, dim.y),
+            x_axis: v2(dim.x, 0.0),
+            y_axis: v2(0.0, -dim.y),
+            color,
+            bitmap,
+            normal_map: None,
+        };
+        draw_textured_quad(target, &quad, None, clip);
+    }
+}
+
+// NOTE: Expects the entries to be sorted already
+fn render_entries(group: &RenderGroup, target: RenderTarget, clip: Rectangle2i) {
+    let screen_center = v2(0.5 * target.width as f32, 0.5 * target.height as f32);
+    let meters_to_pixels = group.meters_to_pixels;
+    let to_screen = |p: V2| {
+        v2(
+            screen_center.x + meters_to_pixels * p.x,
+            screen_center.y - meters_to_pixels * p.y,
+        )
+    };
+
+    let lighting = &group.lighting;
+    let mut screen_lighting = ScreenLighting {
+        sky_color: lighting.sky_color,
+        ground_color: lighting.ground_color,
+        light_count: lighting.light_count,
+        light_p: [V3::default(); MAX_LIGHT_COUNT],
+        light_color: [V3::default(); MAX_LIGHT_COUNT],
+        light_radius: [0.0; MAX_LIGHT_COUNT],
+        debug_show_normals: lighting.debug_show_normals,
+    };
+    for (light_index, light) in lighting.lights[..lighting.light_count].iter().enumerate() {
+        let p = to_screen(light.p);
+        screen_lighting.light_p[light_index] = v3(p.x, p.y, meters_to_pixels * light.height);
+        screen_lighting.light_color[light_index] = light.color;
+        screen_lighting.light_radius[light_index] = meters_to_pixels * light.radius;
+    }
+
+    for sort_entry in &group.sort_entries[..group.entry_count] {
+        match group.entries[sort_entry.index as usize] {
+            RenderEntry::Clear { color } => {
+                let screen_dim = v2(target.width as f32, target.height as f32);
+                draw_rectangle(target, v2(0.0, 0.0), screen_dim, color, clip);
+            }
+            RenderEntry::Rectangle { p, dim, color } => {
+                let center = to_screen(p);
+                let half_dim = 0.5 * meters_to_pixels * dim;
+
+                draw_rectangle(target, center - half_dim, center + half_dim, color, clip);
+            }
+            RenderEntry::Bitmap { bitmap, p, alpha } => {
+                let screen_p = to_screen(p);
+                draw_bitmap(target, &bitmap, screen_p.x, screen_p.y, alpha, clip);
+            }
+            RenderEntry::TexturedQuad(quad) => {
+                // NOTE: Screen y runs down, so the axes flip along with the points
+                let screen_quad = TexturedQuad {
+                    origin: to_screen(quad.origin),
+                    x_axis: meters_to_pixels * v2(quad.x_axis.x, -quad.x_axis.y),
+                    y_axis: meters_to_pixels * v2(quad.y_axis.x, -quad.y_axis.y),
+                    ..quad
+                };
+                draw_textured_quad(target, &screen_quad, Some(&screen_lighting), clip);
+            }
+            RenderEntry::Text {
+                p,
+                text_start,
+                text_length,
+                scale,
+                color,
+                clip: text_clip,
+            } => {
+                let text = &group.text[text_start..text_start + text_length];
+                let clip = text_clip.map_or(clip, |text_clip| intersect(clip, text_clip));
+                match &group.font {
+                    Some(font) => draw_text(target, font, p, text, scale, color, clip),
+                    None => draw_text_placeholder(target, p, text, scale, color, clip),
+                }
+            }
+        }
+    }
+}
+
+pub fn render_group_to_output(group: &mut RenderGroup, buffer: &mut GameOffscreenBuffer) {
+    sort_entries(group);
+
+    let target = render_target(buffer);
+    let clip = Rectangle2i {
+        min_x: 0,
+        min_y: 0,
+        max_x: target.width,
+        max_y: target.height,
+    };
+    render_entries(group, target, clip);
+}
+
+const TILE_COUNT_X: i32 = 4;
+const TILE_COUNT_Y: i32 = 4;
+
+#[derive(Clone, Copy)]
+struct TileRenderWork {
+    group: *const RenderGroup<'static>,
+    target: RenderTarget,
+    clip: Rectangle2i,
+}
+
+fn do_tiled_render_work(data: *mut u8) {
+    let work = unsafe { &*(data as *const TileRenderWork) };
+    let group = unsafe { &*work.group };
+    render_entries(group, work.target, work.clip);
+}
+
+// NOTE: Every pixel goes through the same entries in the same order no
+// matter which tile it lands in, so this matches render_group_to_output
+// bit for bit
+pub fn tiled_render_group_to_output(
+    queue: &PlatformWorkQueue,
+    group: &mut RenderGroup,
+    buffer: &mut GameOffscreenBuffer,
+) {
+    sort_entries(group);
+
+    let target = render_target(buffer);
+
+    // NOTE: Every wide loop is clipped to its tile, so this is not needed
+    // for correctness. Tile edges land on multiples of 8 pixels, the AVX2
+    // width, so rows clipped at a tile's left edge start on the same 8 pixel
+    // grid as the buffer's rows instead of at arbitrary columns.
+    let tile_width = (target.width + TILE_COUNT_X - 1) / TILE_COUNT_X;
+    let tile_width = (tile_width + 7) & !7;
+    let tile_height = (target.height + TILE_COUNT_Y - 1) / TILE_COUNT_Y;
+
+    let group_ptr = (group as *const RenderGroup).cast::<RenderGroup<'static>>();
+    let mut work_array = [TileRenderWork {
+        group: group_ptr,
+        target,
+        clip: Rectangle2i {
+            min_x: 0,
+            min_y: 0,
+            max_x: 0,
+            max_y: 0,
+        },
+    }; (TILE_COUNT_X * TILE_COUNT_Y) as usize];
+
+    for tile_y in 0..TILE_COUNT_Y {
+        for tile_x in 0..TILE_COUNT_X {
+            let work = &mut work_array[(tile_y * TILE_COUNT_X + tile_x) as usize];
+            work.clip = Rectangle2i {
+                min_x: min(target.width, tile_x * tile_width),
+                min_y: min(target.height, tile_y * tile_height),
+                max_x: min(target.width, (tile_x + 1) * tile_width),
+                max_y: min(target.height, (tile_y + 1) * tile_height),
+            };
+        }
+    }
+
+    for work in &mut work_array {
+        add_entry(
+            queue,
+            do_tiled_render_work,
+            work as *mut TileRenderWork as *mut u8,
+        );
+    }
+
+    // NOTE: Keeps work_array, the group and the buffer alive until every tile is done
+    complete_all_work(queue);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::font::tests::font_file;
+    use super::super::simd::tests::{available_levels, random_premultiplied};
+    use super::super::tests::{with_arena, Series};
+    use super::*;
+
+    fn test_buffer(width: i32, height: i32, color: u32) -> GameOffscreenBuffer {
+        GameOffscreenBuffer {
+            memory: color.to_le_bytes().repeat((width * height) as usize),
+            width,
+            height,
+            pitch: width * 4,
+            bytes_per_pixel: 4,
+        }
+    }
+
+    fn buffer_pixel(buffer: &GameOffscreenBuffer, x: i32, y: i32) -> u32 {
+        let at = (y * buffer.pitch + x * buffer.bytes_per_pixel) as usize;
+        u32::from_le_bytes(buffer.memory[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn draw_bitmap_clips_and_blends_premultiplied_alpha() {
+        let mut pixels = [0xFFFF0000u32, 0x80008000, 0x00000000, 0xFF0000FF];
+        let bitmap = LoadedBitmap {
+            width: 2,
+            height: 2,
+            pitch: 8,
+            align_x: 1,
+            align_y: 1,
+            memory: pixels.as_mut_ptr(),
+        };
+        let mut buffer = test_buffer(2, 2, 0xFF202020);
+        let target = render_target(&mut buffer);
+        let clip = Rectangle2i {
+            min_x: 0,
+            min_y: 0,
+            max_x: 2,
+            max_y: 2,
+        };
+
+        // NOTE: Aligned on its bottom-right pixel at (0, 0), only that pixel lands
+        draw_bitmap(target, &bitmap, 0.0, 0.0, 1.0, clip);
+        assert_eq!(buffer_pixel(&buffer, 0, 0), 0xFF0000FF);
+        assert_eq!(buffer_pixel(&buffer, 1, 0), 0xFF202020);
+
+        draw_bitmap(target, &bitmap, 2.0, 1.0, 1.0, clip);
+        assert_eq!(buffer_pixel(&buffer, 1, 0), 0xFFFF0000);
+        assert_eq!(buffer_pixel(&buffer, 1, 1), 0xFF202020);
+
+        // NOTE: Half covered green over gray
+        draw_bitmap(target, &bitmap, 1.0, 2.0, 1.0, clip);
+        assert_eq!(buffer_pixel(&buffer, 1, 1), 0xFF109010);
+
+        let white = v4(1.0, 1.0, 1.0, 0.5);
+        draw_rectangle(target, v2(0.0, 0.0), v2(1.0, 1.0), white, clip);
+        assert_eq!(buffer_pixel(&buffer, 0, 0), 0xFF8080FF);
+    }
+
+    #[test]
+    fn render_group_sorts_by_layer_then_depth() {
+        with_arena(64 * 1024, |arena| {
+            let mut buffer = test_buffer(4, 4, 0);
+            let mut group = allocate_render_group(arena, 16, 64, 1.0).unwrap();
+
+            // NOTE: Pushed front to back, so only sorting gets the right result
+            let dim = v2(4.0, 4.0);
+            push_rectangle(&mut group, v2(0.0, -1.0), dim, v4(0.0, 0.0, 1.0, 1.0), 1);
+            push_rectangle(&mut group, v2(0.0, 1.0), dim, v4(0.0, 1.0, 0.0, 1.0), 1);
+            push_rectangle(
+                &mut group,
+                v2(0.0, 0.0),
+                2.0 * dim,
+                v4(1.0, 1.0, 1.0, 1.0),
+                0,
+            );
+            push_clear(&mut group, v4(1.0, 0.0, 0.0, 1.0));
+            render_group_to_output(&mut group, &mut buffer);
+
+            // NOTE: Only the back rectangle reaches the top row, both overlap below it
+            assert_eq!(buffer_pixel(&buffer, 0, 0), 0xFF00FF00);
+            assert_eq!(buffer_pixel(&buffer, 0, 2), 0xFF0000FF);
+            assert_eq!(buffer_pixel(&buffer, 0, 3), 0xFF0000FF);
+        });
+    }
+
+    #[test]
+    fn tiled_output_matches_single_threaded_output() {
+        let mut series = Series(0x0bad_5eed_1234_5678);
+
+        let mut pixels = [0u32; 7 * 5];
+        for pixel in pixels.iter_mut() {
+            let alpha = series.between(0, 255) as u32;
+            let channel = |series: &mut Series| series.between(0, alpha as i32) as u32;
+            *pixel = (alpha << 24)
+                | (channel(&mut series) << 16)
+                | (channel(&mut series) << 8)
+                | channel(&mut series);
+        }
+        let bitmap = LoadedBitmap {
+            width: 7,
+            height: 5,
+            pitch: 7 * 4,
+            align_x: 3,
+            align_y: 4,
+            memory: pixels.as_mut_ptr(),
+        };
+
+        with_arena(256 * 1024, |arena| {
+            let mut group = allocate_render_group(arena, 1024, 256, 10.0).unwrap();
+
+            push_clear(&mut group, v4(0.2, 0.3, 0.4, 1.0));
+            group.lighting.sky_color = v3(0.5, 0.6, 0.7);
+            group.lighting.ground_color = v3(0.1, 0.2, 0.1);
+            for _ in 0..3 {
+                let light = PointLight {
+                    p: v2(10.0 * series.bilateral(), 6.0 * series.bilateral()),
+                    height: 2.0 * series.unilateral(),
+                    color: v3(series.unilateral(), 1.0, series.unilateral()),
+                    radius: 10.0,
+                };
+                push_light(&mut group, light);
+            }
+            for _ in 0..400 {
+                let p = v2(12.0 * series.bilateral(), 8.0 * series.bilateral());
+                let layer = series.between(0, 3);
+                let kind = series.between(0, 2);
+                if kind == 0 {
+                    let dim = v2(6.0 * series.unilateral(), 6.0 * series.unilateral());
+                    let color = v4(
+                        series.unilateral(),
+                        series.unilateral(),
+                        series.unilateral(),
+                        series.unilateral(),
+                    );
+                    push_rectangle(&mut group, p, dim, color, layer);
+                } else if kind == 1 {
+                    push_bitmap(&mut group, &bitmap, p, series.unilateral(), layer);
+                } else {
+                    let x_axis = 4.0 * v2(series.bilateral(), series.bilateral());
+                    let y_axis = 4.0 * v2(series.bilateral(), series.bilateral());
+                    let color = v4(1.0, series.unilateral(), 1.0, series.unilateral());
+                    let quad = TexturedQuad {
+                        origin: p,
+                        x_axis,
+                        y_axis,
+                        color,
+                        bitmap,
+                        normal_map: Some(bitmap),
+                    };
+                    push_textured_quad(&mut group, quad, layer);
+                }
+            }
+            push_text(
+                &mut group,
+                v2(3.5, 90.0),
+                "tiles\nok",
+                0.6,
+                v4(1.0, 1.0, 1.0, 0.7),
+                None,
+                4,
+            );
+
+            // NOTE: Odd sizes so the last row and column of tiles come up short
+            let mut expected = test_buffer(203, 117, 0);
+            render_group_to_output(&mut group, &mut expected);
+
+            let queue = make_work_queue(4);
+            let mut tiled = test_buffer(203, 117, 0);
+            tiled_render_group_to_output(&queue, &mut group, &mut tiled);
+
+            assert!(expected.memory == tiled.memory);
+        });
+    }
+
+    fn test_bitmap(pixels: &mut [u32], width: i32, height: i32) -> LoadedBitmap {
+        assert_eq!(pixels.len(), (width * height) as usize);
+        LoadedBitmap {
+            width,
+            height,
+            pitch: width * 4,
+            align_x: 0,
+            align_y: 0,
+            memory: pixels.as_mut_ptr(),
+        }
+    }
+
+    fn unlit() -> ScreenLighting {
+        ScreenLighting {
+            sky_color: v3(1.0, 1.0, 1.0),
+            ground_color: v3(1.0, 1.0, 1.0),
+            light_count: 0,
+            light_p: [V3::default(); MAX_LIGHT_COUNT],
+            light_color: [V3::default(); MAX_LIGHT_COUNT],
+            light_radius: [0.0; MAX_LIGHT_COUNT],
+            debug_show_normals: false,
+        }
+    }
+
+    fn draw_quad(
+        target: RenderTarget,
+        origin: V2,
+        x_axis: V2,
+        y_axis: V2,
+        color: V4,
+        bitmap: &LoadedBitmap,
+        clip: Rectangle2i,
+    ) {
+        let quad = TexturedQuad {
+            origin,
+            x_axis,
+            y_axis,
+            color,
+            bitmap: *bitmap,
+            normal_map: None,
+        };
+        draw_textured_quad(target, &quad, None, clip);
+    }
+
+    fn full_clip(buffer: &GameOffscreenBuffer) -> Rectangle2i {
+        Rectangle2i {
+            min_x: 0,
+            min_y: 0,
+            max_x: buffer.width,
+            max_y: buffer.height,
+        }
+    }
+
+    #[test]
+    fn textured_quad_on_pixel_centers_copies_the_bitmap() {
+        let mut pixels: Vec<u32> = (0..16u32).map(|i| 0xFF000000 | (i * 0x0F0B07)).collect();
+        let bitmap = test_bitmap(&mut pixels, 4, 4);
+        let mut buffer = test_buffer(6, 6, 0);
+        let target = render_target(&mut buffer);
+        let clip = full_clip(&buffer);
+
+        let white = v4(1.0, 1.0, 1.0, 1.0);
+        draw_quad(
+            target,
+            v2(1.0, 5.0),
+            v2(4.0, 0.0),
+            v2(0.0, -4.0),
+            white,
+            &bitmap,
+            clip,
+        );
+
+        for y in 0..6 {
+            for x in 0..6 {
+                let expected = if (1..5).contains(&x) && (1..5).contains(&y) {
+                    pixels[((y - 1) * 4 + (x - 1)) as usize]
+                } else {
+                    0
+                };
+                assert_eq!(buffer_pixel(&buffer, x, y), expected, "pixel {x}, {y}");
+            }
+        }
+    }
+
+    #[test]
+    fn textured_quad_rotates_with_its_axes() {
+        let (a, b, c, d) = (0xFF110000, 0xFF002200, 0xFF000033, 0xFF444444);
+        let mut pixels = [a, b, c, d];
+        let bitmap = test_bitmap(&mut pixels, 2, 2);
+        let mut buffer = test_buffer(2, 2, 0);
+        let target = render_target(&mut buffer);
+        let clip = full_clip(&buffer);
+
+        // NOTE: X axis pointing up the screen and Y axis pointing left is a
+        // quarter turn counterclockwise
+        let white = v4(1.0, 1.0, 1.0, 1.0);
+        draw_quad(
+            target,
+            v2(2.0, 2.0),
+            v2(0.0, -2.0),
+            v2(-2.0, 0.0),
+            white,
+            &bitmap,
+            clip,
+        );
+
+        assert_eq!(buffer_pixel(&buffer, 0, 0), b);
+        assert_eq!(buffer_pixel(&buffer, 1, 0), d);
+        assert_eq!(buffer_pixel(&buffer, 0, 1), a);
+        assert_eq!(buffer_pixel(&buffer, 1, 1), c);
+    }
+
+    #[test]
+    fn textured_quad_filters_bilinearly_and_tints() {
+        let mut pixels = [0xFF000000, 0xFFFFFFFF];
+        let bitmap = test_bitmap(&mut pixels, 2, 1);
+        let mut buffer = test_buffer(4, 1, 0);
+        let target = render_target(&mut buffer);
+        let clip = full_clip(&buffer);
+
+        let white = v4(1.0, 1.0, 1.0, 1.0);
+        draw_quad(
+            target,
+            v2(0.0, 1.0),
+            v2(4.0, 0.0),
+            v2(0.0, -1.0),
+            white,
+            &bitmap,
+            clip,
+        );
+        assert_eq!(buffer_pixel(&buffer, 0, 0), 0xFF000000);
+        assert_eq!(buffer_pixel(&buffer, 1, 0), 0xFF404040);
+        assert_eq!(buffer_pixel(&buffer, 2, 0), 0xFFBFBFBF);
+        assert_eq!(buffer_pixel(&buffer, 3, 0), 0xFFFFFFFF);
+
+        // NOTE: A half transparent red tint over what is already there
+        let tint = v4(1.0, 0.0, 0.0, 0.5);
+        draw_quad(
+            target,
+            v2(0.0, 1.0),
+            v2(4.0, 0.0),
+            v2(0.0, -1.0),
+            tint,
+            &bitmap,
+            clip,
+        );
+        assert_eq!(buffer_pixel(&buffer, 3, 0), 0xFFFF8080);
+    }
+
+    #[test]
+    fn textured_quad_edges_follow_pixel_centers() {
+        let mut pixels = [0xFFFFFFFF];
+        let bitmap = test_bitmap(&mut pixels, 1, 1);
+        let white = v4(1.0, 1.0, 1.0, 1.0);
+        let row = |buffer: &GameOffscreenBuffer| -> Vec<u32> {
+            (0..4).map(|x| buffer_pixel(buffer, x, 0)).collect()
+        };
+
+        let mut narrow = test_buffer(4, 1, 0);
+        let target = render_target(&mut narrow);
+        let clip = full_clip(&narrow);
+        draw_quad(
+            target,
+            v2(0.6, 1.0),
+            v2(1.8, 0.0),
+            v2(0.0, -1.0),
+            white,
+            &bitmap,
+            clip,
+        );
+        assert_eq!(row(&narrow), [0, 0xFFFFFFFF, 0, 0]);
+
+        let mut wide = test_buffer(4, 1, 0);
+        let target = render_target(&mut wide);
+        let clip = full_clip(&wide);
+        draw_quad(
+            target,
+            v2(0.4, 1.0),
+            v2(2.2, 0.0),
+            v2(0.0, -1.0),
+            white,
+            &bitmap,
+            clip,
+        );
+        assert_eq!(row(&wide), [0xFFFFFFFF, 0xFFFFFFFF, 0xFFFFFFFF, 0]);
+    }
+
+    // NOTE: 0x80 in a normal map is only nearly zero, so lit results can be
+    // a step off either way
+    fn assert_pixel_near(buffer: &GameOffscreenBuffer, x: i32, y: i32, expected: u32) {
+        let pixel = buffer_pixel(buffer, x, y);
+        for shift in [0, 8, 16, 24] {
+            let channel = ((pixel >> shift) & 0xFF) as i32;
+            let expected_channel = ((expected >> shift) & 0xFF) as i32;
+            assert!(
+                (channel - expected_channel).abs() <= 1,
+                "{pixel:08x} vs {expected:08x}"
+            );
+        }
+    }
+
+    // NOTE: A normal map that points every normal along +x of the bitmap
+    const FACING_RIGHT: u32 = 0xFFFF8080;
+
+    #[test]
+    fn normal_mapped_quad_is_lit_from_the_side_it_faces() {
+        let mut pixels = [0xFFFFFFFF];
+        let bitmap = test_bitmap(&mut pixels, 1, 1);
+        let mut normals = [FACING_RIGHT];
+        let normal_map = test_bitmap(&mut normals, 1, 1);
+
+        let mut lighting = unlit();
+        lighting.sky_color = V3::default();
+        lighting.ground_color = V3::default();
+        lighting.light_count = 1;
+        lighting.light_p[0] = v3(100.0, 1.0, 0.0);
+        lighting.light_color[0] = v3(1.0, 1.0, 1.0);
+        lighting.light_radius[0] = 100_000.0;
+
+        let mut buffer = test_buffer(2, 2, 0);
+        let target = render_target(&mut buffer);
+        let white = v4(1.0, 1.0, 1.0, 1.0);
+
+        // NOTE: Unrotated the normals face the light on the right, turned half
+        // way round they face away from it and only the (black) environment is left
+        let facing = TexturedQuad {
+            origin: v2(0.0, 1.0),
+            x_axis: v2(1.0, 0.0),
+            y_axis: v2(0.0, -1.0),
+            color: white,
+            bitmap,
+            normal_map: Some(normal_map),
+        };
+        let turned = TexturedQuad {
+            origin: v2(1.0, 1.0),
+            x_axis: v2(-1.0, 0.0),
+            y_axis: v2(0.0, 1.0),
+            ..facing
+        };
+        draw_textured_quad(target, &facing, Some(&lighting), full_clip(&buffer));
+        draw_textured_quad(target, &turned, Some(&lighting), full_clip(&buffer));
+
+        let lit = buffer_pixel(&buffer, 0, 0);
+        assert!(lit & 0xFF > 0xF0, "{lit:08x}");
+        assert_eq!(buffer_pixel(&buffer, 0, 1), 0xFF000000);
+
+        // NOTE: Sky and ground split evenly for a flat facing normal
+        lighting.light_count = 0;
+        lighting.sky_color = v3(1.0, 0.0, 0.0);
+        lighting.ground_color = v3(0.0, 0.0, 1.0);
+        draw_textured_quad(target, &facing, Some(&lighting), full_clip(&buffer));
+        assert_pixel_near(&buffer, 0, 0, 0xFF800080);
+    }
+
+    #[test]
+    fn debug_view_shows_rotated_normals() {
+        let mut pixels = [0xFFFFFFFF];
+        let bitmap = test_bitmap(&mut pixels, 1, 1);
+        let mut normals = [FACING_RIGHT];
+        let normal_map = test_bitmap(&mut normals, 1, 1);
+
+        let mut lighting = unlit();
+        lighting.debug_show_normals = true;
+
+        // NOTE: A quarter turn counterclockwise points the normal up the screen
+        let quad = TexturedQuad {
+            origin: v2(1.0, 1.0),
+            x_axis: v2(0.0, -1.0),
+            y_axis: v2(-1.0, 0.0),
+            color: v4(1.0, 1.0, 1.0, 1.0),
+            bitmap,
+            normal_map: Some(normal_map),
+        };
+        let mut buffer = test_buffer(1, 1, 0);
+        let target = render_target(&mut buffer);
+        draw_textured_quad(target, &quad, Some(&lighting), full_clip(&buffer));
+
+        assert_pixel_near(&buffer, 0, 0, 0xFF80FF80);
+    }
+
+    #[test]
+    fn every_level_draws_identical_textured_quads() {
+        let mut series = Series(0x7e57_ab1e_0dd5_0002);
+
+        let mut pixels: Vec<u32> = (0..7 * 5)
+            .map(|_| random_premultiplied(&mut series))
+            .collect();
+        let bitmap = test_bitmap(&mut pixels, 7, 5);
+        let mut normals: Vec<u32> = (0..6 * 4)
+            .map(|_| 0xFF000000 | (series.next_u32() & 0xFFFFFF))
+            .collect();
+        let normal_map = test_bitmap(&mut normals, 6, 4);
+
+        let (width, height) = (37, 29);
+        let mut lighting = unlit();
+        for _ in 0..200 {
+            lighting.sky_color = v3(series.unilateral(), series.unilateral(), 1.0);
+            lighting.ground_color = v3(series.unilateral(), 0.2, series.unilateral());
+            lighting.light_count = series.between(0, MAX_LIGHT_COUNT as i32) as usize;
+            for light_index in 0..lighting.light_count {
+                lighting.light_p[light_index] = v3(
+                    width as f32 * series.unilateral(),
+                    height as f32 * series.unilateral(),
+                    10.0 * series.unilateral(),
+                );
+                lighting.light_color[light_index] = v3(1.0, series.unilateral(), 0.5);
+                lighting.light_radius[light_index] = 40.0 * series.unilateral();
+            }
+            lighting.debug_show_normals = series.between(0, 3) == 0;
+
+            // NOTE: Skewed, flipped and turned every which way, lit or not
+            let quad = TexturedQuad {
+                origin: v2(
+                    width as f32 * series.unilateral(),
+                    height as f32 * series.unilateral(),
+                ),
+                x_axis: 30.0 * v2(series.bilateral(), series.bilateral()),
+                y_axis: 30.0 * v2(series.bilateral(), series.bilateral()),
+                color: v4(
+                    series.unilateral(),
+                    1.0,
+                    series.unilateral(),
+                    series.unilateral(),
+                ),
+                bitmap,
+                normal_map: (series.between(0, 2) != 0).then_some(normal_map),
+            };
+            let dest = test_buffer(width, height, random_premultiplied(&mut series));
+
+            let mut expected = None;
+            for level in available_levels() {
+                let mut buffer = test_buffer(width, height, 0);
+                buffer.memory.copy_from_slice(&dest.memory);
+                let target = RenderTarget {
+                    simd_level: level,
+                    ..render_target(&mut buffer)
+                };
+                draw_textured_quad(target, &quad, Some(&lighting), full_clip(&buffer));
+
+                match &expected {
+                    None => expected = Some(buffer.memory),
+                    Some(expected) => assert!(*expected == buffer.memory, "{level:?} differs"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn draw_text_lays_out_kerns_and_clips() {
+        with_arena(64 * 1024, |arena| {
+            #[rustfmt::skip]
+            let atlas = [
+                0xFF, 0xFF, 0x00, 0xFF,
+                0xFF, 0xFF, 0x00, 0x00,
+            ];
+            let contents = font_file(
+                b'A' as u32,
+                &[(0, 0, 2, 2, 0, 2, 3.0), (3, 0, 1, 1, 0, 1, 2.0)],
+                &[(b'A' as u32, b'B' as u32, -1.0)],
+                4,
+                &atlas,
+            );
+            let font = parse_font(arena, &contents).unwrap();
+
+            let lit_pixels = |buffer: &GameOffscreenBuffer| {
+                let mut result = Vec::new();
+                for y in 0..buffer.height {
+                    for x in 0..buffer.width {
+                        let pixel = buffer_pixel(buffer, x, y);
+                        if pixel != 0xFF000000 {
+                            result.push((x, y, pixel));
+                        }
+                    }
+                }
+                result
+            };
+
+            // NOTE: The font's ascent is 8 and its line advance 12, so the
+            // baselines are at 8 and 20
+            let white = v4(1.0, 1.0, 1.0, 1.0);
+            let mut buffer = test_buffer(8, 24, 0xFF000000);
+            let target = render_target(&mut buffer);
+            let clip = full_clip(&buffer);
+            draw_text(target, &font, v2(1.0, 0.0), b"AB\nA", 1.0, white, clip);
+            let w = 0xFFFFFFFF;
+            assert_eq!(
+                lit_pixels(&buffer),
+                [
+                    (1, 6, w),
+                    (2, 6, w),
+                    (1, 7, w),
+                    (2, 7, w),
+                    (3, 7, w),
+                    (1, 18, w),
+                    (2, 18, w),
+                    (1, 19, w),
+                    (2, 19, w),
+                ]
+            );
+
+            let mut clipped = test_buffer(8, 24, 0xFF000000);
+            let target = render_target(&mut clipped);
+            let clip = Rectangle2i {
+                min_x: 0,
+                min_y: 0,
+                max_x: 2,
+                max_y: 12,
+            };
+            draw_text(target, &font, v2(1.0, 0.0), b"AB\nA", 1.0, white, clip);
+            assert_eq!(lit_pixels(&clipped), [(1, 6, w), (1, 7, w)]);
+
+            let mut scaled = test_buffer(8, 24, 0xFF000000);
+            let target = render_target(&mut scaled);
+            let clip = full_clip(&scaled);
+            draw_text(
+                target,
+                &font,
+                v2(1.0, 0.0),
+                b"A",
+                2.0,
+                v4(1.0, 0.0, 0.0, 1.0),
+                clip,
+            );
+            let expected: Vec<_> = (12..16)
+                .flat_map(|y| (1..5).map(move |x| (x, y, 0xFFFF0000)))
+                .collect();
+            assert_eq!(lit_pixels(&scaled), expected);
+        });
+    }
+}
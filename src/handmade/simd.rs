@@ -0,0 +1,334 @@
+#[cfg(target_arch = "x86")]
+use std::arch::x86::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+use super::round_real32_to_uint32;
+
+// NOTE: The wide paths do the exact same float operations as the scalar
+// one, one lane per pixel channel, and never fuse the multiply and add, so
+// every level produces identical pixels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SimdLevel {
+    Scalar,
+    Sse2,
+    Avx2,
+}
+
+pub fn detect_simd_level() -> SimdLevel {
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    {
+        if is_x86_feature_detected!("avx2") {
+            return SimdLevel::Avx2;
+        }
+        if is_x86_feature_detected!("sse2") {
+            return SimdLevel::Sse2;
+        }
+    }
+
+    SimdLevel::Scalar
+}
+
+// NOTE: Both colors are premultiplied ARGB; c_alpha fades the whole source
+pub fn blend_premultiplied(dest: u32, source: u32, c_alpha: f32) -> u32 {
+    let sa = c_alpha * ((source >> 24) & 0xFF) as f32;
+    let inv_sa = 1.0 - sa / 255.0;
+
+    let mut result = 0;
+    for shift in [0, 8, 16, 24] {
+        let s = c_alpha * ((source >> shift) & 0xFF) as f32;
+        let d = ((dest >> shift) & 0xFF) as f32;
+        let blended = d * inv_sa + s;
+
+        result |= round_real32_to_uint32(blended.min(255.0)) << shift;
+    }
+
+    result
+}
+
+// NOTE: Rows are raw pointers because tile jobs share the offscreen buffer;
+// the caller guarantees count pixels are valid at each of them
+
+pub unsafe fn fill_row(level: SimdLevel, dest: *mut u32, count: usize, color: u32) {
+    let mut done = 0;
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    match level {
+        SimdLevel::Avx2 => done = fill_row_avx2(dest, count, color),
+        SimdLevel::Sse2 => done = fill_row_sse2(dest, count, color),
+        SimdLevel::Scalar => {}
+    }
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+    let _ = level;
+
+    for index in done..count {
+        dest.add(index).write_unaligned(color.to_le());
+    }
+}
+
+// NOTE: Blends the same source color over every pixel in the row
+pub unsafe fn blend_solid_row(level: SimdLevel, dest: *mut u32, count: usize, color: u32) {
+    let mut done = 0;
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    match level {
+        SimdLevel::Avx2 => done = blend_row_avx2(dest, None, color, count, 1.0),
+        SimdLevel::Sse2 => done = blend_row_sse2(dest, None, color, count, 1.0),
+        SimdLevel::Scalar => {}
+    }
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+    let _ = level;
+
+    for index in done..count {
+        let pixel = dest.add(index);
+        let result = blend_premultiplied(u32::from_le(pixel.read_unaligned()), color, 1.0);
+        pixel.write_unaligned(result.to_le());
+    }
+}
+
+pub unsafe fn blend_row(
+    level: SimdLevel,
+    dest: *mut u32,
+    source: *const u32,
+    count: usize,
+    c_alpha: f32,
+) {
+    let mut done = 0;
+
+    #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+    match level {
+        SimdLevel::Avx2 => done = blend_row_avx2(dest, Some(source), 0, count, c_alpha),
+        SimdLevel::Sse2 => done = blend_row_sse2(dest, Some(source), 0, count, c_alpha),
+        SimdLevel::Scalar => {}
+    }
+    #[cfg(not(any(target_arch = "x86", target_arch = "x86_64")))]
+    let _ = level;
+
+    for index in done..count {
+        let pixel = dest.add(index);
+        let source = u32::from_le(source.add(index).read_unaligned());
+        let result = blend_premultiplied(u32::from_le(pixel.read_unaligned()), source, c_alpha);
+        pixel.write_unaligned(result.to_le());
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "sse2")]
+unsafe fn fill_row_sse2(dest: *mut u32, count: usize, color: u32) -> usize {
+    let color_4x = _mm_set1_epi32(color as i32);
+
+    let mut index = 0;
+    while index + 4 <= count {
+        _mm_storeu_si128(dest.add(index) as *mut __m128i, color_4x);
+        index += 4;
+    }
+
+    index
+}
+
+// NOTE: Returns how many pixels it handled, the scalar loop does the rest
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "sse2")]
+unsafe fn blend_row_sse2(
+    dest: *mut u32,
+    source: Option<*const u32>,
+    solid_color: u32,
+    count: usize,
+    c_alpha: f32,
+) -> usize {
+    let mask_ff = _mm_set1_epi32(0xFF);
+    let zero = _mm_setzero_ps();
+    let one = _mm_set1_ps(1.0);
+    let half = _mm_set1_ps(0.5);
+    let two_fifty_five = _mm_set1_ps(255.0);
+    let c_alpha_4x = _mm_set1_ps(c_alpha);
+    let solid_4x = _mm_set1_epi32(solid_color as i32);
+
+    let mut index = 0;
+    while index + 4 <= count {
+        let dest_ptr = dest.add(index) as *mut __m128i;
+        let source_4x = match source {
+            Some(source) => _mm_loadu_si128(source.add(index) as *const __m128i),
+            None => solid_4x,
+        };
+        let dest_4x = _mm_loadu_si128(dest_ptr);
+
+        let sa = _mm_mul_ps(c_alpha_4x, _mm_cvtepi32_ps(_mm_srli_epi32::<24>(source_4x)));
+        let inv_sa = _mm_sub_ps(one, _mm_div_ps(sa, two_fifty_five));
+
+        macro_rules! blend_channel {
+            ($shift:literal) => {{
+                let s = _mm_mul_ps(
+                    c_alpha_4x,
+                    _mm_cvtepi32_ps(_mm_and_si128(_mm_srli_epi32::<$shift>(source_4x), mask_ff)),
+                );
+                let d = _mm_cvtepi32_ps(_mm_and_si128(_mm_srli_epi32::<$shift>(dest_4x), mask_ff));
+                let blended = _mm_min_ps(_mm_add_ps(_mm_mul_ps(d, inv_sa), s), two_fifty_five);
+                let rounded = _mm_cvttps_epi32(_mm_max_ps(_mm_add_ps(blended, half), zero));
+                _mm_slli_epi32::<$shift>(rounded)
+            }};
+        }
+
+        let result = _mm_or_si128(
+            _mm_or_si128(blend_channel!(0), blend_channel!(8)),
+            _mm_or_si128(blend_channel!(16), blend_channel!(24)),
+        );
+        _mm_storeu_si128(dest_ptr, result);
+
+        index += 4;
+    }
+
+    index
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn fill_row_avx2(dest: *mut u32, count: usize, color: u32) -> usize {
+    let color_8x = _mm256_set1_epi32(color as i32);
+
+    let mut index = 0;
+    while index + 8 <= count {
+        _mm256_storeu_si256(dest.add(index) as *mut __m256i, color_8x);
+        index += 8;
+    }
+
+    index
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn blend_row_avx2(
+    dest: *mut u32,
+    source: Option<*const u32>,
+    solid_color: u32,
+    count: usize,
+    c_alpha: f32,
+) -> usize {
+    let mask_ff = _mm256_set1_epi32(0xFF);
+    let zero = _mm256_setzero_ps();
+    let one = _mm256_set1_ps(1.0);
+    let half = _mm256_set1_ps(0.5);
+    let two_fifty_five = _mm256_set1_ps(255.0);
+    let c_alpha_8x = _mm256_set1_ps(c_alpha);
+    let solid_8x = _mm256_set1_epi32(solid_color as i32);
+
+    let mut index = 0;
+    while index + 8 <= count {
+        let dest_ptr = dest.add(index) as *mut __m256i;
+        let source_8x = match source {
+            Some(source) => _mm256_loadu_si256(source.add(index) as *const __m256i),
+            None => solid_8x,
+        };
+        let dest_8x = _mm256_loadu_si256(dest_ptr);
+
+        let sa = _mm256_mul_ps(
+            c_alpha_8x,
+            _mm256_cvtepi32_ps(_mm256_srli_epi32::<24>(source_8x)),
+        );
+        let inv_sa = _mm256_sub_ps(one, _mm256_div_ps(sa, two_fifty_five));
+
+        macro_rules! blend_channel {
+            ($shift:literal) => {{
+                let s = _mm256_mul_ps(
+                    c_alpha_8x,
+                    _mm256_cvtepi32_ps(_mm256_and_si256(
+                        _mm256_srli_epi32::<$shift>(source_8x),
+                        mask_ff,
+                    )),
+                );
+                let d = _mm256_cvtepi32_ps(_mm256_and_si256(
+                    _mm256_srli_epi32::<$shift>(dest_8x),
+                    mask_ff,
+                ));
+                let blended =
+                    _mm256_min_ps(_mm256_add_ps(_mm256_mul_ps(d, inv_sa), s), two_fifty_five);
+                let rounded =
+                    _mm256_cvttps_epi32(_mm256_max_ps(_mm256_add_ps(blended, half), zero));
+                _mm256_slli_epi32::<$shift>(rounded)
+            }};
+        }
+
+        let result = _mm256_or_si256(
+            _mm256_or_si256(blend_channel!(0), blend_channel!(8)),
+            _mm256_or_si256(blend_channel!(16), blend_channel!(24)),
+        );
+        _mm256_storeu_si256(dest_ptr, result);
+
+        index += 8;
+    }
+
+    index
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::super::tests::Series;
+    use super::*;
+
+    pub fn available_levels() -> Vec<SimdLevel> {
+        let mut levels = vec![SimdLevel::Scalar];
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        {
+            if is_x86_feature_detected!("sse2") {
+                levels.push(SimdLevel::Sse2);
+            }
+            if is_x86_feature_detected!("avx2") {
+                levels.push(SimdLevel::Avx2);
+            }
+        }
+        levels
+    }
+
+    pub fn random_premultiplied(series: &mut Series) -> u32 {
+        // NOTE: Bias towards the fully transparent and opaque ends
+        let alpha = match series.between(0, 3) {
+            0 => 0,
+            1 => 255,
+            _ => series.between(0, 255),
+        };
+        let mut result = (alpha as u32) << 24;
+        for shift in [0, 8, 16] {
+            result |= (series.between(0, alpha) as u32) << shift;
+        }
+        result
+    }
+
+    #[test]
+    fn every_level_fills_and_blends_identical_pixels() {
+        let mut series = Series(0x5151_d0d0_aaaa_0001);
+
+        for _ in 0..2000 {
+            let count = series.between(0, 37) as usize;
+            let dest: Vec<u32> = (0..count)
+                .map(|_| random_premultiplied(&mut series))
+                .collect();
+            let source: Vec<u32> = (0..count)
+                .map(|_| random_premultiplied(&mut series))
+                .collect();
+            let color = random_premultiplied(&mut series);
+            let c_alpha = match series.between(0, 2) {
+                0 => 1.0,
+                1 => 0.0,
+                _ => series.unilateral(),
+            };
+
+            let mut expected = None;
+            for level in available_levels() {
+                let mut filled = dest.clone();
+                let mut solid = dest.clone();
+                let mut blended = dest.clone();
+                unsafe {
+                    fill_row(level, filled.as_mut_ptr(), count, color);
+                    blend_solid_row(level, solid.as_mut_ptr(), count, color);
+                    blend_row(level, blended.as_mut_ptr(), source.as_ptr(), count, c_alpha);
+                }
+
+                let result = (filled, solid, blended);
+                match &expected {
+                    None => expected = Some(result),
+                    Some(expected) => assert!(*expected == result, "{level:?} differs"),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,362 @@
+use std::slice;
+
+use super::bitmap::{read_u16, read_u32};
+use super::memory::*;
+use super::{DebugPlatformReadFileRange, ThreadContext};
+
+pub const MAX_SOUND_CHANNEL_COUNT: usize = 2;
+
+// NOTE: Channels are stored one after the other rather than interleaved, so
+// the mixer can walk each of them as a plain run of samples
+#[derive(Clone, Copy, Debug)]
+pub struct LoadedSound {
+    // NOTE: The rate the sound was recorded at, the mixer resamples it to
+    // whatever the platform asks for
+    pub samples_per_second: u32,
+    pub sample_count: u32,
+    pub channel_count: u32,
+    pub samples: [*mut i16; MAX_SOUND_CHANNEL_COUNT],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoadSoundError {
+    ReadFailed,
+    Truncated,
+    Corrupt,
+    Unsupported,
+    OutOfMemory,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WavFormat {
+    pub channel_count: u32,
+    pub samples_per_second: u32,
+    pub bits_per_sample: u32,
+}
+
+// NOTE: Where the samples of a WAV file are, so long ones can be read a
+// chunk at a time instead of all at once
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WavStream {
+    pub format: WavFormat,
+    pub data_offset: u64,
+    pub sample_count: u32,
+}
+
+const WAVE_FORMAT_PCM: u16 = 0x0001;
+const WAVE_FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+// NOTE: Mono or stereo at 16 bits, as big as a frame gets
+pub const MAX_WAV_FRAME_SIZE: usize = 2 * MAX_SOUND_CHANNEL_COUNT;
+
+pub fn sound_is_loaded(sound: &LoadedSound) -> bool {
+    sound.sample_count > 0
+}
+
+pub fn sound_channel(sound: &LoadedSound, channel_index: usize) -> &[i16] {
+    assert!(channel_index < sound.channel_count as usize);
+
+    unsafe { slice::from_raw_parts(sound.samples[channel_index], sound.sample_count as usize) }
+}
+
+// NOTE: Only the "fmt " and "data" chunks matter, everything else a WAV can
+// carry (lists, cue points, broadcast info) is skipped
+pub fn parse_wav(arena: &mut MemoryArena, contents: &[u8]) -> Result<LoadedSound, LoadSoundError> {
+    use LoadSoundError::*;
+
+    if contents.len() < 12 {
+        return Err(Truncated);
+    }
+    if &contents[0..4] != b"RIFF" || &contents[8..12] != b"WAVE" {
+        return Err(Corrupt);
+    }
+
+    let mut format = None;
+    let mut data = None;
+    let mut at = 12;
+    while at + 8 <= contents.len() {
+        let chunk_id = &contents[at..at + 4];
+        let chunk_size = read_u32(contents, at + 4).ok_or(Truncated)? as usize;
+        let chunk_start = at + 8;
+        // NOTE: Writers are known to lie about the last chunk's size
+        let chunk_end = chunk_start.saturating_add(chunk_size).min(contents.len());
+
+        match chunk_id {
+            b"fmt " => format = Some(&contents[chunk_start..chunk_end]),
+            b"data" => data = Some(&contents[chunk_start..chunk_end]),
+            _ => {}
+        }
+
+        // NOTE: Chunks are padded to an even size
+        at = chunk_end + (chunk_size & 1);
+    }
+
+    let format = format.ok_or(Corrupt)?;
+    let data = data.ok_or(Corrupt)?;
+    let format = parse_wav_format(format)?;
+
+    let sample_count = data.len() / wav_frame_size(&format);
+    if sample_count == 0 {
+        return Err(Truncated);
+    }
+    let sample_count = u32::try_from(sample_count).map_err(|_| Unsupported)?;
+
+    let mut sound = LoadedSound {
+        samples_per_second: format.samples_per_second,
+        sample_count,
+        channel_count: format.channel_count,
+        samples: [std::ptr::null_mut(); MAX_SOUND_CHANNEL_COUNT],
+    };
+    for channel_index in 0..format.channel_count as usize {
+        sound.samples[channel_index] =
+            push_array::<i16>(arena, sample_count as usize).ok_or(OutOfMemory)?;
+    }
+    decode_wav_samples(&format, data, &sound);
+
+    Ok(sound)
+}
+
+pub fn wav_frame_size(format: &WavFormat) -> usize {
+    (format.bits_per_sample / 8 * format.channel_count) as usize
+}
+
+fn parse_wav_format(format: &[u8]) -> Result<WavFormat, LoadSoundError> {
+    use LoadSoundError::*;
+
+    let mut format_tag = read_u16(format, 0).ok_or(Truncated)?;
+    let channel_count = read_u16(format, 2).ok_or(Truncated)? as usize;
+    let samples_per_second = read_u32(format, 4).ok_or(Truncated)?;
+    let bits_per_sample = read_u16(format, 14).ok_or(Truncated)?;
+    if format_tag == WAVE_FORMAT_EXTENSIBLE {
+        // NOTE: The real format is the first two bytes of the sub-format GUID
+        format_tag = read_u16(format, 24).ok_or(Truncated)?;
+    }
+
+    if format_tag != WAVE_FORMAT_PCM
+        || channel_count == 0
+        || channel_count > MAX_SOUND_CHANNEL_COUNT
+        || samples_per_second == 0
+        || !(bits_per_sample == 8 || bits_per_sample == 16)
+    {
+        return Err(Unsupported);
+    }
+
+    Ok(WavFormat {
+        channel_count: channel_count as u32,
+        samples_per_second,
+        bits_per_sample: bits_per_sample as u32,
+    })
+}
+
+// NOTE: Splits every whole frame of data into the sound's channels, from its
+// first sample on; the sound has to have room for all of them
+fn decode_wav_samples(format: &WavFormat, data: &[u8], sound: &LoadedSound) {
+    let bytes_per_sample = (format.bits_per_sample / 8) as usize;
+    for (sample_index, frame) in data.chunks_exact(wav_frame_size(format)).enumerate() {
+        for channel_index in 0..format.channel_count as usize {
+            let at = channel_index * bytes_per_sample;
+            let sample = match format.bits_per_sample {
+                // NOTE: 8 bit samples are unsigned around 128
+                8 => ((frame[at] as i16) - 128) << 8,
+                _ => i16::from_le_bytes([frame[at], frame[at + 1]]),
+            };
+            unsafe { sound.samples[channel_index].add(sample_index).write(sample) };
+        }
+    }
+}
+
+// NOTE: Only reads the chunk headers and the format, the samples stay on
+// disk until read_wav_stream asks for them
+pub fn open_wav_stream(
+    thread: &ThreadContext,
+    read_file_range: DebugPlatformReadFileRange,
+    filename: &str,
+) -> Result<WavStream, LoadSoundError> {
+    use LoadSoundError::*;
+
+    let mut header = [0u8; 12];
+    match read_file_range(thread, filename, 0, &mut header) {
+        0 => return Err(ReadFailed),
+        12 => {}
+        _ => return Err(Truncated),
+    }
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return Err(Corrupt);
+    }
+
+    let mut format = None;
+    let mut data = None;
+    let mut at = 12u64;
+    while format.is_none() || data.is_none() {
+        let mut chunk_header = [0u8; 8];
+        if read_file_range(thread, filename, at, &mut chunk_header) < chunk_header.len() {
+            break;
+        }
+        let chunk_size = read_u32(&chunk_header, 4).ok_or(Truncated)?;
+        let chunk_start = at + 8;
+
+        match &chunk_header[0..4] {
+            b"fmt " => {
+                // NOTE: Extensible is the longest format chunk there is
+                let mut contents = [0u8; 40];
+                let size = (chunk_size as usize).min(contents.len());
+                let read = read_file_range(thread, filename, chunk_start, &mut contents[..size]);
+                format = Some(parse_wav_format(&contents[..read])?);
+            }
+            b"data" => data = Some((chunk_start, chunk_size)),
+            _ => {}
+        }
+
+        // NOTE: Chunks are padded to an even size
+        at = chunk_start + chunk_size as u64 + (chunk_size & 1) as u64;
+    }
+
+    let format = format.ok_or(Corrupt)?;
+    let (data_offset, data_size) = data.ok_or(Corrupt)?;
+    let sample_count = (data_size as usize / wav_frame_size(&format)) as u32;
+    if sample_count == 0 {
+        return Err(Truncated);
+    }
+
+    Ok(WavStream {
+        format,
+        data_offset,
+        sample_count,
+    })
+}
+
+// NOTE: Reads from first_sample up to as many samples as fit in staging and
+// in dest, which has to have a buffer per channel; returns how many it got,
+// which is short when the file is shorter than its header claims
+pub fn read_wav_stream(
+    thread: &ThreadContext,
+    read_file_range: DebugPlatformReadFileRange,
+    filename: &str,
+    stream: &WavStream,
+    first_sample: u32,
+    staging: &mut [u8],
+    dest: &mut LoadedSound,
+) -> u32 {
+    let frame_size = wav_frame_size(&stream.format);
+    let wanted = stream.sample_count.saturating_sub(first_sample) as usize;
+    let wanted = wanted.min(staging.len() / frame_size);
+
+    let offset = stream.data_offset + first_sample as u64 * frame_size as u64;
+    let read = read_file_range(
+        thread,
+        filename,
+        offset,
+        &mut staging[..wanted * frame_size],
+    );
+    let sample_count = read / frame_size;
+
+    dest.samples_per_second = stream.format.samples_per_second;
+    dest.channel_count = stream.format.channel_count;
+    dest.sample_count = sample_count as u32;
+    decode_wav_samples(&stream.format, &staging[..sample_count * frame_size], dest);
+
+    sample_count as u32
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::super::tests::with_arena;
+    use super::*;
+
+    pub fn wav_file(
+        samples_per_second: u32,
+        channel_count: u16,
+        bits_per_sample: u16,
+        data: &[u8],
+    ) -> Vec<u8> {
+        let block_align = channel_count * bits_per_sample / 8;
+
+        let mut result = Vec::new();
+        result.extend_from_slice(b"RIFF");
+        result.extend_from_slice(&(4 + 8 + 16 + 8 + 8 + data.len() as u32).to_le_bytes());
+        result.extend_from_slice(b"WAVE");
+
+        result.extend_from_slice(b"fmt ");
+        result.extend_from_slice(&16u32.to_le_bytes());
+        result.extend_from_slice(&WAVE_FORMAT_PCM.to_le_bytes());
+        result.extend_from_slice(&channel_count.to_le_bytes());
+        result.extend_from_slice(&samples_per_second.to_le_bytes());
+        result.extend_from_slice(&(samples_per_second * block_align as u32).to_le_bytes());
+        result.extend_from_slice(&block_align.to_le_bytes());
+        result.extend_from_slice(&bits_per_sample.to_le_bytes());
+
+        // NOTE: An odd sized chunk the loader has to skip, padding included
+        result.extend_from_slice(b"junk");
+        result.extend_from_slice(&3u32.to_le_bytes());
+        result.extend_from_slice(&[1, 2, 3, 0]);
+
+        result.extend_from_slice(b"data");
+        result.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        result.extend_from_slice(data);
+
+        result
+    }
+
+    #[test]
+    fn eight_bit_mono_is_recentered() {
+        let file = wav_file(11025, 1, 8, &[0x80, 0xFF, 0x00]);
+
+        with_arena(64 * 1024, |arena| {
+            let sound = parse_wav(arena, &file).unwrap();
+            assert_eq!(sound.samples_per_second, 11025);
+            assert_eq!((sound.sample_count, sound.channel_count), (3, 1));
+            assert_eq!(sound_channel(&sound, 0), [0, 127 << 8, -128 << 8]);
+        });
+    }
+
+    #[test]
+    fn sixteen_bit_stereo_is_split_into_channels() {
+        let mut data = Vec::new();
+        for sample in [1i16, -1, 1000, -1000, i16::MAX, i16::MIN] {
+            data.extend_from_slice(&sample.to_le_bytes());
+        }
+        // NOTE: A trailing partial frame is dropped
+        data.push(0x7F);
+        let file = wav_file(48000, 2, 16, &data);
+
+        with_arena(64 * 1024, |arena| {
+            let sound = parse_wav(arena, &file).unwrap();
+            assert_eq!((sound.sample_count, sound.channel_count), (3, 2));
+            assert_eq!(sound_channel(&sound, 0), [1, 1000, i16::MAX]);
+            assert_eq!(sound_channel(&sound, 1), [-1, -1000, i16::MIN]);
+        });
+    }
+
+    #[test]
+    fn unsupported_and_truncated_files_are_rejected() {
+        with_arena(64 * 1024, |arena| {
+            let file = wav_file(48000, 1, 24, &[0; 6]);
+            assert_eq!(
+                parse_wav(arena, &file).err(),
+                Some(LoadSoundError::Unsupported)
+            );
+
+            let file = wav_file(48000, 3, 16, &[0; 6]);
+            assert_eq!(
+                parse_wav(arena, &file).err(),
+                Some(LoadSoundError::Unsupported)
+            );
+
+            let file = wav_file(48000, 2, 16, &[0; 3]);
+            assert_eq!(
+                parse_wav(arena, &file).err(),
+                Some(LoadSoundError::Truncated)
+            );
+
+            let file = wav_file(48000, 1, 16, &[0; 4]);
+            assert_eq!(
+                parse_wav(arena, &file[..20]).err(),
+                Some(LoadSoundError::Corrupt)
+            );
+            assert_eq!(
+                parse_wav(arena, &file[..8]).err(),
+                Some(LoadSoundError::Truncated)
+            );
+        });
+    }
+}
@@ -0,0 +1,256 @@
+use std::f32::consts::TAU;
+
+use super::memory::*;
+use super::sound::*;
+
+// NOTE: Patches are rendered once, up front, into plain sounds, so playing
+// one is no different from playing a WAV and gets the same volume, pan,
+// pitch and fades from the mixer
+pub const SYNTH_SAMPLES_PER_SECOND: u32 = 48000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    Sine,
+    Square,
+    Saw,
+    // NOTE: A new random level every cycle, so the pitch still shapes it
+    Noise,
+}
+
+// NOTE: Times in seconds; the sound lasts attack + decay + sustain + release
+#[derive(Clone, Copy, Debug)]
+pub struct Envelope {
+    pub attack: f32,
+    pub decay: f32,
+    pub sustain_level: f32,
+    pub sustain: f32,
+    pub release: f32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct SynthPatch {
+    pub waveform: Waveform,
+    // NOTE: The pitch sweeps from start to end over the whole sound, evenly
+    // in octaves
+    pub start_hz: f32,
+    pub end_hz: f32,
+    pub envelope: Envelope,
+    pub volume: f32,
+}
+
+fn envelope_duration(envelope: &Envelope) -> f32 {
+    envelope.attack + envelope.decay + envelope.sustain + envelope.release
+}
+
+fn envelope_level(envelope: &Envelope, t: f32) -> f32 {
+    let Envelope {
+        attack,
+        decay,
+        sustain_level,
+        sustain,
+        release,
+    } = *envelope;
+
+    if t < attack {
+        t / attack
+    } else if t < attack + decay {
+        1.0 - (1.0 - sustain_level) * (t - attack) / decay
+    } else if t < attack + decay + sustain {
+        sustain_level
+    } else if t < attack + decay + sustain + release {
+        sustain_level * (1.0 - (t - attack - decay - sustain) / release)
+    } else {
+        0.0
+    }
+}
+
+fn oscillator(waveform: Waveform, phase: f32, noise_level: f32) -> f32 {
+    match waveform {
+        Waveform::Sine => (TAU * phase).sin(),
+        Waveform::Square => {
+            if phase < 0.5 {
+                1.0
+            } else {
+                -1.0
+            }
+        }
+        // TODO: Band-limit square and saw if the aliasing on high sweeps
+        // ever gets noticeable
+        Waveform::Saw => 2.0 * phase - 1.0,
+        Waveform::Noise => noise_level,
+    }
+}
+
+// NOTE: xorshift, so the same patch always renders the same noise
+fn next_noise_level(state: &mut u32) -> f32 {
+    *state ^= *state << 13;
+    *state ^= *state >> 17;
+    *state ^= *state << 5;
+    (*state as f32 / u32::MAX as f32) * 2.0 - 1.0
+}
+
+pub fn synthesize_sound(
+    arena: &mut MemoryArena,
+    patch: &SynthPatch,
+) -> Result<LoadedSound, LoadSoundError> {
+    let duration = envelope_duration(&patch.envelope);
+    let sample_count = (duration * SYNTH_SAMPLES_PER_SECOND as f32).round();
+    if !(sample_count >= 1.0 && sample_count <= u32::MAX as f32) {
+        return Err(LoadSoundError::Unsupported);
+    }
+    let sample_count = sample_count as u32;
+
+    let samples =
+        push_array::<i16>(arena, sample_count as usize).ok_or(LoadSoundError::OutOfMemory)?;
+
+    let seconds_per_sample = 1.0 / SYNTH_SAMPLES_PER_SECOND as f32;
+    let octaves = if patch.start_hz > 0.0 && patch.end_hz > 0.0 {
+        (patch.end_hz / patch.start_hz).log2()
+    } else {
+        0.0
+    };
+
+    let mut phase = 0.0f32;
+    let mut noise_state = 0x2545_f491u32;
+    let mut noise_level = next_noise_level(&mut noise_state);
+    for sample_index in 0..sample_count as usize {
+        let t = sample_index as f32 * seconds_per_sample;
+
+        let level = envelope_level(&patch.envelope, t);
+        let value = patch.volume * level * oscillator(patch.waveform, phase, noise_level);
+        let sample = (32767.0 * value).round().clamp(-32768.0, 32767.0) as i16;
+        unsafe { samples.add(sample_index).write(sample) };
+
+        let hz = patch.start_hz * (octaves * t / duration).exp2();
+        phase += hz * seconds_per_sample;
+        if phase >= 1.0 {
+            phase -= phase.floor();
+            noise_level = next_noise_level(&mut noise_state);
+        }
+    }
+
+    Ok(LoadedSound {
+        samples_per_second: SYNTH_SAMPLES_PER_SECOND,
+        sample_count,
+        channel_count: 1,
+        samples: [samples, samples],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::with_arena;
+    use super::*;
+
+    fn flat_envelope(seconds: f32) -> Envelope {
+        Envelope {
+            attack: 0.0,
+            decay: 0.0,
+            sustain_level: 1.0,
+            sustain: seconds,
+            release: 0.0,
+        }
+    }
+
+    fn rising_zero_crossings(samples: &[i16]) -> usize {
+        samples
+            .windows(2)
+            .filter(|pair| pair[0] < 0 && pair[1] >= 0)
+            .count()
+    }
+
+    #[test]
+    fn envelope_ramps_through_each_stage() {
+        let envelope = Envelope {
+            attack: 1.0,
+            decay: 1.0,
+            sustain_level: 0.5,
+            sustain: 2.0,
+            release: 1.0,
+        };
+
+        assert_eq!(envelope_duration(&envelope), 5.0);
+        let levels: Vec<f32> = [0.0, 0.5, 1.0, 1.5, 2.0, 3.5, 4.0, 4.5, 5.0]
+            .iter()
+            .map(|&t| envelope_level(&envelope, t))
+            .collect();
+        assert_eq!(levels, [0.0, 0.5, 1.0, 0.75, 0.5, 0.5, 0.5, 0.25, 0.0]);
+    }
+
+    #[test]
+    fn oscillators_run_at_the_patch_pitch() {
+        with_arena(1024 * 1024, |arena| {
+            for waveform in [Waveform::Sine, Waveform::Square, Waveform::Saw] {
+                let patch = SynthPatch {
+                    waveform,
+                    start_hz: 100.0,
+                    end_hz: 100.0,
+                    envelope: flat_envelope(1.0),
+                    volume: 0.5,
+                };
+                let sound = synthesize_sound(arena, &patch).unwrap();
+                let samples = sound_channel(&sound, 0);
+
+                assert_eq!(sound.sample_count, SYNTH_SAMPLES_PER_SECOND);
+                let crossings = rising_zero_crossings(samples) as i32;
+                assert!((crossings - 100).abs() <= 1, "{waveform:?} {crossings}");
+
+                let peak = samples.iter().map(|&s| (s as i32).abs()).max().unwrap();
+                assert!((peak - 16384).abs() <= 1, "{waveform:?} {peak}");
+            }
+        });
+    }
+
+    #[test]
+    fn sweeps_move_evenly_in_octaves() {
+        with_arena(1024 * 1024, |arena| {
+            let patch = SynthPatch {
+                waveform: Waveform::Square,
+                start_hz: 100.0,
+                end_hz: 400.0,
+                envelope: flat_envelope(2.0),
+                volume: 1.0,
+            };
+            let sound = synthesize_sound(arena, &patch).unwrap();
+            let samples = sound_channel(&sound, 0);
+
+            // NOTE: One octave a second, 100 to 200Hz then 200 to 400Hz, so
+            // about 100/ln(2) and twice that many cycles
+            let half = samples.len() / 2;
+            let first = rising_zero_crossings(&samples[..half]) as i32;
+            let second = rising_zero_crossings(&samples[half..]) as i32;
+            assert!((first - 144).abs() <= 2, "{first}");
+            assert!((second - 289).abs() <= 2, "{second}");
+        });
+    }
+
+    #[test]
+    fn noise_is_repeatable_and_enveloped() {
+        with_arena(1024 * 1024, |arena| {
+            let patch = SynthPatch {
+                waveform: Waveform::Noise,
+                start_hz: 8000.0,
+                end_hz: 2000.0,
+                envelope: Envelope {
+                    attack: 0.01,
+                    decay: 0.0,
+                    sustain_level: 1.0,
+                    sustain: 0.0,
+                    release: 0.09,
+                },
+                volume: 1.0,
+            };
+            let a = synthesize_sound(arena, &patch).unwrap();
+            let b = synthesize_sound(arena, &patch).unwrap();
+
+            assert_eq!(sound_channel(&a, 0), sound_channel(&b, 0));
+            assert_eq!(a.sample_count, 4800);
+
+            let samples = sound_channel(&a, 0);
+            assert_eq!(samples[0], 0);
+            assert!(samples.iter().any(|&s| s > 8000));
+            assert!(samples.iter().any(|&s| s < -8000));
+            assert!(samples[4790..].iter().all(|&s| s.abs() < 400));
+        });
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+// NOTE: Work entries hand raw pointers across threads, so whoever adds an
+// entry must keep its data alive until complete_all_work returns
+pub type PlatformWorkQueueCallback = fn(data: *mut u8);
+
+struct WorkQueueEntry {
+    callback: PlatformWorkQueueCallback,
+    data: *mut u8,
+}
+
+unsafe impl Send for WorkQueueEntry {}
+
+#[derive(Default)]
+struct WorkQueueState {
+    entries: VecDeque<WorkQueueEntry>,
+    completion_goal: u32,
+    completion_count: u32,
+}
+
+pub struct PlatformWorkQueue {
+    state: Mutex<WorkQueueState>,
+    work_available: Condvar,
+    work_finished: Condvar,
+}
+
+pub fn make_work_queue(thread_count: usize) -> Arc<PlatformWorkQueue> {
+    let queue = Arc::new(PlatformWorkQueue {
+        state: Mutex::new(WorkQueueState::default()),
+        work_available: Condvar::new(),
+        work_finished: Condvar::new(),
+    });
+
+    for _ in 0..thread_count {
+        let queue = Arc::clone(&queue);
+        thread::spawn(move || loop {
+            let entry = {
+                let mut state = queue.state.lock().unwrap();
+                loop {
+                    if let Some(entry) = state.entries.pop_front() {
+                        break entry;
+                    }
+                    state = queue.work_available.wait(state).unwrap();
+                }
+            };
+            run_entry(&queue, entry);
+        });
+    }
+
+    queue
+}
+
+fn run_entry(queue: &PlatformWorkQueue, entry: WorkQueueEntry) {
+    (entry.callback)(entry.data);
+
+    let mut state = queue.state.lock().unwrap();
+    state.completion_count += 1;
+    if state.completion_count == state.completion_goal {
+        queue.work_finished.notify_all();
+    }
+}
+
+pub fn add_entry(queue: &PlatformWorkQueue, callback: PlatformWorkQueueCallback, data: *mut u8) {
+    let mut state = queue.state.lock().unwrap();
+    state.entries.push_back(WorkQueueEntry { callback, data });
+    state.completion_goal += 1;
+    queue.work_available.notify_one();
+}
+
+// NOTE: The calling thread works through entries too instead of just waiting
+pub fn complete_all_work(queue: &PlatformWorkQueue) {
+    loop {
+        let mut state = queue.state.lock().unwrap();
+        if let Some(entry) = state.entries.pop_front() {
+            drop(state);
+            run_entry(queue, entry);
+            continue;
+        }
+
+        while state.completion_count != state.completion_goal {
+            state = queue.work_finished.wait(state).unwrap();
+        }
+        state.completion_goal = 0;
+        state.completion_count = 0;
+        return;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn add_one(data: *mut u8) {
+        let counter = unsafe { &*(data as *const AtomicU32) };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn complete_all_work_waits_for_every_entry() {
+        let queue = make_work_queue(3);
+        let counter = AtomicU32::new(0);
+
+        for _ in 0..2 {
+            for _ in 0..100 {
+                add_entry(&queue, add_one, &counter as *const AtomicU32 as *mut u8);
+            }
+            complete_all_work(&queue);
+        }
+
+        assert_eq!(counter.load(Ordering::Relaxed), 200);
+    }
+}
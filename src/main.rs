@@ -1,8 +1,16 @@
 mod handmade;
 
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
 use std::mem;
+use std::process;
+use std::thread;
+use std::time::UNIX_EPOCH;
 
 use handmade::*;
+use sdl2::audio::{AudioQueue, AudioSpecDesired};
+use sdl2::controller::{Button, GameController};
 use sdl2::event::Event;
 use sdl2::keyboard::Scancode;
 use sdl2::pixels::PixelFormatEnum;
@@ -12,21 +20,71 @@ const MOVE_DOWN: usize = 1;
 const MOVE_LEFT: usize = 2;
 const MOVE_RIGHT: usize = 3;
 
+// NOTE: Controller 0 is the keyboard, SDL game controllers take the rest
+const MAX_GAMEPADS: usize = 4;
+
+const SOUND_SAMPLES_PER_SECOND: i32 = 48000;
+// NOTE: How far ahead of the speakers the game keeps the queue filled; a
+// bit over three frames at 30Hz so a slow frame doesn't starve it
+const SOUND_LATENCY_SAMPLE_COUNT: usize = SOUND_SAMPLES_PER_SECOND as usize / 8;
+
 fn debug_platform_free_file_memory(_thread: &ThreadContext, _memory: &mut [u8]) {
     // Implement freeing file memory if necessary
 }
 
 fn debug_platform_read_entire_file(_thread: &ThreadContext, filename: &str) -> DebugReadFileResult {
-    // Implement file reading logic
-    DebugReadFileResult {
-        contents_size: 0,
-        contents: vec![],
+    match fs::read(filename) {
+        Ok(contents) => DebugReadFileResult {
+            contents_size: contents.len() as u32,
+            contents,
+        },
+        // NOTE: A missing or unreadable file comes back empty
+        Err(_) => DebugReadFileResult {
+            contents_size: 0,
+            contents: vec![],
+        },
+    }
+}
+
+fn debug_platform_read_file_range(
+    _thread: &ThreadContext,
+    filename: &str,
+    offset: u64,
+    dest: &mut [u8],
+) -> usize {
+    let Ok(mut file) = File::open(filename) else {
+        return 0;
+    };
+    if file.seek(SeekFrom::Start(offset)).is_err() {
+        return 0;
+    }
+
+    // NOTE: read can come back short before the end, so keep going
+    let mut read = 0;
+    while read < dest.len() {
+        match file.read(&mut dest[read..]) {
+            Ok(0) | Err(_) => break,
+            Ok(count) => read += count,
+        }
     }
+
+    read
 }
 
 fn debug_platform_write_entire_file(_thread: &ThreadContext, filename: &str, data: &[u8]) -> bool {
-    // Implement file writing logic
-    true
+    fs::write(filename, data).is_ok()
+}
+
+fn debug_platform_get_file_write_time(_thread: &ThreadContext, filename: &str) -> u64 {
+    fs::metadata(filename)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since_epoch| since_epoch.as_nanos() as u64)
+}
+
+fn debug_platform_log(_thread: &ThreadContext, message: &str) {
+    eprintln!("handmadehero-rust: {message}");
 }
 
 fn megabytes(value: usize) -> usize {
@@ -45,44 +103,55 @@ fn process_key_press(new_state: &mut GameButtonState, is_down: bool) {
     }
 }
 
-fn main() {
-    // Initialize SDL2
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
+fn gamepad_button_index(button: Button) -> Option<usize> {
+    match button {
+        Button::DPadUp => Some(MOVE_UP),
+        Button::DPadDown => Some(MOVE_DOWN),
+        Button::DPadLeft => Some(MOVE_LEFT),
+        Button::DPadRight => Some(MOVE_RIGHT),
+        Button::RightShoulder => Some(BUTTON_RIGHT_SHOULDER),
+        Button::Back => Some(BUTTON_BACK),
+        Button::Start => Some(BUTTON_START),
+        _ => None,
+    }
+}
 
-    // Create window and canvas
-    let window = video_subsystem
-        .window("Game Window", 960, 540)
-        .position_centered()
-        .resizable()
-        .build()
-        .unwrap();
-    #[cfg(feature = "vsync")]
-    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
-    #[cfg(not(feature = "vsync"))]
-    let mut canvas = window.into_canvas().build().unwrap();
+fn gamepad_slot(gamepads: &[Option<GameController>], instance_id: u32) -> Option<usize> {
+    gamepads.iter().position(|gamepad| {
+        gamepad
+            .as_ref()
+            .is_some_and(|gamepad| gamepad.instance_id() == instance_id)
+    })
+}
 
-    // Create texture for rendering
-    let texture_creator = canvas.texture_creator();
-    let mut texture = texture_creator
-        .create_texture_streaming(PixelFormatEnum::ARGB8888, 960, 540)
-        .unwrap();
+// NOTE: Asset loads mostly wait on the disk, a couple of threads is plenty
+const LOW_PRIORITY_THREAD_COUNT: usize = 2;
+
+fn make_game_memory() -> GameMemory {
+    // NOTE: The main thread works the queue too while it waits on it
+    let worker_thread_count = thread::available_parallelism().map_or(0, |count| count.get() - 1);
 
-    // Allocate game memory
-    let mut game_memory = GameMemory {
+    GameMemory {
         permanent_storage_size: megabytes(64),
         transient_storage_size: gigabytes(1),
         debug_platform_free_file_memory: Some(debug_platform_free_file_memory),
         debug_platform_read_entire_file: Some(debug_platform_read_entire_file),
+        debug_platform_read_file_range: Some(debug_platform_read_file_range),
         debug_platform_write_entire_file: Some(debug_platform_write_entire_file),
+        debug_platform_get_file_write_time: Some(debug_platform_get_file_write_time),
+        debug_platform_log: Some(debug_platform_log),
+        high_priority_queue: Some(make_work_queue(worker_thread_count)),
+        low_priority_queue: Some(make_work_queue(LOW_PRIORITY_THREAD_COUNT)),
         // Initialize other fields as needed
         ..Default::default()
-    };
+    }
+}
 
-    // Game input
+fn make_game_input() -> GameInput {
     let mut game_input = GameInput {
         dt_for_frame: 1.0 / 30.0,
         controllers: [GameControllerInput {
+            is_connected: false,
             is_analog: false,
             stick_average_x: 0.0,
             stick_average_y: 0.0,
@@ -92,20 +161,181 @@ fn main() {
             }; 12],
         }; 5],
     };
+    game_input.controllers[0].is_connected = true;
+
+    game_input
+}
 
-    // Offscreen buffer
-    let mut offscreen_buffer = GameOffscreenBuffer {
+fn make_offscreen_buffer() -> GameOffscreenBuffer {
+    GameOffscreenBuffer {
         memory: vec![0u8; 960 * 540 * 4],
         width: 960,
         height: 540,
         pitch: 960 * 4,
         bytes_per_pixel: 4,
+    }
+}
+
+// NOTE: Everything the game mixed while running headless, and where in it
+// each frame's samples start, so a capture lines up with the frames that
+// made it
+struct SoundCapture {
+    samples_per_second: i32,
+    // NOTE: Stereo, interleaved the way GameSoundOutputBuffer has them
+    samples: Vec<i16>,
+    frame_sample_indices: Vec<u32>,
+}
+
+// NOTE: A plain 16 bit stereo WAV with a cue point at the start of every
+// frame, named by its frame index, which audio editors show as markers
+fn write_capture_wav(capture: &SoundCapture) -> Vec<u8> {
+    let data_size = (capture.samples.len() * mem::size_of::<i16>()) as u32;
+    let cue_size = 4 + 24 * capture.frame_sample_indices.len() as u32;
+    let block_align = 2 * mem::size_of::<i16>() as u32;
+
+    let mut result = Vec::new();
+    result.extend_from_slice(b"RIFF");
+    result.extend_from_slice(&(4 + (8 + 16) + (8 + cue_size) + (8 + data_size)).to_le_bytes());
+    result.extend_from_slice(b"WAVE");
+
+    result.extend_from_slice(b"fmt ");
+    result.extend_from_slice(&16u32.to_le_bytes());
+    result.extend_from_slice(&1u16.to_le_bytes());
+    result.extend_from_slice(&2u16.to_le_bytes());
+    result.extend_from_slice(&(capture.samples_per_second as u32).to_le_bytes());
+    result.extend_from_slice(&(capture.samples_per_second as u32 * block_align).to_le_bytes());
+    result.extend_from_slice(&(block_align as u16).to_le_bytes());
+    result.extend_from_slice(&16u16.to_le_bytes());
+
+    result.extend_from_slice(b"cue ");
+    result.extend_from_slice(&cue_size.to_le_bytes());
+    result.extend_from_slice(&(capture.frame_sample_indices.len() as u32).to_le_bytes());
+    for (frame_index, &sample_index) in capture.frame_sample_indices.iter().enumerate() {
+        result.extend_from_slice(&(frame_index as u32).to_le_bytes());
+        result.extend_from_slice(&sample_index.to_le_bytes());
+        result.extend_from_slice(b"data");
+        result.extend_from_slice(&0u32.to_le_bytes());
+        result.extend_from_slice(&0u32.to_le_bytes());
+        result.extend_from_slice(&sample_index.to_le_bytes());
+    }
+
+    result.extend_from_slice(b"data");
+    result.extend_from_slice(&data_size.to_le_bytes());
+    for sample in &capture.samples {
+        result.extend_from_slice(&sample.to_le_bytes());
+    }
+
+    result
+}
+
+// NOTE: No window and no audio device; runs a fixed number of frames as
+// fast as it can, mixing exactly one frame's worth of sound per frame, so the
+// same build always captures the same samples. Start is pressed on the first
+// frame so a hero joins and there's something to hear.
+fn run_headless(frame_count: u32, capture_filename: &str) -> std::io::Result<()> {
+    let mut game_memory = make_game_memory();
+    let mut game_input = make_game_input();
+    let mut offscreen_buffer = make_offscreen_buffer();
+
+    let samples_per_frame =
+        (SOUND_SAMPLES_PER_SECOND as f32 * game_input.dt_for_frame).round() as usize;
+    let mut capture = SoundCapture {
+        samples_per_second: SOUND_SAMPLES_PER_SECOND,
+        samples: Vec::new(),
+        frame_sample_indices: Vec::new(),
     };
 
+    for frame_index in 0..frame_count {
+        let start = &mut game_input.controllers[0].buttons[BUTTON_START];
+        start.half_transition_count = 0;
+        process_key_press(start, frame_index == 0);
+
+        game_update_and_render(&mut game_memory, &game_input, &mut offscreen_buffer);
+
+        let first_sample = capture.samples.len();
+        capture.frame_sample_indices.push((first_sample / 2) as u32);
+        capture
+            .samples
+            .resize(first_sample + 2 * samples_per_frame, 0);
+
+        let mut sound_buffer = GameSoundOutputBuffer {
+            samples_per_second: SOUND_SAMPLES_PER_SECOND,
+            sample_count: samples_per_frame as i32,
+            samples: &mut capture.samples[first_sample..],
+        };
+        game_get_sound_samples(&mut game_memory, &mut sound_buffer);
+    }
+
+    fs::write(capture_filename, write_capture_wav(&capture))
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("--headless") {
+        let frame_count = args.get(2).and_then(|count| count.parse::<u32>().ok());
+        let (Some(frame_count), Some(capture_filename)) = (frame_count, args.get(3)) else {
+            eprintln!("usage: handmadehero-rust --headless <frame count> <capture.wav>");
+            process::exit(1);
+        };
+        if let Err(error) = run_headless(frame_count, capture_filename) {
+            eprintln!("handmadehero-rust: can't write {capture_filename}: {error}");
+            process::exit(1);
+        }
+        return;
+    }
+
+    // Initialize SDL2
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+    let controller_subsystem = sdl_context.game_controller().unwrap();
+
+    // NOTE: Without an audio device the game just runs silent
+    let audio_queue: Option<AudioQueue<i16>> = sdl_context.audio().ok().and_then(|audio| {
+        let desired = AudioSpecDesired {
+            freq: Some(SOUND_SAMPLES_PER_SECOND),
+            channels: Some(2),
+            samples: None,
+        };
+        audio.open_queue(None, &desired).ok()
+    });
+    if let Some(audio_queue) = &audio_queue {
+        audio_queue.resume();
+    }
+    let mut sound_samples: Vec<i16> = Vec::new();
+    let mut gamepads: [Option<GameController>; MAX_GAMEPADS] = Default::default();
+
+    // Create window and canvas
+    let window = video_subsystem
+        .window("Game Window", 960, 540)
+        .position_centered()
+        .resizable()
+        .build()
+        .unwrap();
+    #[cfg(feature = "vsync")]
+    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
+    #[cfg(not(feature = "vsync"))]
+    let mut canvas = window.into_canvas().build().unwrap();
+
+    // Create texture for rendering
+    let texture_creator = canvas.texture_creator();
+    let mut texture = texture_creator
+        .create_texture_streaming(PixelFormatEnum::ARGB8888, 960, 540)
+        .unwrap();
+
+    let mut game_memory = make_game_memory();
+    let mut game_input = make_game_input();
+    let mut offscreen_buffer = make_offscreen_buffer();
+
     let mut event_pump = sdl_context.event_pump().unwrap();
     let mut running = true;
 
     while running {
+        for controller in game_input.controllers.iter_mut() {
+            for button in controller.buttons.iter_mut() {
+                button.half_transition_count = 0;
+            }
+        }
+
         // Handle events
         for event in event_pump.poll_iter() {
             match event {
@@ -129,6 +359,15 @@ fn main() {
                         Scancode::D => {
                             process_key_press(&mut controller.buttons[MOVE_RIGHT], true);
                         }
+                        Scancode::Space => {
+                            process_key_press(&mut controller.buttons[BUTTON_START], true);
+                        }
+                        Scancode::Tab => {
+                            process_key_press(&mut controller.buttons[BUTTON_BACK], true);
+                        }
+                        Scancode::E => {
+                            process_key_press(&mut controller.buttons[BUTTON_RIGHT_SHOULDER], true);
+                        }
                         _ => {}
                     }
                 }
@@ -151,9 +390,56 @@ fn main() {
                         Scancode::D => {
                             process_key_press(&mut controller.buttons[MOVE_RIGHT], false);
                         }
+                        Scancode::Space => {
+                            process_key_press(&mut controller.buttons[BUTTON_START], false);
+                        }
+                        Scancode::Tab => {
+                            process_key_press(&mut controller.buttons[BUTTON_BACK], false);
+                        }
+                        Scancode::E => {
+                            process_key_press(
+                                &mut controller.buttons[BUTTON_RIGHT_SHOULDER],
+                                false,
+                            );
+                        }
                         _ => {}
                     }
                 }
+                Event::ControllerDeviceAdded { which, .. } => {
+                    if let Some(slot) = gamepads.iter().position(|gamepad| gamepad.is_none()) {
+                        if let Ok(gamepad) = controller_subsystem.open(which) {
+                            gamepads[slot] = Some(gamepad);
+                            game_input.controllers[slot + 1].is_connected = true;
+                        }
+                    }
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    if let Some(slot) = gamepad_slot(&gamepads, which) {
+                        gamepads[slot] = None;
+
+                        let controller = &mut game_input.controllers[slot + 1];
+                        controller.is_connected = false;
+                        for button in controller.buttons.iter_mut() {
+                            button.ended_down = false;
+                        }
+                    }
+                }
+                Event::ControllerButtonDown { which, button, .. } => {
+                    if let (Some(slot), Some(button_index)) =
+                        (gamepad_slot(&gamepads, which), gamepad_button_index(button))
+                    {
+                        let controller = &mut game_input.controllers[slot + 1];
+                        process_key_press(&mut controller.buttons[button_index], true);
+                    }
+                }
+                Event::ControllerButtonUp { which, button, .. } => {
+                    if let (Some(slot), Some(button_index)) =
+                        (gamepad_slot(&gamepads, which), gamepad_button_index(button))
+                    {
+                        let controller = &mut game_input.controllers[slot + 1];
+                        process_key_press(&mut controller.buttons[button_index], false);
+                    }
+                }
                 // Handle other events like mouse input here
                 _ => {}
             }
@@ -162,6 +448,26 @@ fn main() {
         // Update and render the game
         game_update_and_render(&mut game_memory, &game_input, &mut offscreen_buffer);
 
+        // NOTE: Top the queue back up to the latency target, however much
+        // the speakers ate since last frame
+        if let Some(audio_queue) = &audio_queue {
+            let bytes_per_sample = 2 * mem::size_of::<i16>();
+            let queued_sample_count = audio_queue.size() as usize / bytes_per_sample;
+            if queued_sample_count < SOUND_LATENCY_SAMPLE_COUNT {
+                let sample_count = SOUND_LATENCY_SAMPLE_COUNT - queued_sample_count;
+                sound_samples.resize(2 * sample_count, 0);
+
+                let mut sound_buffer = GameSoundOutputBuffer {
+                    samples_per_second: audio_queue.spec().freq,
+                    sample_count: sample_count as i32,
+                    samples: &mut sound_samples,
+                };
+                game_get_sound_samples(&mut game_memory, &mut sound_buffer);
+
+                let _ = audio_queue.queue_audio(&sound_samples);
+            }
+        }
+
         // Update texture with the offscreen buffer
         texture
             .update(
@@ -177,3 +483,39 @@ fn main() {
         canvas.present();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captures_mark_where_each_frame_starts() {
+        let capture = SoundCapture {
+            samples_per_second: 48000,
+            samples: vec![1, -1, 2, -2, 3, -3],
+            frame_sample_indices: vec![0, 2],
+        };
+        let file = write_capture_wav(&capture);
+
+        let read_u32 = |at: usize| u32::from_le_bytes(file[at..at + 4].try_into().unwrap());
+        assert_eq!(&file[0..4], b"RIFF");
+        assert_eq!(read_u32(4) as usize, file.len() - 8);
+        assert_eq!(read_u32(24), 48000);
+
+        // NOTE: The cue chunk follows the 16 byte format chunk
+        assert_eq!(&file[36..40], b"cue ");
+        assert_eq!(read_u32(44), 2);
+        let cue_points: Vec<(u32, u32)> = (0..2)
+            .map(|cue_index| {
+                let at = 48 + 24 * cue_index;
+                (read_u32(at), read_u32(at + 20))
+            })
+            .collect();
+        assert_eq!(cue_points, [(0, 0), (1, 2)]);
+
+        let data_at = 48 + 2 * 24;
+        assert_eq!(&file[data_at..data_at + 4], b"data");
+        assert_eq!(read_u32(data_at + 4), 12);
+        assert_eq!(file[data_at + 8..data_at + 12], [1, 0, 0xFF, 0xFF]);
+    }
+}